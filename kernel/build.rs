@@ -1,4 +1,4 @@
-use std::{fs, io};
+use std::{env, fs, io};
 
 use chrono::{Datelike, Local};
 use serde::Deserialize;
@@ -38,13 +38,30 @@ fn main() -> Result<(), ParseVersionError> {
     let v: Version = toml::from_slice(&buf)?;
     let version_fmt = format!("{}.{}.{}", v.kernel.version_major, v.kernel.version_minor, v.kernel.version_patch);
     let century = Local::now().year() / 100;
+    let watchdog_secs = env::var("SFK_WATCHDOG_SECS").unwrap_or_else(|_| String::from("10"));
+    let ticks_hz = env::var("SFK_TICKS_HZ").unwrap_or_else(|_| String::from("100"));
+    let tickless = env::var("SFK_TICKLESS").unwrap_or_else(|_| String::from("1"));
+    let timer = match env::var("SFK_TIMER").unwrap_or_else(|_| String::from("apic")).as_str() {
+        "pit" => 0,
+        "apic" => 1,
+        "hpet" => 2,
+        other => return Err(ParseVersionError::BadTimer(other.to_string())),
+    };
     
     println!("cargo::rerun-if-changed={VERSION}");
+    println!("cargo::rerun-if-env-changed=SFK_WATCHDOG_SECS");
+    println!("cargo::rerun-if-env-changed=SFK_TIMER");
+    println!("cargo::rerun-if-env-changed=SFK_TICKS_HZ");
+    println!("cargo::rerun-if-env-changed=SFK_TICKLESS");
     println!("cargo::rustc-env=SFK_VERSION={}", version_fmt);
     println!("cargo::rustc-env=SFK_PATCH_QUOTE={}", v.kernel.patch_quote);
     println!("cargo::rustc-env=SFK_FLOPPYFS_YEAR={}", v.floppyfs.year);
     println!("cargo::rustc-env=SFK_FLOPPYFS_DAY={}", v.floppyfs.day);
     println!("cargo::rustc-env=SFK_TIME_CENTURY={}", century);
+    println!("cargo::rustc-env=SFK_WATCHDOG_SECS={}", watchdog_secs);
+    println!("cargo::rustc-env=SFK_TIMER={}", timer);
+    println!("cargo::rustc-env=SFK_TICKS_HZ={}", ticks_hz);
+    println!("cargo::rustc-env=SFK_TICKLESS={}", tickless);
 
     Ok(())
 }
@@ -56,4 +73,7 @@ enum ParseVersionError {
 
       #[error("failed parsing the VERSION file: {0}")]
       ParseError(#[from] de::Error),
+
+      #[error("unknown SFK_TIMER `{0}`, expected pit, apic or hpet")]
+      BadTimer(String),
 }
@@ -0,0 +1,404 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/acpi.rs
+
+    Finds the ACPI tables and parses the MADT and HPET tables.
+*/
+
+use alloc::vec::Vec;
+use core::{slice, str};
+
+use libutil::{InitError, InitLater};
+use thiserror::Error;
+
+use crate::exit_on_err;
+use crate::memory::phys_to_virt;
+use crate::startup::ExitCode;
+
+/// The MADT, loaded by [`init`].
+pub static MADT: InitLater<Madt> = InitLater::uninit();
+
+/// The HPET table, loaded by [`init`] if the firmware has one.
+pub static HPET: InitLater<HpetInfo> = InitLater::uninit();
+
+/// The size of the header at the start of every ACPI table.
+const HEADER_LEN: usize = 36;
+
+/// The parts of the Multiple APIC Description Table sunflower uses.
+#[derive(Debug, Default)]
+pub struct Madt {
+      /// The physical address of the local APIC.
+      pub lapic_addr: u64,
+      /// Whether the legacy 8259 PICs are also installed.
+      pub has_8259:   bool,
+      /// The number of enabled processors.
+      pub cpus:       usize,
+      pub ioapics:    Vec<IoApicInfo>,
+      pub overrides:  Vec<IrqOverride>,
+}
+
+/// An I/O APIC listed in the MADT.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+      pub id:       u8,
+      /// The physical address of it's registers.
+      pub addr:     u64,
+      /// The first GSI it handles.
+      pub gsi_base: u32,
+}
+
+/// A legacy ISA IRQ which isn't identity mapped to a GSI.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrqOverride {
+      pub irq:   u8,
+      pub gsi:   u32,
+      /// The MPS INTI flags, holding the polarity and trigger mode.
+      pub flags: u16,
+}
+
+impl IrqOverride {
+      /// Returns whether the IRQ is active low.
+      pub fn active_low(&self) -> bool {
+            self.flags & 0b11 == 0b11
+      }
+
+      /// Returns whether the IRQ is level triggered.
+      pub fn level_triggered(&self) -> bool {
+            (self.flags >> 2) & 0b11 == 0b11
+      }
+}
+
+/// The parts of the High Precision Event Timer table sunflower uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HpetInfo {
+      /// The physical address of it's registers.
+      pub addr:     u64,
+      /// The fewest main counter ticks allowed between periodic interrupts.
+      pub min_tick: u16,
+}
+
+impl HpetInfo {
+      /// Parses the HPET table `table`, including it's header.
+      fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+            /// The length of the HPET table.
+            const LEN: usize = HEADER_LEN + 20;
+
+            /// The address space ID of system memory.
+            const SYSTEM_MEMORY: u8 = 0;
+
+            // The registers must be memory mapped
+            if table.len() < LEN ||
+                  &table[..4] != b"HPET" ||
+                  table[HEADER_LEN + 4] != SYSTEM_MEMORY
+            {
+                  return Err(AcpiError::BadTable("HPET"));
+            }
+
+            Ok(HpetInfo {
+                  addr:     u64_at(table, HEADER_LEN + 8),
+                  min_tick: u16_at(table, HEADER_LEN + 17),
+            })
+      }
+}
+
+impl Madt {
+      /// Parses the MADT `table`, including it's header.
+      fn parse(table: &[u8]) -> Result<Self, AcpiError> {
+            /// PC-AT compatible 8259s are installed.
+            const PCAT_COMPAT: u32 = 1;
+
+            /// The processor is enabled or can be enabled.
+            const LAPIC_USABLE: u32 = 0b11;
+
+            if table.len() < HEADER_LEN + 8 || &table[..4] != b"APIC" {
+                  return Err(AcpiError::BadTable("APIC"));
+            }
+
+            let mut madt = Madt {
+                  lapic_addr: u32_at(table, HEADER_LEN) as u64,
+                  has_8259: u32_at(table, HEADER_LEN + 4) & PCAT_COMPAT != 0,
+                  ..Default::default()
+            };
+
+            let mut offset = HEADER_LEN + 8;
+            while offset + 2 <= table.len() {
+                  let (kind, len) = (table[offset], table[offset + 1] as usize);
+                  if len < 2 || offset + len > table.len() {
+                        return Err(AcpiError::BadTable("APIC"));
+                  }
+
+                  let entry = &table[offset..offset + len];
+                  match (kind, len) {
+                        (0, 8) if u32_at(entry, 4) & LAPIC_USABLE != 0 => {
+                              madt.cpus += 1
+                        }
+                        (1, 12) => madt.ioapics.push(IoApicInfo {
+                              id:       entry[2],
+                              addr:     u32_at(entry, 4) as u64,
+                              gsi_base: u32_at(entry, 8),
+                        }),
+                        (2, 10) => madt.overrides.push(IrqOverride {
+                              irq:   entry[3],
+                              gsi:   u32_at(entry, 4),
+                              flags: u16_at(entry, 8),
+                        }),
+                        (5, 12) => madt.lapic_addr = u64_at(entry, 4),
+                        _ => (),
+                  }
+                  offset += len;
+            }
+
+            Ok(madt)
+      }
+
+      /// Returns the GSI ISA IRQ `irq` is connected to, and it's override if it
+      /// has one.
+      pub fn irq_to_gsi(&self, irq: u8) -> (u32, Option<&IrqOverride>) {
+            match self.overrides.iter().find(|o| o.irq == irq) {
+                  Some(o) => (o.gsi, Some(o)),
+                  None => (irq as u32, None),
+            }
+      }
+}
+
+/// Finds the RSDP, then loads the MADT and HPET tables from the RSDT or XSDT
+/// into [`MADT`] and [`HPET`].
+pub fn init() -> ExitCode<AcpiError> {
+      let (root, entry_size) = exit_on_err!(find_root());
+      let root = exit_on_err!(table_at(root));
+      let entries = root[HEADER_LEN..].chunks_exact(entry_size);
+
+      for entry in entries {
+            let addr = match entry_size {
+                  4 => u32_at(entry, 0) as u64,
+                  _ => u64_at(entry, 0),
+            };
+
+            let table = exit_on_err!(table_at(addr));
+            match &table[..4] {
+                  b"APIC" => {
+                        let madt = exit_on_err!(Madt::parse(table));
+                        dbg_info!(
+                              "acpi: madt: lapic 0x{:x}, {} ioapic(s), \
+                              {} cpu(s)",
+                              madt.lapic_addr,
+                              madt.ioapics.len(),
+                              madt.cpus
+                        );
+
+                        exit_on_err!(
+                              MADT.init(madt)
+                                    .map_err(|_| AcpiError::Loaded("MADT"))
+                        );
+                  }
+                  b"HPET" => {
+                        let hpet = exit_on_err!(HpetInfo::parse(table));
+                        dbg_info!("acpi: hpet at 0x{:x}", hpet.addr);
+
+                        exit_on_err!(
+                              HPET.init(hpet)
+                                    .map_err(|_| AcpiError::Loaded("HPET"))
+                        );
+                  }
+                  _ => (),
+            }
+      }
+
+      match MADT.read() {
+            Ok(_) => ExitCode::Ok,
+            Err(_) => ExitCode::Error(AcpiError::NoMadt),
+      }
+}
+
+/// Searches the EBDA and BIOS area for the RSDP, returning the address of the
+/// root table it points to and the size of each entry in it.
+fn find_root() -> Result<(u64, usize), AcpiError> {
+      /// The RSDP's size in ACPI 1.0.
+      const RSDP_V1_LEN: usize = 20;
+
+      /// The RSDP's size in ACPI 2.0+.
+      const RSDP_V2_LEN: usize = 36;
+
+      // Safety: The EBDA's segment is stored in the BDA, which always exists
+      let ebda = unsafe { (phys_to_virt(0x40E)? as *const u16).read() };
+      let ebda = (ebda as u64) << 4;
+      let areas = [(ebda, 1024), (0xE0000, 0x20000)];
+
+      for (start, len) in areas {
+            // Safety: The EBDA and BIOS area are always mapped and never
+            // written to
+            let area =
+                  unsafe { slice::from_raw_parts(phys_to_virt(start)?, len) };
+
+            // The RSDP is always 16 byte aligned
+            for offset in (0..len).step_by(16) {
+                  let rsdp = &area[offset..(offset + RSDP_V2_LEN).min(len)];
+                  if rsdp.len() < RSDP_V1_LEN ||
+                        &rsdp[..8] != b"RSD PTR " ||
+                        !checksum(&rsdp[..RSDP_V1_LEN])
+                  {
+                        continue;
+                  }
+
+                  let revision = rsdp[15];
+                  return if revision >= 2 && rsdp.len() == RSDP_V2_LEN {
+                        if !checksum(rsdp) {
+                              return Err(AcpiError::BadTable("RSDP"));
+                        }
+                        Ok((u64_at(rsdp, 24), 8))
+                  } else {
+                        Ok((u32_at(rsdp, 16) as u64, 4))
+                  };
+            }
+      }
+
+      Err(AcpiError::NoRsdp)
+}
+
+/// Returns the ACPI table at physical address `addr`, checking it's checksum.
+fn table_at(addr: u64) -> Result<&'static [u8], AcpiError> {
+      let ptr = phys_to_virt(addr)?;
+      // Safety: ACPI tables are always mapped and are never written to
+      let header = unsafe { slice::from_raw_parts(ptr, HEADER_LEN) };
+      let len = u32_at(header, 4) as usize;
+      // Safety: The header says that the table is `len` bytes long
+      let table = unsafe { slice::from_raw_parts(ptr, len.max(HEADER_LEN)) };
+
+      if !checksum(table) {
+            let sig = str::from_utf8(&table[..4]).unwrap_or("unknown");
+            return Err(AcpiError::BadTable(sig));
+      }
+      Ok(table)
+}
+
+/// Returns whether every byte in `bytes` adds up to zero.
+fn checksum(bytes: &[u8]) -> bool {
+      bytes.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == 0
+}
+
+/// Reads the little endian u16 at `offset` in `bytes`.
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+      u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+/// Reads the little endian u32 at `offset` in `bytes`.
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+      let mut buf = [0; 4];
+      buf.copy_from_slice(&bytes[offset..offset + 4]);
+      u32::from_le_bytes(buf)
+}
+
+/// Reads the little endian u64 at `offset` in `bytes`.
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+      let mut buf = [0; 8];
+      buf.copy_from_slice(&bytes[offset..offset + 8]);
+      u64::from_le_bytes(buf)
+}
+
+#[derive(Error, Debug)]
+pub enum AcpiError {
+      #[error(transparent)]
+      NoOffset(#[from] InitError<u64>),
+
+      #[error("Couldn't find the RSDP")]
+      NoRsdp,
+
+      #[error("The {0} table is corrupted")]
+      BadTable(&'static str),
+
+      #[error("There's no MADT, so no APIC either")]
+      NoMadt,
+
+      #[error("The {0} table was already loaded")]
+      Loaded(&'static str),
+}
+
+#[cfg(test)]
+mod tests {
+      use alloc::vec;
+
+      use super::*;
+
+      /// Tests that [`Madt::parse`] reads every entry sunflower uses and
+      /// skips the rest.
+      #[test_case]
+      fn madt_parses_entries() {
+            let mut table = vec![0; HEADER_LEN];
+            table[..4].copy_from_slice(b"APIC");
+            table.extend(0xFEE0_0000_u32.to_le_bytes());
+            table.extend(1_u32.to_le_bytes());
+            table.extend([0, 8, 0, 0, 1, 0, 0, 0]); // enabled lapic
+            table.extend([0, 8, 1, 1, 0, 0, 0, 0]); // disabled lapic
+            table.extend([1, 12, 3, 0, 0, 0, 0xC0, 0xFE, 0, 0, 0, 0]);
+            table.extend([2, 10, 0, 0, 2, 0, 0, 0, 0xF, 0]);
+            table.extend([4, 6, 0xFF, 0, 0, 1]); // lapic nmi
+
+            let madt = Madt::parse(&table).unwrap();
+            assert_eq!(madt.lapic_addr, 0xFEE0_0000);
+            assert!(madt.has_8259);
+            assert_eq!(madt.cpus, 1);
+            assert_eq!(
+                  madt.ioapics,
+                  [IoApicInfo {
+                        id:       3,
+                        addr:     0xFEC0_0000,
+                        gsi_base: 0,
+                  }]
+            );
+
+            let (gsi, irq_override) = madt.irq_to_gsi(0);
+            let irq_override = irq_override.unwrap();
+            assert_eq!(gsi, 2);
+            assert!(
+                  irq_override.active_low() && irq_override.level_triggered()
+            );
+            assert_eq!(madt.irq_to_gsi(1), (1, None));
+      }
+
+      /// Tests that [`HpetInfo::parse`] reads the register address and
+      /// minimum tick, rejecting I/O port registers.
+      #[test_case]
+      fn hpet_parses_table() {
+            let mut table = vec![0; HEADER_LEN];
+            table[..4].copy_from_slice(b"HPET");
+            table.extend(0x8086_A201_u32.to_le_bytes()); // block id
+            table.extend([0, 64, 0, 0]); // system memory
+            table.extend(0xFED0_0000_u64.to_le_bytes());
+            table.push(0); // hpet number
+            table.extend(0x80_u16.to_le_bytes());
+            table.push(0); // page protection
+
+            let hpet = HpetInfo::parse(&table).unwrap();
+            assert_eq!(hpet.addr, 0xFED0_0000);
+            assert_eq!(hpet.min_tick, 0x80);
+
+            table[HEADER_LEN + 4] = 1; // system I/O
+            assert!(HpetInfo::parse(&table).is_err());
+      }
+
+      /// Tests that [`Madt::parse`] fails on entries running past the table.
+      #[test_case]
+      fn madt_rejects_bad_entries() {
+            let mut table = vec![0; HEADER_LEN + 8];
+            table[..4].copy_from_slice(b"APIC");
+            table.extend([1, 12, 0, 0]);
+            assert!(Madt::parse(&table).is_err());
+      }
+}
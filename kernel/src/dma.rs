@@ -0,0 +1,152 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/dma.rs
+
+    Drives channel 2 of the ISA 8237 DMA controller, used by the floppy drive
+*/
+
+use core::slice;
+
+use libutil::{InitError, InitLater};
+use thiserror::Error;
+
+use crate::exit_on_err;
+use crate::memory::{self, PAGE_SIZE, frames};
+use crate::ports::{self, Port};
+use crate::startup::ExitCode;
+
+/// The number of frames in the bounce buffer.
+const BUFFER_FRAMES: u64 = 4;
+
+/// The size of the bounce buffer, big enough to hold a whole track.
+pub const BUFFER_SIZE: usize = (BUFFER_FRAMES * PAGE_SIZE) as usize;
+
+/// ISA DMA only has 24 address lines, so can't reach past 16 MiB.
+const ISA_LIMIT: u64 = 16 * 1024 * 1024;
+
+/// The channel the floppy controller is wired to.
+const FLOPPY_CHANNEL: u8 = 2;
+
+/// The physical address of the bounce buffer.
+///
+/// The buffer is below [`ISA_LIMIT`] and never crosses a 64 KiB boundary,
+/// since the controller can't carry into the page register.
+static BUFFER: InitLater<u64> = InitLater::uninit();
+
+/// The way data moves during a transfer, named from the device's side.
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+pub enum Direction {
+      /// Device to memory, used when reading from the device.
+      Read  = 0b01 << 2,
+
+      /// Memory to device, used when writing to the device.
+      Write = 0b10 << 2,
+}
+
+/// Allocates the bounce buffer used by DMA transfers.
+pub fn init() -> ExitCode<DmaError> {
+      // Aligned to it's size, so it can't cross a 64 KiB boundary
+      let Some(frame) = frames::alloc_contiguous(BUFFER_FRAMES, ISA_LIMIT)
+      else {
+            return ExitCode::Error(DmaError::NoBuffer);
+      };
+
+      let _addr = *exit_on_err!(BUFFER.init(frame.addr()));
+      dbg_info!("dma: {BUFFER_SIZE} byte bounce buffer at 0x{_addr:x}");
+      ExitCode::Ok
+}
+
+/// Returns whether the bounce buffer was allocated, meaning DMA transfers can
+/// be used.
+pub fn available() -> bool {
+      BUFFER.read().is_ok()
+}
+
+/// Returns the bounce buffer.
+///
+/// # Safety
+/// The buffer must not be referenced anywhere else, and no transfer which uses
+/// it can be in progress.
+pub unsafe fn buffer() -> Result<&'static mut [u8], DmaError> {
+      let virt = memory::phys_to_virt(*BUFFER.read()?)?;
+
+      // Safety: The buffer was allocated for just DMA and the caller ensures
+      // that nothing else is using it
+      Ok(unsafe { slice::from_raw_parts_mut(virt, BUFFER_SIZE) })
+}
+
+/// Programs the floppy's channel to move the first `len` bytes of the bounce
+/// buffer in direction `dir`, which starts once the controller asks for it.
+///
+/// See https://wiki.osdev.org/ISA_DMA#Floppy_Disk_DMA_Initialization
+///
+/// # Safety
+/// The bounce buffer must not be used until the transfer has finished.
+pub unsafe fn start_floppy(dir: Direction, len: usize) -> Result<(), DmaError> {
+      /// Masks the channel ORed into the single mask register.
+      const MASK: u8 = 1 << 2;
+
+      /// Single transfer mode, which moves a byte each time it's asked.
+      const SINGLE_MODE: u8 = 0b01 << 6;
+
+      if len == 0 || len > BUFFER_SIZE {
+            return Err(DmaError::BadLen(len));
+      }
+
+      let addr = *BUFFER.read()?;
+      let count = (len - 1) as u16; // the controller moves count + 1 bytes
+
+      // Safety: The channel's masked while it's being programmed and the
+      // buffer's known to be below 16 MiB and not cross 64 KiB
+      unsafe {
+            ports::writeb(Port::DMASingleMask, MASK | FLOPPY_CHANNEL);
+
+            ports::writeb(Port::DMAFlipFlop, 0xFF);
+            ports::writeb(Port::DMAChannel2Addr, addr as u8);
+            ports::writeb(Port::DMAChannel2Addr, (addr >> 8) as u8);
+            ports::writeb(Port::DMAChannel2Page, (addr >> 16) as u8);
+
+            ports::writeb(Port::DMAFlipFlop, 0xFF);
+            ports::writeb(Port::DMAChannel2Count, count as u8);
+            ports::writeb(Port::DMAChannel2Count, (count >> 8) as u8);
+
+            ports::writeb(
+                  Port::DMAMode,
+                  SINGLE_MODE | dir as u8 | FLOPPY_CHANNEL,
+            );
+            ports::writeb(Port::DMASingleMask, FLOPPY_CHANNEL);
+      }
+
+      Ok(())
+}
+
+/// An error created by the DMA driver.
+#[derive(Error, Debug)]
+pub enum DmaError {
+      #[error("No free memory below 16 MiB for the bounce buffer!")]
+      NoBuffer,
+
+      #[error(transparent)]
+      Uninit(#[from] InitError<u64>),
+
+      #[error("can't transfer {0} bytes through the {BUFFER_SIZE} byte buffer")]
+      BadLen(usize),
+}
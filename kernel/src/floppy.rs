@@ -35,14 +35,18 @@
 */
 
 use core::fmt::Display;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use disk::DiskError;
 use fifo::{FifoIOError, SendCmdError, SenseIntError};
 use libutil::{InitError, InitLater, UnsafeFlag};
 use thiserror::Error;
 
+use crate::dma::{self, DmaError};
 use crate::floppy::fifo::FloppyCommand;
+use crate::interrupts::{self, IrqError};
 use crate::startup::{self, ExitCode};
+use crate::task::{self, wake};
 use crate::{exit_on_err, ports, time};
 
 pub mod disk;
@@ -51,6 +55,9 @@ pub mod floppyfs;
 pub mod motor;
 mod reset;
 
+/// The IRQ line floppy controllers use.
+const FLOPPY_IRQ: u8 = 6;
+
 /// The base address of the floppy being used by sunflower.
 pub static BASE_OFFSET: InitLater<u16> = InitLater::uninit();
 
@@ -63,6 +70,10 @@ pub static FLOPPY_SPACE: InitLater<u16> = InitLater::uninit();
 /// use an invalid drive.
 pub static DRIVE_ONE: UnsafeFlag = UnsafeFlag::new(false);
 
+/// Set while transfers are moved by DMA channel 2, cleared if DMA fails and
+/// the driver falls back to moving each byte through the FIFO.
+pub static DMA_MODE: AtomicBool = AtomicBool::new(false);
+
 /// Timeout until we assume a command failed, in kernel ticks.
 const TIMEOUT: u64 = 30;
 
@@ -127,6 +138,14 @@ pub enum FloppyError {
       #[error(transparent)]
       FifoTimeout(FifoIOError),
 
+      /// The DMA channel couldn't be programmed.
+      #[error(transparent)]
+      Dma(#[from] DmaError),
+
+      /// The floppy IRQ handler couldn't be registered.
+      #[error(transparent)]
+      Irq(#[from] IrqError),
+
       /// Some other error occurred.
       #[error("{0}")]
       Other(&'static str),
@@ -204,6 +223,13 @@ pub fn init_wrapper() -> ExitCode<FloppyError> {
       ExitCode::Ok
 }
 
+/// Wakes any tasks waiting on the floppy controller.
+///
+/// Registered on IRQ line 6 by [`init`].
+fn floppy_irq() {
+      wake::FLOPPY.fire()
+}
+
 /// Initialises the first floppy controller found.
 fn init() -> Result<(), FloppyError> {
       /// The CMOS register responsible for storing floppy information.
@@ -243,6 +269,7 @@ fn init() -> Result<(), FloppyError> {
             return Err(FloppyError::Other("No floppy drives found!"));
       }
 
+      interrupts::register_irq(FLOPPY_IRQ, floppy_irq)?;
       motor::enable_motor()?;
 
       // Safety: Version can be sent before initialisation
@@ -257,11 +284,12 @@ fn init() -> Result<(), FloppyError> {
       }
 
       reset::send_configure()?;
+      DMA_MODE.store(dma::available(), Ordering::Relaxed);
 
       // Safety: All disk operations fail before FLOPPY_INIT is set
       unsafe {
             reset::init_fdc()?;
-            fifo::seek(None)?
+            task::block_on(fifo::seek(None))?
       };
 
       // Safety: The controller is well initialised by this point
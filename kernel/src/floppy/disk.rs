@@ -23,15 +23,18 @@
     Contained within the floppy module
 */
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
 use thiserror::Error;
 
+use crate::dma::{self, Direction, DmaError};
 use crate::floppy::{
-      CYLINDERS, DRIVE_ONE, FloppyCommand, FloppyError, FloppyPort, HEADS,
-      SECTOR_SIZE, SECTORS, ST0_ERR_OR_RESET, TIMEOUT, fifo, motor,
+      CYLINDERS, DMA_MODE, DRIVE_ONE, FloppyCommand, FloppyError, FloppyPort,
+      HEADS, SECTOR_SIZE, SECTORS, ST0_ERR_OR_RESET, fifo, motor, reset,
 };
-use crate::{startup, time};
+use crate::task::wake::{self, IrqWait};
+use crate::time::{self, Instant, KERNEL_TICKS_HZ};
+use crate::{ports, startup, task};
 
 /// The number of successfully read bytes from the floppy drive.
 pub static READ_BYTES: AtomicU64 = AtomicU64::new(0);
@@ -39,28 +42,91 @@ pub static READ_BYTES: AtomicU64 = AtomicU64::new(0);
 /// The number of successfully written bytes to the floppy drive.
 pub static WRITTEN_BYTES: AtomicU64 = AtomicU64::new(0);
 
+/// The number of nanoseconds spent moving the successfully transferred bytes,
+/// used to measure throughput.
+static TRANSFER_NANOS: AtomicU64 = AtomicU64::new(0);
+
+/// How long a DMA transfer of up to a whole track can take before it's
+/// assumed to have failed, in kernel ticks.
+const DMA_TIMEOUT: u64 = KERNEL_TICKS_HZ;
+
 /// The number of retries before a read or write fails
 /// and the floppy driver is disabled.
 const DISK_RETRIES: u8 = 8;
 
-/// Waits for the RQM bit in the MSR to be set
-fn wait_for_rqm() -> Result<(), FloppyError> {
-      let start_time = time::get_time();
-      while start_time + TIMEOUT > time::get_time() {
-            let msr = FloppyPort::msr()?;
-            if msr == msr | 0b10000000 {
-                  return Ok(());
+/// Set while a transfer is using the controller.
+static BUSY: AtomicBool = AtomicBool::new(false);
+
+/// The buffer a transfer reads into or writes from.
+enum Transfer<'a> {
+      Read(&'a mut [u8]),
+      Write(&'a [u8]),
+}
+
+impl Transfer<'_> {
+      /// Returns the length of the buffer.
+      fn len(&self) -> usize {
+            match self {
+                  Transfer::Read(buf) => buf.len(),
+                  Transfer::Write(buf) => buf.len(),
             }
       }
-      Err(DiskError::FifoTimeout.into())
+
+      /// Returns whether the transfer is a read.
+      fn is_read(&self) -> bool {
+            matches!(self, Transfer::Read(_))
+      }
 }
 
-/// Reads from the floppy drive starting at sector `ptr` into `buf`.
+/// Reads from the floppy drive starting at sector `ptr` into `buf`, blocking
+/// until it's done.
 ///
 /// Fails if the length of `buf` isn't a multiple of 512.
 pub fn read(ptr: u64, buf: &mut [u8]) -> Result<(), FloppyError> {
-      if buf.is_empty() {
-            warn!("floppy: useless read with an empty buffer");
+      task::block_on(read_async(ptr, buf))
+}
+
+/// Writes `buf` into the sector at offset `ptr`, blocking until it's done.
+///
+/// Fails if the length of `buf` isn't a multiple of 512.
+pub fn write(ptr: u64, buf: &[u8]) -> Result<(), FloppyError> {
+      task::block_on(write_async(ptr, buf))
+}
+
+/// Returns a request which reads from the floppy drive starting at sector
+/// `ptr` into `buf`, completing once the read has.
+///
+/// The controller is only touched when it raises IRQ 6, so other tasks keep
+/// running while the request is in progress.
+/// Fails if the length of `buf` isn't a multiple of 512.
+pub async fn read_async(ptr: u64, buf: &mut [u8]) -> Result<(), FloppyError> {
+      transfer(ptr, Transfer::Read(buf)).await
+}
+
+/// Returns a request which writes `buf` into the sector at offset `ptr`,
+/// completing once the write has.
+///
+/// The controller is only touched when it raises IRQ 6, so other tasks keep
+/// running while the request is in progress.
+/// Fails if the length of `buf` isn't a multiple of 512.
+pub async fn write_async(ptr: u64, buf: &[u8]) -> Result<(), FloppyError> {
+      transfer(ptr, Transfer::Write(buf)).await
+}
+
+/// Returns the average floppy throughput in bytes per second, or `None` if
+/// nothing has been transferred yet.
+pub fn throughput() -> Option<u64> {
+      let bytes = READ_BYTES.load(Ordering::Relaxed) +
+            WRITTEN_BYTES.load(Ordering::Relaxed);
+      let nanos = TRANSFER_NANOS.load(Ordering::Relaxed).max(1) as u128;
+      (bytes != 0).then(|| (bytes as u128 * 1_000_000_000 / nanos) as u64)
+}
+
+/// Transfers `buf` to or from the sector at offset `ptr`, waiting for any
+/// other transfers to finish first.
+async fn transfer(ptr: u64, mut buf: Transfer<'_>) -> Result<(), FloppyError> {
+      if buf.len() == 0 {
+            warn!("floppy: useless transfer with an empty buffer");
             return Ok(());
       }
 
@@ -72,141 +138,223 @@ pub fn read(ptr: u64, buf: &mut [u8]) -> Result<(), FloppyError> {
             return Err(DiskError::BadBufLen(buf.len() as u64).into());
       }
 
+      while BUSY.swap(true, Ordering::Acquire) {
+            time::sleep(0).await
+      }
+
+      let res = transfer_locked(ptr, &mut buf).await;
+      BUSY.store(false, Ordering::Release);
+      motor::disable_motor();
+
+      if res.is_ok() {
+            let stat = if buf.is_read() {
+                  &READ_BYTES
+            } else {
+                  &WRITTEN_BYTES
+            };
+            stat.fetch_add(buf.len() as u64, Ordering::Relaxed);
+      }
+      res
+}
+
+/// Transfers `buf` to or from the sector at offset `ptr`, retrying a few
+/// times before disabling the driver.
+///
+/// Uses a single DMA command while [`DMA_MODE`] is set, falling back to PIO
+/// if one can't be started or the controller never finishes it.
+///
+/// Only ran while [`BUSY`] is held.
+async fn transfer_locked(
+      ptr: u64, buf: &mut Transfer<'_>,
+) -> Result<(), FloppyError> {
       let sects = (buf.len() / SECTOR_SIZE) as u16;
       let ptr = ptr as u16;
       let mut err = DiskError::IoTimeout.into();
 
       dbg_info!(
-            "floppy: reading sectors {ptr}-{} ({}b)",
+            "floppy: {} sectors {ptr}-{} ({}b)",
+            if buf.is_read() { "reading" } else { "writing" },
             ptr + sects,
             buf.len()
       );
 
-      'retry: for _ in 0..DISK_RETRIES {
-            if let Err(e) = send_read_write(true, ptr, sects) {
-                  dbg_info!(
-                        "floppy: failed sending read command: {e}. retrying..."
-                  );
-                  err = e;
-                  continue 'retry;
+      motor::spin_up().await?;
+      let start = Instant::now();
+      for _ in 0..DISK_RETRIES {
+            // Safety: BUSY is held and the command's sent right after
+            if DMA_MODE.load(Ordering::Relaxed) &&
+                  let Err(e) = unsafe { start_dma(buf) }
+            {
+                  // PIO can move buffers which don't fit in the bounce buffer
+                  warn!("floppy: failed starting DMA: {e}, retrying with PIO");
+                  // Safety: No command has been sent yet
+                  unsafe { fall_back_to_pio()? };
+                  err = e.into();
+                  continue;
             }
 
-            for byte in buf.iter_mut() {
-                  wait_for_rqm()?;
-
-                  // Safety: The read_write call ensures that we're
-                  // reading bytes off the drive
-                  match unsafe { fifo::read_byte() } {
-                        Ok(data) => *byte = data,
-                        Err(e) => {
-                              warn!("floppy: read failed: {e}, retrying...");
-                              err = e;
-                              continue 'retry;
-                        }
+            let irq = match send_read_write(buf.is_read(), ptr, sects).await {
+                  Ok(irq) => irq,
+                  Err(e) => {
+                        dbg_info!(
+                              "floppy: failed sending read/write \
+                              command: {e}. retrying..."
+                        );
+                        err = e;
+                        continue;
+                  }
+            };
+
+            let dma = DMA_MODE.load(Ordering::Relaxed);
+            let res = if dma {
+                  // DMA moves the whole buffer, then raises a single IRQ
+                  fifo::wait_irq_within(irq, DMA_TIMEOUT).await
+            } else {
+                  // Safety: The read_write call ensures that the controller's
+                  // in the execution phase of the command
+                  unsafe { transfer_pio(buf, irq).await }
+            };
+
+            if let Err(e) = res {
+                  warn!("floppy: transfer failed: {e}, retrying...");
+                  if dma {
+                        // Safety: Resetting aborts the unfinished command
+                        unsafe { fall_back_to_pio()? };
                   }
+                  err = e;
+                  continue;
             }
 
-            // Safety: Just finished a read command
+            // Safety: Just finished a read or write command
             unsafe { read_write_status()? };
-            motor::disable_motor();
-            READ_BYTES.fetch_add(buf.len() as u64, Ordering::Relaxed);
+
+            if dma && let Transfer::Read(buf) = buf {
+                  // Safety: The transfer's finished and BUSY is held
+                  let bounce = unsafe { dma::buffer()? };
+                  buf.copy_from_slice(&bounce[..buf.len()]);
+            }
+
+            let nanos = start.elapsed().as_nanos() as u64;
+            TRANSFER_NANOS.fetch_add(nanos, Ordering::Relaxed);
+            dbg_info!("floppy: transfer took {nanos} ns");
             return Ok(());
       }
 
-      // Safety: Bailing halfway through a read command may leave the controller
-      // in an unsynced state and since it can't be reset while a disk
-      // operation is in progress, there's no real way to recover
+      // Safety: Bailing halfway through a read or write command may leave the
+      // controller in an unsynced state and since it can't be reset while a
+      // disk operation is in progress, there's no real way to recover
       unsafe { startup::FLOPPY_INIT.store(false) };
-      motor::disable_motor();
       println!(
-            "Reading from the floppy driver caused an \
-            unrecoverable error, {err}"
+            "{} the floppy driver caused an unrecoverable error, {err}",
+            if buf.is_read() {
+                  "Reading from"
+            } else {
+                  "Writing to"
+            }
       );
       println!(fg = LightRed, "All following floppy operations will fail");
       Err(err)
 }
 
-/// Writes `buf` into the sector at offset `ptr`.
+/// Programs DMA channel 2 to move `buf`, copying it into the bounce buffer
+/// first if it's being written.
 ///
-/// Fails if the length of `buf` isn't a multiple of 512.
-pub fn write(ptr: u64, buf: &[u8]) -> Result<(), FloppyError> {
-      if buf.is_empty() {
-            warn!("floppy: useless write with an empty buffer");
-            return Ok(());
-      }
-
-      if !startup::FLOPPY_INIT.load() {
-            return Err(DiskError::ControllerUninit.into());
-      }
-
-      if !buf.len().is_multiple_of(SECTOR_SIZE) {
-            return Err(DiskError::BadBufLen(buf.len() as u64).into());
-      }
-
-      // Loop attempting to write the data for a while
-      let sects = (buf.len() / SECTOR_SIZE) as u16;
-      let ptr = ptr as u16;
-      let mut err = DiskError::IoTimeout.into();
+/// # Safety
+/// Must be ran while [`BUSY`] is held, right before sending the read or write
+/// command.
+unsafe fn start_dma(buf: &Transfer<'_>) -> Result<(), DmaError> {
+      let len = buf.len();
+      let dir = match buf {
+            Transfer::Read(_) => Direction::Read,
+            Transfer::Write(buf) => {
+                  // Safety: BUSY ensures no other transfer's using the buffer
+                  let bounce = unsafe { dma::buffer()? };
+                  bounce.get_mut(..len)
+                        .ok_or(DmaError::BadLen(len))?
+                        .copy_from_slice(buf);
+                  Direction::Write
+            }
+      };
 
-      dbg_info!(
-            "floppy: writing sectors {ptr}-{} ({}b)",
-            ptr + sects,
-            buf.len()
-      );
+      // Safety: The bounce buffer isn't touched until the IRQ arrives
+      unsafe { dma::start_floppy(dir, len) }
+}
 
-      'retry: for _ in 0..DISK_RETRIES {
-            if let Err(e) = send_read_write(false, ptr, sects) {
-                  dbg_info!(
-                        "floppy: failed sending write \
-                        command: {e}. retrying..."
-                  );
-                  err = e;
-                  continue 'retry;
-            }
+/// Stops using DMA and reinitialises the controller to move bytes through
+/// the FIFO instead.
+///
+/// # Safety
+/// Must be ran while [`BUSY`] is held.
+unsafe fn fall_back_to_pio() -> Result<(), FloppyError> {
+      warn!("floppy: DMA isn't working, falling back to PIO");
+      DMA_MODE.store(false, Ordering::Relaxed);
 
-            // Write the data from the buf.
-            for byte in buf.iter() {
-                  wait_for_rqm()?;
+      // Safety: BUSY ensures that no other transfers are in progress
+      unsafe { reset::init_fdc() }
+}
 
-                  // Safety: The read_write call ensures that we're writing
-                  // bytes to the drive
-                  if let Err(e) = unsafe { fifo::send_byte(*byte) } {
-                        warn!("floppy: write failed: {e}, retrying...");
-                        err = e;
-                        continue 'retry;
+/// Moves every byte in `buf` through the FIFO, waiting for the controller to
+/// raise an IRQ whenever it isn't ready for more, then waits for the result
+/// phase.
+///
+/// # Safety
+/// Must be ran right after sending a read or write command, with `irq`
+/// created before it was sent.
+async unsafe fn transfer_pio(
+      buf: &mut Transfer<'_>, mut irq: IrqWait<'static>,
+) -> Result<(), FloppyError> {
+      /// Set when the FIFO is ready for the next byte.
+      const MSR_RQM: u8 = 1 << 7;
+
+      /// Set while in the execution phase of a non-DMA command.
+      const MSR_NON_DMA: u8 = 1 << 5;
+
+      let fifo = FloppyPort::Fifo.add_offset()?;
+      let mut idx = 0;
+      while idx < buf.len() {
+            fifo::wait_irq(irq).await?;
+            // Created before emptying the FIFO, so the next IRQ can't be missed
+            irq = wake::FLOPPY.wait();
+            motor::enable_motor()?;
+
+            let mut msr = FloppyPort::msr()?;
+            while msr & MSR_RQM != 0 &&
+                  msr & MSR_NON_DMA != 0 &&
+                  idx < buf.len()
+            {
+                  // Safety: The controller's asking for the next byte in
+                  // the execution phase, which the caller ensures is ours
+                  unsafe {
+                        match buf {
+                              Transfer::Read(buf) => {
+                                    buf[idx] = ports::readb(fifo)
+                              }
+                              Transfer::Write(buf) => {
+                                    ports::writeb(fifo, buf[idx])
+                              }
+                        }
                   }
+                  idx += 1;
+                  msr = FloppyPort::msr()?;
             }
 
-            /*/ FIXME: reading status fails and the next command being sent
-            fails, causing a fdc reset
-            #[allow(unused_variables)]
-            // Safety: Just finished a write command
-            if let Err(e) = unsafe { read_write_status() } {
-                warn!("failed retrieving floppy write status: {e}");
-            }*/
-            motor::disable_motor();
-            WRITTEN_BYTES.fetch_add(buf.len() as u64, Ordering::Relaxed);
-            return Ok(());
+            // The command ended early, the result phase says why
+            if msr & MSR_NON_DMA == 0 {
+                  break;
+            }
       }
 
-      // Safety: Bailing halfway through a write command may leave the
-      // controller in an unsynced state and since it can't be reset while
-      // a disk operation is in progress, there's no real way to recover
-      unsafe { startup::FLOPPY_INIT.store(false) };
-      motor::disable_motor();
-      println!(
-            "Writing to the floppy driver caused an unrecoverable error, {err}"
-      );
-      println!(fg = LightRed, "All following floppy operations will fail");
-      Err(err)
+      fifo::wait_irq(irq).await
 }
 
 /// Sends either the read or write command to the controller.
 ///
 /// See section 8.4 Read/Write Data Operations of the datasheet.
-fn send_read_write(
+///
+/// Returns an IRQ wait created right before the command was sent.
+async fn send_read_write(
       read: bool, ptr: u16, sects: u16,
-) -> Result<(), FloppyError> {
+) -> Result<IrqWait<'static>, FloppyError> {
       /// How many retries until we assume that there's either a
       /// seek/recalibrate or hardware error.
       const SEEK_RETRIES: u8 = 5;
@@ -253,16 +401,15 @@ fn send_read_write(
             unsafe {
                   // FIXME: first cmd sent always fails after a write,
                   // probs due to broken write read_write_status check
-                  fifo::seek(None)?;
-                  fifo::seek(Some(start_cyl))?
+                  fifo::seek(None).await?;
+                  fifo::seek(Some(start_cyl)).await?
             };
 
-            wait_for_rqm()?;
-
             // Safety: Only one disk command is ever ran at a time,
             // meaning one can never be in progress here
+            let irq = wake::FLOPPY.wait();
             if unsafe { fifo::send_command(&cmd, params).is_ok() } {
-                  return Ok(());
+                  return Ok(irq);
             }
       }
 
@@ -280,8 +427,6 @@ fn send_read_write(
 /// # Safety
 /// Must be sent right after a read or write command.
 unsafe fn read_write_status() -> Result<(), FloppyError> {
-      wait_for_rqm()?;
-
       // Safety: The check above ensures that we're reading the result
       // bytes from the command
       let (st0, st1, st2, _, _, _, _) = unsafe {
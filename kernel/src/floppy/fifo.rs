@@ -23,13 +23,19 @@
     Contained within the floppy module
 */
 
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
 use thiserror::Error;
 
+use crate::floppy::disk::DiskError;
 use crate::floppy::{
       DRIVE_ONE, FloppyError, FloppyPort, RETRIES, ST0_ERR_OR_RESET, TIMEOUT,
       motor, reset,
 };
-use crate::{ports, startup, time};
+use crate::task::wake::{self, IrqWait};
+use crate::time::{self, tickless};
+use crate::{ports, startup};
 
 /// Magnetic encoding mode bit, can be ORed into commands.
 /// Required for read / write
@@ -132,6 +138,52 @@ pub unsafe fn read_byte() -> Result<u8, FloppyError> {
       Err(FloppyError::FifoTimeout(FifoIOError::Read))
 }
 
+/// Returns a future which completes once the controller raises an IRQ after
+/// `irq` was created, or fails if it doesn't within [`TIMEOUT`] ticks.
+///
+/// `irq` must be created before sending whatever raises the IRQ, so that it
+/// can't be missed.
+pub fn wait_irq(irq: IrqWait<'static>) -> IrqTimeout {
+      wait_irq_within(irq, TIMEOUT)
+}
+
+/// Same as [`wait_irq`], but gives up after `ticks` kernel ticks instead.
+pub fn wait_irq_within(irq: IrqWait<'static>, ticks: u64) -> IrqTimeout {
+      IrqTimeout {
+            irq,
+            deadline: time::get_time() + ticks,
+      }
+}
+
+/// Completes once the controller raises an IRQ, returned by [`wait_irq`] and
+/// [`wait_irq_within`].
+pub struct IrqTimeout {
+      irq:      IrqWait<'static>,
+      /// When to give up waiting, in kernel ticks.
+      deadline: u64,
+}
+
+impl Future for IrqTimeout {
+      type Output = Result<(), FloppyError>;
+
+      fn poll(
+            mut self: Pin<&mut Self>, cx: &mut Context<'_>,
+      ) -> Poll<Self::Output> {
+            if Pin::new(&mut self.irq).poll(cx).is_ready() {
+                  return Poll::Ready(Ok(()));
+            }
+
+            if time::get_time() >= self.deadline {
+                  return Poll::Ready(Err(DiskError::IoTimeout.into()));
+            }
+
+            // Check the deadline again on the next tick
+            tickless::wake_by(self.deadline);
+            wake::TIMER.register(cx.waker());
+            Poll::Pending
+      }
+}
+
 /// The error returned from FIFO operations.
 #[derive(Error, Debug)]
 pub enum FifoIOError {
@@ -223,14 +275,16 @@ pub enum SendCmdError {
       ResetError(u8),
 }
 
-/// Sends the recalibrate command if `cyl` is `None`, otherwise seeks to `cyl`.
+/// Sends the recalibrate command if `cyl` is `None`, otherwise seeks to `cyl`,
+/// completing once the controller raises an IRQ for it.
 ///
 /// # Safety
 /// The controller must be initialised and not have a disk transfer in progress.
-pub unsafe fn seek(cyl: Option<u8>) -> Result<(), FloppyError> {
+pub async unsafe fn seek(cyl: Option<u8>) -> Result<(), FloppyError> {
       let mut ret = Ok(());
 
       'retry: for _ in 0..RETRIES {
+            let irq = wake::FLOPPY.wait();
             if let Some(cyl) = cyl {
                   let params = &[DRIVE_ONE.load() as u8, cyl];
                   // Safety: Caller must ensure valid cyl & no disk operations
@@ -241,6 +295,11 @@ pub unsafe fn seek(cyl: Option<u8>) -> Result<(), FloppyError> {
                   unsafe { send_command(&FloppyCommand::Recalibrate, params)? }
             }
 
+            if let Err(e) = wait_irq(irq).await {
+                  ret = Err(e);
+                  continue 'retry;
+            }
+
             if let Err(e) = unsafe { sense_int(SenseIntState::SeekOrRecal) } {
                   // There's no point checking for a ResendCommand error, since
                   // this function already retries after errors, and
@@ -23,22 +23,32 @@
     Contained within the floppy module
 */
 
+use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, Ordering};
 
 use libfs::header::{FilesystemHeader, FsFeatures};
 use libfs::init::{self, ReadTblError};
-use libfs::table::{BlockBitmap, InodeTable};
-use libfs::{BlockPtr, INODES, INode, MAGIC};
-use libutil::ExclusiveMap;
+use libfs::table::{self, BlockBitmap, InodeTable, UpdateInodeError};
+use libfs::{
+      BLOCK_SIZE, BLOCK_START, BlockPtr, FileMode, INODES, INode, InodePtr,
+      MAGIC,
+};
+use libutil::{AsBytes, ExclusiveMap};
 use thiserror::Error;
 
 use crate::floppy::{CYL_BOUNDARY, FloppyError, SECTOR_SIZE, SECTORS, disk};
+use crate::memory::slab::{Cached, INODE_CACHE};
 use crate::startup::{self, ExitCode};
-use crate::{exit_on_err, interrupts};
+use crate::{exit_on_err, interrupts, time};
 
 /// Has floppyfs been initialised yet?
 pub static FLOPPYFS_INIT: AtomicBool = AtomicBool::new(false);
 
+/// Set for each inode while a file's being written to it, so that concurrent
+/// writes can't overwrite each other's changes to the inode.
+static WRITING: [AtomicBool; INODES] =
+      [const { AtomicBool::new(false) }; INODES];
+
 /// The year value in the floppy fsheader.
 const YEAR: u16 = crate::env_as_int!("SFK_FLOPPYFS_YEAR", u16);
 
@@ -62,6 +72,8 @@ libfs::table_statics!();
 
 /// A wrapper over [`disk::write`], which allows writing over cylinder
 /// boundaries.
+///
+/// Blocks until it's done, so it's only used while starting up.
 pub fn write(block: u64, buf: &[u8]) -> Result<(), FloppyError> {
       // Since block can start anywhere relative to a cyl boundary,
       // we have to make sure to use a smaller buf for the first write
@@ -81,6 +93,8 @@ pub fn write(block: u64, buf: &[u8]) -> Result<(), FloppyError> {
 
 /// A wrapper over [`disk::read`], which allows reading over cylinder
 /// boundaries.
+///
+/// Blocks until it's done, so it's only used while starting up.
 pub fn read(block: u64, buf: &mut [u8]) -> Result<(), FloppyError> {
       // Since block can start anywhere relative to a cyl boundary,
       // we have to make sure to use a smaller buf for the first read
@@ -146,6 +160,169 @@ Filesystem features: {}",
       ExitCode::Ok
 }
 
+/// Returns a copy of the regular file stored in inode `idx` of the table,
+/// allocated from the inode cache.
+pub fn file_inode(idx: usize) -> Result<Cached<INode>, FileError> {
+      if !FLOPPYFS_INIT.load(Ordering::Relaxed) {
+            return Err(FileError::NoFilesystem);
+      }
+
+      let exmap = INODE_TBL.get(idx).ok_or(FileError::NoFile(idx))?;
+      let mut nod = INODE_CACHE.alloc().ok_or(FileError::NoCache)?;
+      exmap.map(|n| *nod = n.clone())
+            .ok_or(FileError::Contended(idx))?;
+      if nod.is_available() {
+            Err(FileError::NoFile(idx))
+      } else if nod.mode().contains(FileMode::DIRECTORY) {
+            Err(FileError::IsDirectory(idx))
+      } else {
+            Ok(nod)
+      }
+}
+
+/// Reads the file in inode `idx` starting from byte `pos` into `buf`,
+/// returning the number of bytes read.
+pub async fn read_file(
+      idx: usize, mut pos: usize, buf: &mut [u8],
+) -> Result<usize, FileError> {
+      let nod = file_inode(idx)?;
+      let end = (nod.size() as usize).min(pos.saturating_add(buf.len()));
+      let (mut block, mut read) = ([0; BLOCK_SIZE], 0);
+
+      while pos < end {
+            let offset = pos % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - offset).min(end - pos);
+            match nod.block(pos / BLOCK_SIZE).get() {
+                  Some(ptr) => {
+                        disk::read_async(BLOCK_START + ptr as u64, &mut block)
+                              .await?
+                  }
+                  None => block.fill(0), // never written to
+            }
+
+            buf[read..read + len].copy_from_slice(&block[offset..offset + len]);
+            (pos, read) = (pos + len, read + len);
+      }
+
+      Ok(read)
+}
+
+/// Writes `buf` into the file in inode `idx` starting from byte `pos`,
+/// allocating any new blocks needed and growing the file.
+///
+/// The block bitmap is rebuilt from the inode table when mounting, so new
+/// blocks only stay allocated once the inode's saved. They're freed if the
+/// write fails before then.
+pub async fn write_file(
+      idx: usize, pos: usize, buf: &[u8],
+) -> Result<(), FileError> {
+      let writing = WRITING.get(idx).ok_or(FileError::NoFile(idx))?;
+      while writing.swap(true, Ordering::Acquire) {
+            time::sleep(0).await
+      }
+
+      let mut allocated = Vec::new();
+      let res = write_file_locked(idx, pos, buf, &mut allocated).await;
+      if res.is_err() {
+            for ptr in allocated {
+                  if let Err(_e) = table::free_bmp(&ptr, &BLOCK_BMP) {
+                        warn!("floppyfs: failed freeing block: {_e}")
+                  }
+            }
+      }
+
+      writing.store(false, Ordering::Release);
+      res
+}
+
+/// Writes `buf` into the file in inode `idx` starting from byte `pos`,
+/// adding the blocks it allocates to `allocated`.
+///
+/// Only ran while the inode's [`WRITING`] flag is held.
+async fn write_file_locked(
+      idx: usize, mut pos: usize, buf: &[u8], allocated: &mut Vec<BlockPtr>,
+) -> Result<(), FileError> {
+      let mut nod = file_inode(idx)?;
+      let end = pos
+            .checked_add(buf.len())
+            .filter(|end| *end <= INode::MAX_SIZE)
+            .ok_or(FileError::TooBig)?;
+      let (mut block, mut written) = ([0; BLOCK_SIZE], 0);
+
+      while pos < end {
+            let offset = pos % BLOCK_SIZE;
+            let len = (BLOCK_SIZE - offset).min(end - pos);
+            let mut ptr = nod.block(pos / BLOCK_SIZE);
+
+            if ptr.is_null() {
+                  ptr = table::alloc_next_bmp(&BLOCK_BMP);
+                  if ptr.is_null() {
+                        return Err(FileError::NoSpace);
+                  }
+                  allocated.push(ptr.clone());
+                  nod.set_block(pos / BLOCK_SIZE, &ptr);
+                  block.fill(0);
+            } else if len != BLOCK_SIZE {
+                  // Keep the rest of the block
+                  disk::read_async(
+                        BLOCK_START + ptr.get_nullable() as u64,
+                        &mut block,
+                  )
+                  .await?
+            }
+
+            block[offset..offset + len]
+                  .copy_from_slice(&buf[written..written + len]);
+            disk::write_async(BLOCK_START + ptr.get_nullable() as u64, &block)
+                  .await?;
+            (pos, written) = (pos + len, written + len);
+      }
+
+      // Save the new size and blocks
+      let size = nod.size().max(end as u16);
+      nod.set_size(size);
+      INODE_TBL[idx]
+            .map(|n| *n = nod.clone())
+            .ok_or(FileError::Contended(idx))?;
+      allocated.clear(); // now used by the inode table
+
+      let (block, nods) =
+            table::inode_block(&InodePtr::new(idx as u16 + 1), &INODE_TBL)?;
+      disk::write_async(block, nods.as_bytes()).await?;
+      Ok(())
+}
+
+/// An error created when reading or writing a file.
+#[derive(Error, Debug)]
+pub enum FileError {
+      #[error("floppyfs hasn't been initialised")]
+      NoFilesystem,
+
+      #[error("inode {0} doesn't contain a file")]
+      NoFile(usize),
+
+      #[error("inode {0} is a directory")]
+      IsDirectory(usize),
+
+      #[error("files can't be larger than {} bytes", INode::MAX_SIZE)]
+      TooBig,
+
+      #[error("ran out of free blocks on the drive")]
+      NoSpace,
+
+      #[error("inode {0}'s exmap is being used somewhere else")]
+      Contended(usize),
+
+      #[error("couldn't allocate a copy of the inode from it's cache")]
+      NoCache,
+
+      #[error("floppy driver error: {0}")]
+      FloppyError(#[from] FloppyError),
+
+      #[error("update error: {0}")]
+      UpdateInode(#[from] UpdateInodeError<FloppyError>),
+}
+
 /// An error created when trying to initialise the floppy filesystem.
 #[derive(Error, Debug)]
 pub enum InitError {
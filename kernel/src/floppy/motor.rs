@@ -23,15 +23,17 @@
     Contained within the floppy module
 */
 
-use core::sync::atomic::{AtomicU8, AtomicU16, Ordering};
+use core::sync::atomic::{AtomicU8, Ordering};
 
-use libutil::InitError;
+use libutil::{ExclusiveMap, InitError};
 
 use super::{DRIVE_ONE, FloppyPort};
+use crate::time::KERNEL_TICKS_HZ;
+use crate::time::timer::{self, Context, TimerId};
 use crate::{ports, time};
 
-/// How long is left before the floppy's motor is disabled.
-static MOTOR_TIME_LEFT: AtomicU16 = AtomicU16::new(0);
+/// The timer which disables the floppy's motor, if it's waiting to be.
+static MOTOR_TIMER: ExclusiveMap<Option<TimerId>> = ExclusiveMap::new(None);
 
 /// The current state of the floppy's motor. See below consts for valid states.
 static MOTOR_STATE: AtomicU8 = AtomicU8::new(MOTOR_OFF);
@@ -45,88 +47,112 @@ const MOTOR_DISABLING: u8 = 1;
 /// The floppy's motor is off.
 const MOTOR_OFF: u8 = 2;
 
+/// How long the motor takes to speed up, in kernel ticks.
+const SPIN_UP: u64 = KERNEL_TICKS_HZ / 2;
+
 /// Enables the floppy's motor if it was disabled.
 pub fn enable_motor() -> Result<(), InitError<u16>> {
-      /// Drive 0's motor on, IRQs & DMA off, drive 0.
-      const DRIVE0_COMMAND: u8 = 0b01_0_1_00;
-
-      /// Drive 1's motor on, IRQs & DMA off, drive 1
-      const DRIVE1_COMMAND: u8 = 0b10_0_1_01;
-
       match MOTOR_STATE.load(Ordering::Relaxed) {
-            MOTOR_OFF => send_enable_cmd()?,
-            MOTOR_DISABLING => MOTOR_STATE.store(MOTOR_ON, Ordering::Relaxed),
+            MOTOR_OFF => {
+                  send_enable_cmd()?;
+                  time::wait(SPIN_UP);
+            }
+            MOTOR_DISABLING => {
+                  MOTOR_STATE.store(MOTOR_ON, Ordering::Relaxed);
+                  MOTOR_TIMER.map(|t| t.take().map(timer::cancel));
+            }
             MOTOR_ON => (),
             _state => {
                   warn!("floppy: unknown motor state: {_state}")
             }
       }
 
-      return Ok(());
+      Ok(())
+}
 
-      fn send_enable_cmd() -> Result<(), InitError<u16>> {
-            let dor_port = FloppyPort::DigitalOutputRegister.add_offset()?;
+/// Enables the floppy's motor if it was disabled, letting other tasks run
+/// while it speeds up.
+pub async fn spin_up() -> Result<(), InitError<u16>> {
+      if MOTOR_STATE.load(Ordering::Relaxed) == MOTOR_OFF {
+            send_enable_cmd()?;
+            time::sleep(SPIN_UP).await;
+      }
+      enable_motor()
+}
 
-            if DRIVE_ONE.load() {
-                  // Safety: Check above ensures drive 1 is being used
-                  unsafe { ports::writeb(dor_port, DRIVE1_COMMAND) };
-            } else {
-                  // Safety: The check above ensures drive 0 is being used
-                  unsafe { ports::writeb(dor_port, DRIVE0_COMMAND) }
-            }
+/// Turns on the floppy's motor, which can take up to 500 ms to speed up.
+fn send_enable_cmd() -> Result<(), InitError<u16>> {
+      /// Drive 0's motor on, IRQs & DMA on, drive 0.
+      const DRIVE0_COMMAND: u8 = 0b01_1_1_00;
 
-            MOTOR_STATE.store(MOTOR_ON, Ordering::Relaxed);
-            time::wait(50); // motor can take up to 500 ms to speed up
-            dbg_info!("floppy: motor on!");
-            Ok(())
+      /// Drive 1's motor on, IRQs & DMA on, drive 1
+      const DRIVE1_COMMAND: u8 = 0b10_1_1_01;
+
+      let dor_port = FloppyPort::DigitalOutputRegister.add_offset()?;
+
+      if DRIVE_ONE.load() {
+            // Safety: Check above ensures drive 1 is being used
+            unsafe { ports::writeb(dor_port, DRIVE1_COMMAND) };
+      } else {
+            // Safety: The check above ensures drive 0 is being used
+            unsafe { ports::writeb(dor_port, DRIVE0_COMMAND) }
       }
+
+      MOTOR_STATE.store(MOTOR_ON, Ordering::Relaxed);
+      dbg_info!("floppy: motor on!");
+      Ok(())
 }
 
-/// Enters the disabling state for the floppy's motor.
+/// Enters the disabling state for the floppy's motor, disabling it once it
+/// hasn't been used for a while.
 pub fn disable_motor() {
-      /// Time (plus one) until the motor is be disabled, in kernel ticks.
-      const TIMEOUT: u16 = 50;
+      /// Time until the motor is disabled, in kernel ticks.
+      const TIMEOUT: u64 = KERNEL_TICKS_HZ / 2;
 
-      MOTOR_TIME_LEFT.store(TIMEOUT, Ordering::Relaxed);
       MOTOR_STATE.store(MOTOR_DISABLING, Ordering::Relaxed);
+      MOTOR_TIMER.map(|t| {
+            if let Some(id) = t.take() {
+                  timer::cancel(id);
+            }
+
+            // The motor's left running if this fails, which is harmless
+            *t = timer::one_shot(TIMEOUT, Context::Deferred, motor_off).ok();
+      });
 }
 
 /// Forcefully disables the floppy's motor.
 pub fn force_disable() {
       MOTOR_STATE.store(MOTOR_DISABLING, Ordering::Relaxed);
-      MOTOR_TIME_LEFT.store(0, Ordering::Relaxed);
-      decrease_motor_time();
+      motor_off();
 }
 
-/// Decreases the time until the motor will be disabled.
-/// Called by the timer handler every 10 ms.
-#[unsafe(export_name = "dec_floppy_motor_time")]
-pub extern "sysv64" fn decrease_motor_time() {
-      /// Drive 0's motor off, IRQs & DMA off, drive 0 selected.
-      const DRIVE0_COMMAND: u8 = 0b00_0_1_00;
-
-      /// Drive 1's motor off, IRQs & DMA off, drive 1 selected.
-      const DRIVE1_COMMAND: u8 = 0b00_0_1_01;
-
-      if MOTOR_STATE.load(Ordering::Relaxed) == MOTOR_DISABLING &&
-            MOTOR_TIME_LEFT.fetch_sub(1, Ordering::Relaxed) == 0 &&
-            let Ok(dor) = FloppyPort::DigitalOutputRegister.add_offset()
-      {
-            send_disable_cmd(dor);
+/// Disables the floppy's motor if it's still waiting to be disabled.
+/// Ran by the motor's timer.
+fn motor_off() {
+      /// Drive 0's motor off, IRQs & DMA on, drive 0 selected.
+      const DRIVE0_COMMAND: u8 = 0b00_1_1_00;
+
+      /// Drive 1's motor off, IRQs & DMA on, drive 1 selected.
+      const DRIVE1_COMMAND: u8 = 0b00_1_1_01;
+
+      if MOTOR_STATE.load(Ordering::Relaxed) != MOTOR_DISABLING {
+            return;
       }
 
-      fn send_disable_cmd(dor: u16) {
-            if DRIVE_ONE.load() {
-                  // Safety: Check above ensure that drive 1 is being used
-                  unsafe { ports::writeb(dor, DRIVE1_COMMAND) }
-            } else {
-                  // Safety: The check above ensure that drive 0 is being used
-                  unsafe { ports::writeb(dor, DRIVE0_COMMAND) }
-            }
+      let Ok(dor) = FloppyPort::DigitalOutputRegister.add_offset() else {
+            return;
+      };
 
-            dbg_info!("floppy: motor off!");
-            MOTOR_STATE.store(MOTOR_OFF, Ordering::Relaxed);
+      if DRIVE_ONE.load() {
+            // Safety: Check above ensure that drive 1 is being used
+            unsafe { ports::writeb(dor, DRIVE1_COMMAND) }
+      } else {
+            // Safety: The check above ensure that drive 0 is being used
+            unsafe { ports::writeb(dor, DRIVE0_COMMAND) }
       }
+
+      dbg_info!("floppy: motor off!");
+      MOTOR_STATE.store(MOTOR_OFF, Ordering::Relaxed);
 }
 
 #[cfg(test)]
@@ -23,10 +23,13 @@
     Contained within the floppy module
 */
 
+use core::sync::atomic::Ordering;
+
 use crate::floppy::fifo::{self, SenseIntState};
 use crate::floppy::{
-      FLOPPY_SPACE, FloppyCommand, FloppyError, FloppyPort, motor,
+      DMA_MODE, FLOPPY_SPACE, FloppyCommand, FloppyError, FloppyPort, motor,
 };
+use crate::task::{self, wake};
 use crate::{ports, time};
 
 /// Sends the configure command to the controller.
@@ -64,6 +67,7 @@ pub unsafe fn init_fdc() -> Result<(), FloppyError> {
       motor::enable_motor()?;
       let dor = FloppyPort::DigitalOutputRegister.add_offset()?;
 
+      let irq = wake::FLOPPY.wait();
       // Safety: The DOR's state is restored after clearing
       // the reset bit and waiting for the it to finish.
       unsafe {
@@ -73,6 +77,9 @@ pub unsafe fn init_fdc() -> Result<(), FloppyError> {
             ports::writeb(dor, prev);
       }
 
+      // The controller raises an IRQ once it's finished resetting
+      task::block_on(fifo::wait_irq(irq))?;
+
       // Safety: 4 sense interrupts are required after a reset
       unsafe {
             fifo::sense_int(SenseIntState::FirstReset)?;
@@ -107,10 +114,10 @@ pub unsafe fn init_fdc() -> Result<(), FloppyError> {
       /// Zero = max head unload time.
       const HUT: u8 = 0;
 
-      /// Not DMA flag.
-      const NDMA: u8 = true as u8;
+      // Not DMA flag, only set once DMA has failed or isn't available
+      let ndma = !DMA_MODE.load(Ordering::Relaxed) as u8;
 
-      let params = &[((srt << 4) | HUT), ((hlt << 1) | NDMA)];
+      let params = &[((srt << 4) | HUT), ((hlt << 1) | ndma)];
 
       // Safety: The check above ensures that we're sending the right params
       unsafe { fifo::send_command(&FloppyCommand::Specify, params)? }
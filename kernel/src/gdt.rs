@@ -23,32 +23,64 @@
 */
 
 use core::arch::asm;
+use core::cell::SyncUnsafeCell;
 use core::mem;
 
 use libutil::{InitError, InitLater, LoadRegisterError, TableDescriptor};
 use thiserror::Error;
 
+use crate::memory::stacks::{self, StackError};
 use crate::startup::{self, ExitCode, GDT_INIT};
 use crate::{exit_on_err, interrupts};
 
 /// The number of entries the GDT contains.
-const GDT_ENTRIES: usize = 5;
+const GDT_ENTRIES: usize = 7;
 
 /// The loaded GDT.
 pub static GDT: InitLater<Gdt> = InitLater::uninit();
 
-/// The size of the emergency stack, in bytes.
-const STACK_SIZE: u64 = 2048;
+/// The IST used by double faults.
+pub const DF_IST: u8 = 1;
 
-/// The emergency stack given to IST 1.
-static mut STACK: [u8; STACK_SIZE as usize] = [0; STACK_SIZE as usize];
+/// The IST used by page faults.
+pub const PF_IST: u8 = 2;
+
+/// The IST used by NMIs.
+pub const NMI_IST: u8 = 3;
+
+/// The IST used by machine checks.
+pub const MC_IST: u8 = 4;
+
+/// The name of each IST stack, starting from IST 1.
+const IST_NAMES: [&str; 4] = [
+      "double fault stack", "page fault stack", "NMI stack",
+      "machine check stack",
+];
+
+/// The size of each IST stack, in pages.
+const IST_PAGES: u64 = 4;
+
+/// The size of the fallback stack, in bytes.
+const FALLBACK_STACK_SIZE: u64 = 2048;
+
+/// The stack given to any IST whose stack couldn't be allocated.
+static mut FALLBACK_STACK: [u8; FALLBACK_STACK_SIZE as usize] =
+      [0; FALLBACK_STACK_SIZE as usize];
 
 /// Offset in the GDT where the kernel's code segment will be.
 #[unsafe(no_mangle)]
-static CODE_SEGMENT_OFFSET: u16 = 0x8;
+pub static CODE_SEGMENT_OFFSET: u16 = 0x8;
+
+/// Offset in the GDT where the user's data segment will be.
+///
+/// Placed directly before the user code segment, as `sysret` expects.
+pub const USER_DATA_OFFSET: u16 = 0x18;
+
+/// Offset in the GDT where the user's code segment will be.
+pub const USER_CODE_OFFSET: u16 = 0x20;
 
 /// Offset in the GDT where the TSS's system segment descriptor will be.
-const TSS_SEGMENT_OFFSET: u64 = 0x18;
+const TSS_SEGMENT_OFFSET: u64 = 0x28;
 
 /// The Global Descriptor Table.
 #[derive(Debug)]
@@ -62,21 +94,33 @@ struct SegmentDescriptor(u64);
 
 impl SegmentDescriptor {
       /// Creates either a new data or code segment depending
-      /// on if `code_segment` is set or not.
+      /// on if `code_segment` is set or not, usable from ring 3 if `user` is
+      /// set.
       #[rustfmt::skip]
-      fn new(code_segment: bool) -> Self {
+      fn new(code_segment: bool, user: bool) -> Self {
             // Code / data segment, present & long mode bits set
             SegmentDescriptor(
                   (1 << 44) |
                   (1 << 47) |
                   (1 << 53) |
-                  (code_segment as u64) << 43,
+                  (code_segment as u64) << 43 |
+                  (!code_segment as u64) << 41 | // writable, for SS
+                  (user as u64 * 3) << 45,       // DPL
             )
       }
+
+      /// Returns the segment's descriptor privilege level.
+      #[cfg(test)]
+      fn dpl(&self) -> u64 {
+            (self.0 >> 45) & 0b11
+      }
 }
 
 /// The loaded Task State Segment.
-static TSS: InitLater<Tss> = InitLater::uninit();
+///
+/// In a cell as `rsp0` changes whenever a thread running user code is switched
+/// to.
+static TSS: InitLater<SyncUnsafeCell<Tss>> = InitLater::uninit();
 
 /// The 64 bit Task State Segment.
 #[derive(Debug, Default)]
@@ -117,11 +161,11 @@ struct SystemSegmentDescriptor {
 
 impl SystemSegmentDescriptor {
       /// Creates a new descriptor from the provided TSS.
-      fn new_tss(tss: &'static Tss) -> Self {
+      fn new_tss(tss: &'static SyncUnsafeCell<Tss>) -> Self {
             /// Present, available 64 bit TSS
             const ACCESS: u8 = 0b1000_1001;
 
-            let tss = tss as *const Tss as u64;
+            let tss = tss.get() as u64;
 
             SystemSegmentDescriptor {
                   limit:           (size_of::<Tss>() - 1) as u16,
@@ -139,23 +183,69 @@ impl SystemSegmentDescriptor {
 }
 
 /// Loads a new TSS into the `TSS` static.
-/// Gives the first IST stack pointer it's own stack.
-pub fn setup_tss() -> ExitCode<InitError<Tss>> {
-      // Calculate stack start & end addresses
+/// Gives each IST stack pointer it's own stack with a guard page, falling back
+/// to a static stack if allocating one fails.
+pub fn setup_tss() -> ExitCode<SetupTssError> {
       let mut tss = Tss::default();
-      let stack_addr = &raw const STACK as u64;
-      let stack_end_addr = stack_addr + STACK_SIZE;
-      dbg_info!("emergency stack at 0x{stack_addr:x} to 0x{stack_end_addr:x}");
+      let fallback = &raw const FALLBACK_STACK as u64 + FALLBACK_STACK_SIZE;
+      let mut res = Ok(());
+
+      // The TSS is packed, so the stacks can't be written to it in place
+      let mut ists = tss.ist;
+      for (ist, name) in ists.iter_mut().zip(IST_NAMES) {
+            match stacks::alloc_stack(name, IST_PAGES) {
+                  Ok(stack) => {
+                        dbg_info!("{name} at 0x{:x}", stack.bottom);
+                        *ist = stack.top
+                  }
+                  Err(e) => {
+                        *ist = fallback;
+                        res = Err(e);
+                  }
+            }
+      }
 
       // Load the TSS into it's static
-      tss.ist[0] = stack_end_addr;
+      tss.ist = ists;
       tss.iomap = size_of::<Tss>() as u16;
-      exit_on_err!(TSS.init(tss));
+      exit_on_err!(TSS.init(SyncUnsafeCell::new(tss)));
       dbg_info!("TSS at 0x{:x}", &raw const TSS as u64);
 
+      // Only fail after loading the TSS, as the fallback stack still works
+      exit_on_err!(res);
       ExitCode::Ok
 }
 
+/// An error created when preparing the TSS.
+#[derive(Error, Debug)]
+pub enum SetupTssError {
+      #[error(transparent)]
+      Init(#[from] InitError<SyncUnsafeCell<Tss>>),
+
+      #[error("Failed allocating an IST stack, using fallback: {0}")]
+      Stack(#[from] StackError),
+}
+
+/// Returns a pointer to the stack pointer the CPU switches to when an interrupt
+/// occurs in ring 3, which is only 4 byte aligned.
+pub fn rsp0_ptr() -> Option<*mut u64> {
+      let tss = TSS.read().ok()?.get();
+      // Safety: Only creating a pointer to the field
+      Some(unsafe { &raw mut (*tss).privilege_ptrs }.cast())
+}
+
+/// Sets the stack pointer the CPU switches to when an interrupt occurs in
+/// ring 3.
+///
+/// Must be called with external interrupts disabled.
+pub fn set_rsp0(rsp0: u64) {
+      if let Some(ptr) = rsp0_ptr() {
+            // Safety: The CPU only reads rsp0 when an interrupt occurs, which
+            // can't happen while it's being written
+            unsafe { ptr.write_unaligned(rsp0) }
+      }
+}
+
 /// Loads the TSS into the task register.
 pub fn load_tss() -> ExitCode<LoadTssError> {
       if !startup::GDT_INIT.load() {
@@ -199,8 +289,10 @@ pub fn load_gdt() -> ExitCode<LoadRegisterError<Gdt>> {
       interrupts::cli();
       let mut gdt = Gdt([const { SegmentDescriptor(0) }; GDT_ENTRIES]);
 
-      gdt.0[1] = SegmentDescriptor::new(true); // loaded at CODE_SEGMENT_OFFSET
-      gdt.0[2] = SegmentDescriptor::new(false); // <- is this needed?
+      gdt.0[1] = SegmentDescriptor::new(true, false); // at CODE_SEGMENT_OFFSET
+      gdt.0[2] = SegmentDescriptor::new(false, false); // <- is this needed?
+      gdt.0[3] = SegmentDescriptor::new(false, true); // at USER_DATA_OFFSET
+      gdt.0[4] = SegmentDescriptor::new(true, true); // at USER_CODE_OFFSET
 
       // Don't need to log an error if the read fails, since it would
       // be printed in the 'Prepared TSS load' startup task
@@ -217,8 +309,8 @@ pub fn load_gdt() -> ExitCode<LoadRegisterError<Gdt>> {
                   >(desc)
             };
 
-            gdt.0[3] = low; // load descriptor at TSS_SEGMENT_OFFSET
-            gdt.0[4] = high;
+            gdt.0[5] = low; // load descriptor at TSS_SEGMENT_OFFSET
+            gdt.0[6] = high;
       }
 
       let gdt = exit_on_err!(GDT.init(gdt));
@@ -284,6 +376,7 @@ pub fn cs_register() -> u16 {
 #[cfg(test)]
 mod tests {
       use super::*;
+      use crate::memory::{PAGE_SIZE, paging};
 
       /// Tests that various structs passed to hardware
       /// are the size that it expects.
@@ -308,7 +401,7 @@ mod tests {
       #[test_case]
       fn tss_segment_has_correct_ptr() {
             let tss = TSS.read().unwrap();
-            let ptr = tss as *const Tss as u64;
+            let ptr = tss.get() as u64;
             let segment = SystemSegmentDescriptor::new_tss(tss);
 
             let mut segment_ptr = segment.offset_very_low as u64;
@@ -318,12 +411,40 @@ mod tests {
             assert_eq!(ptr, segment_ptr)
       }
 
-      /// Tests that IST 1 points to the emergency stack.
+      /// Tests that the user segments are usable from ring 3 and in the order
+      /// that `sysret` expects.
+      #[test_case]
+      fn user_segments_are_ring_3() {
+            let gdt = GDT.read().unwrap();
+            let segment = |offset: u16| &gdt.0[offset as usize / 8];
+            assert_eq!(segment(CODE_SEGMENT_OFFSET).dpl(), 0);
+            assert_eq!(segment(USER_DATA_OFFSET).dpl(), 3);
+            assert_eq!(segment(USER_CODE_OFFSET).dpl(), 3);
+            assert_eq!(USER_CODE_OFFSET - USER_DATA_OFFSET, 8);
+      }
+
+      /// Tests that `set_rsp0` changes the TSS's `rsp0`.
       #[test_case]
-      fn ist_one_points_to_emergency_stack() {
+      fn set_rsp0_changes_tss() {
             let tss = TSS.read().unwrap();
-            let stack_end_addr = &raw const STACK as u64 + STACK_SIZE;
-            let ist1 = tss.ist[0];
-            assert_eq!(ist1, stack_end_addr)
+            interrupts::without_interrupts(|| {
+                  let old = unsafe { (*tss.get()).privilege_ptrs[0] };
+                  set_rsp0(0xF00D);
+                  let ptrs = unsafe { (*tss.get()).privilege_ptrs };
+                  set_rsp0(old); // so programs don't switch to a junk stack
+                  assert_eq!(ptrs[0], 0xF00D);
+            });
+      }
+
+      /// Tests that each IST points to the top of it's own guarded stack.
+      #[test_case]
+      fn ists_point_to_guarded_stacks() {
+            let tss = TSS.read().unwrap();
+            let ists = unsafe { (*tss.get()).ist };
+            for (ist, name) in ists.into_iter().zip(IST_NAMES) {
+                  let guard = ist - (IST_PAGES + 1) * PAGE_SIZE;
+                  assert!(paging::translate(ist - 8).is_some());
+                  assert_eq!(stacks::overflowed_stack(guard), Some(name));
+            }
       }
 }
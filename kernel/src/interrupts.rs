@@ -22,30 +22,45 @@
     The interrupts module handles exceptions and irqs.
     This file is responsible for nothing really :(
 
-    Contains 4 submodules:
+    Contains 8 submodules:
+    * apic.rs - Local APIC and I/O APIC driver
     * cont_access.rs - Defines the `ContAccess` type.
     * idt.rs - Handles loading the IDT and it's handlers
+    * irq.rs - Lets drivers register handlers for IRQ lines
     * keyboard.rs - PS/2 keyboard driver, TODO: move out of interrupts module
-    * pic.rs - Initialises the PICs
+    * pic.rs - Initialises the PICs, used when there's no APIC
+    * stats.rs - Counts every interrupt raised, and draws the interrupt monitor
+    * watchdog.rs - NMI watchdog, which reports the kernel as hung
 */
 
 use core::arch::asm;
 use core::ffi::c_void;
 use core::fmt::Display;
 
+pub use apic::{init as init_apic, one_shot_lapic_timer, restart_lapic_timer};
 use idt::InterruptDescriptor;
-pub use keyboard::init as init_kbd;
+pub use irq::{IrqError, register_irq};
+pub use keyboard::{clear_typed, init as init_kbd, next_typed};
 use libutil::{InitLater, LoadRegisterError, TableDescriptor};
 pub use pic::init as init_pic;
+pub use stats::IntStats;
+pub use watchdog::{
+      init as init_watchdog, pet as pet_watchdog, stop as stop_watchdog,
+};
 
+use crate::exit_on_err;
 use crate::startup::ExitCode;
+use crate::task::wake;
 use crate::vga::cursor;
-use crate::{exit_on_err, time};
 
+mod apic;
 pub mod cont_access;
 mod idt;
+mod irq;
 mod keyboard;
 mod pic;
+mod stats;
+mod watchdog;
 
 /// Where IRQ vectors start in the IDT.
 const IRQ_START: usize = 32;
@@ -69,6 +84,13 @@ pub struct IntStackFrame {
       ss:     u64,
 }
 
+impl IntStackFrame {
+      /// Returns whether the interrupt occurred while running user code.
+      pub fn is_user(&self) -> bool {
+            self.cs & 0b11 == 3
+      }
+}
+
 impl Display for IntStackFrame {
       fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
             write!(
@@ -109,18 +131,34 @@ pub fn idt_register() -> TableDescriptor<Idt> {
       idt
 }
 
-/// Repeatedly loops polling the keyboard.
-pub fn kbd_poll_loop() -> ! {
-      loop {
-            keyboard::poll_keyboard();
+/// Sends the EOI command for IRQ `irq` to either the LAPIC or the PICs,
+/// depending on which one is being used.
+#[unsafe(no_mangle)]
+pub extern "sysv64" fn eoi(irq: u8) {
+      if apic::enabled() {
+            apic::eoi()
+      } else {
+            pic::eoi(irq)
+      }
+}
 
-            // Safety: Since the PIT fires an interrupt every
-            // ms anyway, why not just hlt after each poll?
-            unsafe { asm!("hlt") }
+/// Masks or unmasks IRQ line `line` in either the I/O APIC or the PICs.
+fn set_irq_masked(line: u8, masked: bool) {
+      if apic::enabled() {
+            apic::set_masked(line, masked);
+      } else {
+            pic::set_masked(line, masked)
+      }
+}
 
-            if time::get_time().is_multiple_of(10) {
-                  cursor::update_visual_pos(); // updates every 100 ms
-            }
+/// Handles keys pressed on the keyboard, waiting for the keyboard IRQ
+/// whenever there aren't any left.
+pub async fn kbd_task() {
+      loop {
+            // Start waiting before polling so no keys are missed
+            let irq = wake::KEYBOARD.wait();
+            while keyboard::poll_keyboard() {}
+            irq.await;
       }
 }
 
@@ -154,6 +192,22 @@ pub fn cli() {
       unsafe { asm!("cli") }
 }
 
+/// Runs `f` with external interrupts cleared, setting them again afterwards if
+/// they were set before.
+pub fn without_interrupts<F: FnOnce() -> R, R>(f: F) -> R {
+      const IF: u64 = 1 << 9;
+      let rflags: u64;
+      // Safety: Just reading RFLAGS
+      unsafe { asm!("pushf", "pop {}", out(reg) rflags) };
+
+      cli();
+      let res = f();
+      if rflags & IF != 0 {
+            sti()
+      }
+      res
+}
+
 /// Halts the CPU.
 pub fn hlt() {
       // Safety: Just halting
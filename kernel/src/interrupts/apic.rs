@@ -0,0 +1,396 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/interrupts/apic.rs
+
+    Local APIC and I/O APIC driver, replacing the PICs when an APIC exists.
+    Contained within the interrupts module
+*/
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicPtr, AtomicU32, Ordering};
+use core::{hint, ptr};
+
+use libutil::IrqSafeMutex;
+use thiserror::Error;
+
+use super::irq::{self, IRQ_LINES};
+use super::{IRQ_START, pic};
+use crate::memory::PAGE_SIZE;
+use crate::memory::paging::{self, MapError};
+use crate::msr::{self, Msr};
+use crate::startup::{self, ExitCode};
+use crate::time::clock::NANOS_PER_TICK;
+use crate::time::{self, TickSource};
+use crate::{acpi, exit_on_err};
+
+/// The vector spurious LAPIC interrupts are sent to.
+pub const SPURIOUS_VECTOR: usize = 0xFF;
+
+/// The secondary PIC's cascade IRQ, which isn't routed.
+const CASCADE_IRQ: u8 = 2;
+
+/// The mapped LAPIC registers, or null if the PICs are being used instead.
+static LAPIC: AtomicPtr<u32> = AtomicPtr::new(ptr::null_mut());
+
+/// Every I/O APIC in the MADT.
+static IOAPICS: IrqSafeMutex<Vec<IoApic>> = IrqSafeMutex::new(Vec::new());
+
+/// A register in the local APIC, by it's offset from the LAPIC's base.
+#[derive(Debug, Clone, Copy)]
+#[repr(usize)]
+pub(super) enum LapicReg {
+      Id           = 0x20,
+      /// Task priority
+      Tpr          = 0x80,
+      Eoi          = 0xB0,
+      /// Spurious interrupt vector
+      Svr          = 0xF0,
+      LvtTimer     = 0x320,
+      /// Performance monitoring counters
+      LvtPerf      = 0x340,
+      InitialCount = 0x380,
+      CurrentCount = 0x390,
+      DivideConfig = 0x3E0,
+}
+
+/// Returns the value in LAPIC register `reg`.
+fn lapic_read(reg: LapicReg) -> u32 {
+      let lapic = LAPIC.load(Ordering::Relaxed);
+      // Safety: LAPIC is only set once it's registers have been mapped
+      unsafe { lapic.byte_add(reg as usize).read_volatile() }
+}
+
+/// Writes `val` into LAPIC register `reg`.
+pub(super) fn lapic_write(reg: LapicReg, val: u32) {
+      let lapic = LAPIC.load(Ordering::Relaxed);
+      // Safety: LAPIC is only set once it's registers have been mapped
+      unsafe { lapic.byte_add(reg as usize).write_volatile(val) }
+}
+
+/// Returns whether the APICs are being used instead of the PICs.
+pub fn enabled() -> bool {
+      !LAPIC.load(Ordering::Relaxed).is_null()
+}
+
+/// Sends the EOI command to the local APIC.
+pub fn eoi() {
+      lapic_write(LapicReg::Eoi, 0)
+}
+
+/// An I/O APIC, which routes GSIs to interrupt vectors.
+struct IoApic {
+      /// The address of the mapped `IOREGSEL` register.
+      regs:     usize,
+      gsi_base: u32,
+      /// The number of redirection entries it has.
+      entries:  u32,
+}
+
+impl IoApic {
+      /// The register holding the number of redirection entries.
+      const VERSION: u32 = 1;
+
+      /// The register holding the low half of the first redirection entry.
+      const REDIRECTION: u32 = 0x10;
+
+      /// Returns the value in register `reg`.
+      fn read(&self, reg: u32) -> u32 {
+            let regs = self.regs as *mut u32;
+            // Safety: `regs` points to the mapped IOREGSEL, with IOWIN 16
+            // bytes after it, and IOAPICS being locked prevents races
+            unsafe {
+                  regs.write_volatile(reg);
+                  regs.byte_add(0x10).read_volatile()
+            }
+      }
+
+      /// Writes `val` into register `reg`.
+      fn write(&self, reg: u32, val: u32) {
+            let regs = self.regs as *mut u32;
+            // Safety: See read
+            unsafe {
+                  regs.write_volatile(reg);
+                  regs.byte_add(0x10).write_volatile(val)
+            }
+      }
+
+      /// Returns whether `gsi` is handled by this I/O APIC.
+      fn handles(&self, gsi: u32) -> bool {
+            (self.gsi_base..self.gsi_base + self.entries).contains(&gsi)
+      }
+
+      /// Sets the redirection entry for `gsi` to `entry`.
+      fn set_entry(&self, gsi: u32, entry: u64) {
+            let reg = Self::REDIRECTION + (gsi - self.gsi_base) * 2;
+            self.write(reg, entry as u32);
+            self.write(reg + 1, (entry >> 32) as u32);
+      }
+
+      /// Returns the redirection entry for `gsi`.
+      fn entry(&self, gsi: u32) -> u64 {
+            let reg = Self::REDIRECTION + (gsi - self.gsi_base) * 2;
+            self.read(reg) as u64 | (self.read(reg + 1) as u64) << 32
+      }
+}
+
+/// The redirection entry flag for active low IRQs.
+const ENTRY_ACTIVE_LOW: u64 = 1 << 13;
+
+/// The redirection entry flag for level triggered IRQs.
+const ENTRY_LEVEL: u64 = 1 << 15;
+
+/// The redirection entry flag which masks the IRQ.
+const ENTRY_MASKED: u64 = 1 << 16;
+
+/// Returns the redirection entry sending an IRQ to `vector` on the LAPIC with
+/// id `dest`, using fixed delivery and physical destination mode.
+fn redirection_entry(vector: u8, dest: u8, low: bool, level: bool) -> u64 {
+      let mut entry = vector as u64 | (dest as u64) << 56;
+      if low {
+            entry |= ENTRY_ACTIVE_LOW;
+      }
+      if level {
+            entry |= ENTRY_LEVEL;
+      }
+      entry
+}
+
+/// Masks or unmasks ISA IRQ `irq` in the I/O APIC handling it.
+///
+/// Returns `false` if the APICs aren't being used or no I/O APIC handles it.
+pub fn set_masked(irq: u8, masked: bool) -> bool {
+      let Ok(madt) = acpi::MADT.read() else {
+            return false;
+      };
+      if !startup::APIC_INIT.load() {
+            return false;
+      }
+      let (gsi, _) = madt.irq_to_gsi(irq);
+      let ioapics = IOAPICS.lock();
+
+      match ioapics.iter().find(|io| io.handles(gsi)) {
+            Some(ioapic) => {
+                  let entry = ioapic.entry(gsi) & !ENTRY_MASKED;
+                  let mask = if masked { ENTRY_MASKED } else { 0 };
+                  ioapic.set_entry(gsi, entry | mask);
+                  true
+            }
+            None => false,
+      }
+}
+
+/// Enables the local APIC, routes the ISA IRQs through the I/O APICs then
+/// replaces the PIT with the LAPIC timer, if it's the preferred tick source.
+///
+/// Leaves the PICs in use if there's no APIC.
+///
+/// # Safety
+/// Only run this once, after the PIT has started ticking.
+pub unsafe fn init() -> ExitCode<ApicError> {
+      /// Enables the LAPIC in the spurious interrupt vector register.
+      const SVR_ENABLE: u32 = 1 << 8;
+
+      /// Enables the LAPIC in the APIC base MSR.
+      const BASE_ENABLE: u64 = 1 << 11;
+
+      let madt = exit_on_err!(acpi::MADT.read().map_err(|_| ApicError::NoMadt));
+      if madt.ioapics.is_empty() {
+            return ExitCode::Error(ApicError::NoIoApic);
+      }
+
+      // Safety: The MADT gives the addresses of the APIC's registers
+      let lapic = exit_on_err!(unsafe {
+            paging::map_mmio(madt.lapic_addr, PAGE_SIZE)
+      });
+
+      let mut ioapics = Vec::new();
+      for info in &madt.ioapics {
+            // Safety: See above
+            let regs =
+                  exit_on_err!(unsafe { paging::map_mmio(info.addr, 0x20) });
+            let mut ioapic = IoApic {
+                  regs:     regs as usize,
+                  gsi_base: info.gsi_base,
+                  entries:  0,
+            };
+
+            ioapic.entries = ((ioapic.read(IoApic::VERSION) >> 16) & 0xFF) + 1;
+            let gsis = ioapic.gsi_base..ioapic.gsi_base + ioapic.entries;
+            gsis.for_each(|gsi| ioapic.set_entry(gsi, ENTRY_MASKED));
+            ioapics.push(ioapic);
+      }
+
+      super::without_interrupts(|| {
+            // Safety: The MADT says there's a LAPIC, so the MSR exists
+            unsafe {
+                  let base = msr::read(Msr::ApicBase);
+                  msr::write(Msr::ApicBase, base | BASE_ENABLE);
+            }
+
+            // The PICs are still remapped, so any spurious IRQs they send
+            // don't get mistaken for exceptions
+            if madt.has_8259 {
+                  pic::mask_all();
+            }
+            LAPIC.store(lapic.cast(), Ordering::Relaxed);
+            lapic_write(LapicReg::Svr, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+            lapic_write(LapicReg::Tpr, 0);
+
+            let dest = (lapic_read(LapicReg::Id) >> 24) as u8;
+            let mut ioapics_lock = IOAPICS.lock();
+            *ioapics_lock = ioapics;
+
+            // Route every ISA IRQ, only unmasking the timer and lines with
+            // handlers registered
+            for irq in (0..IRQ_LINES as u8).filter(|irq| *irq != CASCADE_IRQ) {
+                  let (gsi, irq_override) = madt.irq_to_gsi(irq);
+                  let low = irq_override.is_some_and(|o| o.active_low());
+                  let level = irq_override.is_some_and(|o| o.level_triggered());
+                  let vector = IRQ_START as u8 + irq;
+                  let mut entry = redirection_entry(vector, dest, low, level);
+                  if irq != 0 && !irq::registered(irq) {
+                        entry |= ENTRY_MASKED;
+                  }
+
+                  if let Some(ioapic) =
+                        ioapics_lock.iter().find(|io| io.handles(gsi))
+                  {
+                        ioapic.set_entry(gsi, entry)
+                  } else {
+                        warn!("apic: no ioapic handles irq {irq}");
+                  }
+            }
+      });
+
+      // Safety: Just enabled them above
+      unsafe { startup::APIC_INIT.store(true) }
+      dbg_info!("apic: routed isa irqs through the ioapic");
+
+      if time::PREFERRED_TICKS != TickSource::Apic {
+            return ExitCode::Ok;
+      }
+
+      let Some(count) = calibrate_timer() else {
+            return ExitCode::Error(ApicError::Calibration);
+      };
+      start_timer(count);
+      ExitCode::Ok
+}
+
+/// The divide configuration value which divides the bus clock by 16.
+const DIVIDE_BY_16: u32 = 0b0011;
+
+/// Masks the LVT entry it's in.
+const LVT_MASKED: u32 = 1 << 16;
+
+/// The LAPIC timer's initial count which fires once every tick, set once it
+/// starts raising ticks.
+static TICK_COUNT: AtomicU32 = AtomicU32::new(0);
+
+/// Returns the LAPIC timer's initial count which fires once every PIT tick,
+/// or `None` if it never ticked.
+fn calibrate_timer() -> Option<u32> {
+      /// How many PIT ticks to measure the LAPIC timer over.
+      const TICKS: u64 = 10;
+
+      lapic_write(LapicReg::DivideConfig, DIVIDE_BY_16);
+      lapic_write(LapicReg::LvtTimer, LVT_MASKED | IRQ_START as u32);
+
+      // Start measuring right after a tick
+      let prev = time::get_time();
+      while time::get_time() == prev {
+            hint::spin_loop()
+      }
+
+      lapic_write(LapicReg::InitialCount, u32::MAX);
+      let start = time::get_time();
+      while time::get_time() - start < TICKS {
+            if lapic_read(LapicReg::CurrentCount) == 0 {
+                  return None;
+            }
+            hint::spin_loop()
+      }
+
+      let elapsed = u32::MAX - lapic_read(LapicReg::CurrentCount);
+      lapic_write(LapicReg::InitialCount, 0);
+      Some(elapsed / TICKS as u32).filter(|count| *count != 0)
+}
+
+/// Starts the LAPIC timer firing every `count` bus cycles / 16, then stops
+/// the PIT's IRQ so ticks aren't counted twice.
+fn start_timer(count: u32) {
+      TICK_COUNT.store(count, Ordering::Relaxed);
+      super::without_interrupts(|| {
+            restart_lapic_timer();
+            set_masked(0, true);
+            time::set_tick_source(TickSource::Apic);
+      });
+      dbg_info!("apic: lapic timer ticking every {count} cycles");
+}
+
+/// Makes the LAPIC timer fire every tick again, after it was made to fire
+/// once by [`one_shot_lapic_timer`].
+pub fn restart_lapic_timer() {
+      /// Periodic timer mode.
+      const LVT_PERIODIC: u32 = 1 << 17;
+
+      lapic_write(LapicReg::LvtTimer, LVT_PERIODIC | IRQ_START as u32);
+      lapic_write(LapicReg::InitialCount, TICK_COUNT.load(Ordering::Relaxed));
+}
+
+/// Makes the LAPIC timer fire once after `nanos` nanoseconds, instead of
+/// every tick.
+pub fn one_shot_lapic_timer(nanos: u64) {
+      let count =
+            nanos * TICK_COUNT.load(Ordering::Relaxed) as u64 / NANOS_PER_TICK;
+      // One shot mode is the default
+      lapic_write(LapicReg::LvtTimer, IRQ_START as u32);
+      lapic_write(LapicReg::InitialCount, count.min(u32::MAX as u64) as u32);
+}
+
+#[derive(Error, Debug)]
+pub enum ApicError {
+      #[error("No MADT was loaded, using the PICs instead")]
+      NoMadt,
+
+      #[error("The MADT has no I/O APICs, using the PICs instead")]
+      NoIoApic,
+
+      #[error(transparent)]
+      Map(#[from] MapError),
+
+      #[error("The LAPIC timer never ticked, using the PIT instead")]
+      Calibration,
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that [`redirection_entry`] puts each field in the right place.
+      #[test_case]
+      fn redirection_entry_fields() {
+            assert_eq!(redirection_entry(0x21, 0, false, false), 0x21);
+            assert_eq!(
+                  redirection_entry(0x22, 3, true, true),
+                  0x0300_0000_0000_A022
+            );
+      }
+}
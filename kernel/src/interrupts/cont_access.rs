@@ -36,6 +36,12 @@ use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 #[unsafe(export_name = "int_handler_count")]
 static INTERRUPT_DEPTH: AtomicU32 = AtomicU32::new(0);
 
+/// Marks the current interrupt handler as finished, for handlers which never
+/// return to where the interrupt occurred.
+pub fn leave_handler() {
+      INTERRUPT_DEPTH.fetch_sub(1, Ordering::Relaxed);
+}
+
 /// Incremented whenever [`ContAccess::check_access`] would fail.
 #[cfg(test)]
 static CONT_ACCESS_PANICS: AtomicU8 = AtomicU8::new(0);
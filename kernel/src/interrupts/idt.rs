@@ -23,13 +23,20 @@
     Contained within the interrupts module
 */
 
+use core::arch::x86_64::__cpuid;
 use core::arch::{asm, naked_asm};
+use core::ffi::CStr;
+use core::sync::atomic::{AtomicU64, Ordering};
 
 use libutil::TableDescriptor;
 
-use super::{IRQ_START, Idt, IntStackFrame};
+use super::{
+      IRQ_START, Idt, IntStackFrame, apic, cont_access, stats, watchdog,
+};
+use crate::memory::{paging, stacks};
+use crate::msr::{self, Msr};
 use crate::vga::buffers;
-use crate::{PANIC, gdt};
+use crate::{PANIC, gdt, user};
 
 type Handler = u64;
 
@@ -46,7 +53,17 @@ macro_rules! savestate {
             push r9
             push r10
              push r11
-            lock inc dword ptr int_handler_count" // prevent cont access
+            mov eax, 1
+            lock xadd dword ptr int_handler_count, eax
+            inc eax
+            mov ecx, eax
+            mov eax, dword ptr int_max_depth
+            8:
+            cmp ecx, eax
+            jbe 9f
+            lock cmpxchg dword ptr int_max_depth, ecx
+            jnz 8b
+            9:" // prevent cont access & record the max depth
       };
 }
 
@@ -88,10 +105,73 @@ macro_rules! cont_wrapper {
     }};
 }
 
+/// Creates a handler for exceptions with an error code which resumes at the
+/// fixup if the faulting instruction has one in the exception table, or jumps
+/// to `$panic` if not.
+macro_rules! fixup_wrapper {
+    ($panic: expr) => {{
+        static PANIC_HANDLER: AtomicU64 = AtomicU64::new(0);
+        PANIC_HANDLER.store($panic, Ordering::Relaxed);
+
+        #[unsafe(naked)]
+        extern "C" fn wrapper() -> ! {
+            naked_asm!(
+                savestate!(), // save state for search_fixups
+                "mov rdi, [rsp + 10*8]", // ip, above the regs & errcode
+                "call search_fixups", // in memory/extable.rs
+                "test rax, rax",
+                "jz 2f",
+                "mov [rsp + 10*8], rax", // resume at the fixup instead
+                restore_state!(),
+                "add rsp, 8", // remove the error code
+                "iretq",
+
+                "2:",
+                restore_state!(),
+                "jmp [rip + {panic}]", // as if the CPU jumped there itself
+                panic = sym PANIC_HANDLER,
+            )
+        }
+
+        wrapper as *const () as Handler
+    }};
+}
+
+/// Creates a handler for IRQ line `$line` which runs the handlers registered
+/// on it with [`dispatch_irq`](super::irq), which also sends EOI.
+macro_rules! irq_stub {
+    ($line: literal) => {{
+        #[unsafe(naked)]
+        extern "C" fn stub() -> ! {
+            naked_asm!(
+                savestate!(), // save state for dispatch_irq
+                concat!("mov rdi, ", stringify!($line)), // line as first arg
+                "call dispatch_irq", // in interrupts/irq.rs
+                restore_state!(),
+                "iretq"
+            )
+        }
+
+        stub as *const () as Handler
+    }};
+}
+
+/// Points the vector of each IRQ line `$line` in `$idt` to it's [`irq_stub!`].
+macro_rules! set_irq_stubs {
+    ($idt: expr, $($line: literal),+) => {
+        $($idt.set_handler(IRQ_START + $line, None, irq_stub!($line));)+
+    };
+}
+
 /// Prints the error passed by the wrapper.
 #[unsafe(no_mangle)]
 #[cfg_attr(test, allow(unused_variables))]
 extern "sysv64" fn cont(frame: IntStackFrame) {
+      if frame.is_user() {
+            cont_access::leave_handler();
+            user::kill(c"INVALID OPCODE OR BREAKPOINT", &frame, || ());
+      }
+
       #[unsafe(export_name = "cont_errcode")]
       static mut ERR_CODE: ErrCode = ErrCode::Invalid;
 
@@ -125,30 +205,58 @@ impl Idt {
 
             idt.set_handler(0, None, PANIC!(exception noerror c"DIVIDE ERROR"));
             idt.set_handler(1, None, PANIC!(exception noerror c"DEBUG"));
-            idt.set_handler(2, None, PANIC!(exception noerror c"NMI")); // TODO: ignore or make cont_wrapper?
+            idt.set_handler(2, Some(gdt::NMI_IST), watchdog::nmi_handler as *const () as Handler);
             idt.set_handler(3, None, cont_wrapper!(3, 0));
-            idt.set_handler(5, None, PANIC!(exception noerror c"OVERFLOW")); // should NEVER happen
+            idt.set_handler(4, None, PANIC!(exception noerror c"OVERFLOW")); // should NEVER happen
+            idt.set_handler(5, None, PANIC!(exception noerror c"BOUND RANGE EXCEEDED")); // so should this
             idt.set_handler(6, None, cont_wrapper!(6, 2));
             idt.set_handler(7, None, PANIC!(exception noerror c"DEVICE NOT AVAILABLE"));
-            idt.set_handler(8, Some(1), double_fault_handler as *const () as Handler);
-            idt.set_handler(13, None, PANIC!(exception c"GP FAULT", gp_errcode));
-            idt.set_handler(14, Some(1), PANIC!(exception c"PAGE FAULT", pf_errcode));
+            idt.set_handler(8, Some(gdt::DF_IST), double_fault_handler as *const () as Handler);
+            idt.set_handler(9, None, PANIC!(exception noerror c"COPROCESSOR SEGMENT OVERRUN"));
+            idt.set_handler(10, None, PANIC!(exception c"INVALID TSS", selector_errcode));
+            idt.set_handler(11, None, PANIC!(exception c"SEGMENT NOT PRESENT", selector_errcode));
+            idt.set_handler(12, None, PANIC!(exception c"STACK SEGMENT FAULT", selector_errcode));
+            idt.set_handler(13, None, fixup_wrapper!(PANIC!(exception c"GP FAULT", selector_errcode)));
+            idt.set_handler(14, Some(gdt::PF_IST), fixup_wrapper!(PANIC!(exception pf_cause(), pf_errcode)));
+            idt.set_handler(16, None, PANIC!(exception noerror c"X87 FLOATING POINT", x87_info));
+            idt.set_handler(17, None, PANIC!(exception c"ALIGNMENT CHECK", plain_errcode));
+            idt.set_handler(18, Some(gdt::MC_IST), PANIC!(exception noerror c"MACHINE CHECK", mca_info));
+            idt.set_handler(19, None, PANIC!(exception noerror c"SIMD FLOATING POINT", simd_info));
+            idt.set_handler(20, None, PANIC!(exception noerror c"VIRTUALIZATION"));
+            idt.set_handler(21, None, PANIC!(exception c"CONTROL PROTECTION", cp_errcode));
+            idt.set_handler(28, None, PANIC!(exception noerror c"HYPERVISOR INJECTION"));
+            idt.set_handler(29, None, PANIC!(exception c"VMM COMMUNICATION", plain_errcode));
+            idt.set_handler(30, None, PANIC!(exception c"SECURITY", plain_errcode));
+
+            // Reserved vectors, which shouldn't ever be raised
+            let reserved = PANIC!(exception noerror c"RESERVED EXCEPTION");
+            for vector in [15, 22, 23, 24, 25, 26, 27, 31] {
+                  idt.set_handler(vector, None, reserved);
+            }
             idt.set_handler(IRQ_START + 0, None, timer_handler as *const () as Handler);
-            idt.set_handler(IRQ_START + 1, None, kbd_wrapper as *const () as Handler);
-            idt.set_handler(IRQ_START + 6, None, floppy_handler as *const () as Handler);
-            idt.set_handler(IRQ_START + 7, None, dummy_handler as *const () as Handler);
-            idt.set_handler(IRQ_START + 8, None, rtc_handler as *const () as Handler);
-            idt.set_handler(IRQ_START + 15, None, dummy_handler as *const () as Handler);
+            set_irq_stubs!(idt, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15);
+            idt.set_handler(apic::SPURIOUS_VECTOR, None, dummy_handler as *const () as Handler);
 
             idt
       }
 
       /// Sets the table's entry with id `entry_id`
+      /// to a stub which counts it, then jumps to `handler`.
       fn set_handler(
             &mut self, entry_id: usize, ist: Option<u8>, handler: Handler,
       ) {
+            let stub = stats::counted(entry_id, handler);
             self.0[entry_id] =
-                  InterruptDescriptor::new(handler, ist.unwrap_or_default())
+                  InterruptDescriptor::new(stub, ist.unwrap_or_default())
+      }
+
+      /// Returns the handler the entry with id `entry_id` jumps to.
+      #[cfg(test)]
+      fn handler(&self, entry_id: usize) -> Handler {
+            match self.0[entry_id].ptr() {
+                  0 => 0,
+                  _ => stats::target(entry_id),
+            }
       }
 
       /// Loads the table into the `IDTR` register.
@@ -223,8 +331,8 @@ impl InterruptDescriptor {
       }
 }
 
-/// Immediately returns as a really terrible way of handling spurious IRQs.
-/// Since IRQs 7 & 15 aren't used by sunflower though, it's not that bad.
+/// Immediately returns, used for spurious LAPIC interrupts which don't need an
+/// EOI.
 #[inline(never)]
 extern "x86-interrupt" fn dummy_handler(_frame: IntStackFrame) {}
 
@@ -233,6 +341,23 @@ fn bit_set(code: u64, bit: u64) -> bool {
       code == code | 1 << bit
 }
 
+/// Returns the address which caused the last page fault.
+fn cr2() -> u64 {
+      let cr2: u64;
+      // Safety: Just reading from a register
+      unsafe { asm!("mov {}, cr2", out(reg) cr2) };
+      cr2
+}
+
+/// Returns the cause of the current page fault, which is a stack overflow if
+/// the faulting address is inside of a stack's guard page.
+fn pf_cause() -> &'static CStr {
+      match stacks::overflowed_stack(cr2()) {
+            Some(_) => c"STACK OVERFLOW",
+            None => c"PAGE FAULT",
+      }
+}
+
 /// Prints out page fault info based on `errcode`.
 fn pf_errcode(errcode: u64) {
       let rw = if bit_set(errcode, 1) { "Wrote" } else { "Read" };
@@ -242,15 +367,22 @@ fn pf_errcode(errcode: u64) {
             "Non-present page"
       };
 
-      let cr2: usize;
-      // Safety: Just reading from a register
-      unsafe { asm!("mov {}, cr2", out(reg) cr2) };
-      println!("Errcode: {rw} {cause} ({errcode:b})\nCR2: 0x{cr2:x}")
+      let cr2 = cr2();
+      if let Some(stack) = stacks::overflowed_stack(cr2) {
+            println!("Stack overflow in {stack}");
+      }
+      println!("Errcode: {rw} {cause} ({errcode:b})\nCR2: 0x{cr2:x}");
+
+      match paging::walk(cr2) {
+            Ok(walk) => println!("{walk}"),
+            Err(e) => println!("Walk failed: {e}"),
+      }
 }
 
-/// Prints out general protection fault info based on `errcode`.
+/// Prints out selector error code info based on `errcode`, used by invalid
+/// TSS, segment not present, stack segment and general protection faults.
 #[rustfmt::skip]
-fn gp_errcode(errcode: u64) {
+fn selector_errcode(errcode: u64) {
     let ext = if bit_set(errcode, 0) {
         "External"
     } else {
@@ -267,6 +399,157 @@ fn gp_errcode(errcode: u64) {
     println!("Errcode: {ext}{null} in {gate} ({errcode:b})\nIndex: {idx}")
 }
 
+/// Prints out `errcode` for exceptions which don't need it decoded.
+fn plain_errcode(errcode: u64) {
+      println!("Errcode: 0x{errcode:x}")
+}
+
+/// Prints out control protection fault info based on `errcode`.
+fn cp_errcode(errcode: u64) {
+      let cause = match errcode & 0x7FFF {
+            1 => "NEAR-RET",
+            2 => "FAR-RET/IRET",
+            3 => "ENDBRANCH",
+            4 => "RSTORSSP",
+            5 => "SETSSBSY",
+            _ => "Unknown",
+      };
+      let encl = if bit_set(errcode, 15) {
+            " in an enclave"
+      } else {
+            ""
+      };
+      println!("Errcode: {cause}{encl} ({errcode:b})")
+}
+
+/// The names of the floating point exception flags, which are the same in
+/// the x87 status word and MXCSR.
+const FP_FLAGS: [&str; 6] = [
+      "INVALID", "DENORMAL", "DIVIDE BY ZERO", "OVERFLOW", "UNDERFLOW",
+      "PRECISION",
+];
+
+/// Prints which floating point exception flags are set in `flags`.
+fn print_fp_flags(name: &str, flags: u32) {
+      print!("{name}: 0x{flags:x} -");
+      FP_FLAGS
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| bit_set(flags as u64, *bit as u64))
+            .for_each(|(_, flag)| print!(" {flag}"));
+      println!()
+}
+
+/// Prints out the x87 FPU's status word.
+fn x87_info() {
+      let status: u16;
+      // Safety: Just storing the status word
+      unsafe { asm!("fnstsw ax", out("ax") status, options(nostack)) };
+      print_fp_flags("FPU status", status as u32)
+}
+
+/// Prints out the SSE control & status register.
+fn simd_info() {
+      let mut mxcsr: u32 = 0;
+      // Safety: SIMD exceptions are only raised when SSE is enabled
+      unsafe { asm!("stmxcsr [{}]", in(reg) &mut mxcsr, options(nostack)) };
+      print_fp_flags("MXCSR", mxcsr)
+}
+
+/// Prints out the machine check status and each MCA bank holding an error.
+fn mca_info() {
+      /// The CPUID leaf 1 EDX bit set if the MCA MSRs exist.
+      const CPUID_MCA: u32 = 1 << 14;
+
+      /// The first MCA bank's status MSR, with each bank 4 MSRs apart.
+      const MC0_STATUS: u32 = 0x401;
+
+      /// The bank holds an error.
+      const VAL: u64 = 1 << 63;
+      /// Another error happened before the last one was cleared.
+      const OVER: u64 = 1 << 62;
+      /// The error wasn't corrected.
+      const UC: u64 = 1 << 61;
+      /// The MISC MSR is valid.
+      const MISCV: u64 = 1 << 59;
+      /// The ADDR MSR is valid.
+      const ADDRV: u64 = 1 << 58;
+      /// The processor context is corrupt.
+      const PCC: u64 = 1 << 57;
+
+      // Safety: Leaf 1 always exists on x86_64
+      let edx = unsafe { __cpuid(1) }.edx;
+      if edx & CPUID_MCA == 0 {
+            return println!(
+                  "MCA isn't supported, so there's no banks to read"
+            );
+      }
+
+      // Safety: Just checked that MCA is supported above
+      let (cap, status) =
+            unsafe { (msr::read(Msr::McgCap), msr::read(Msr::McgStatus)) };
+      println!(
+            "MCG_STATUS: 0x{status:x} - RIPV {} EIPV {} MCIP {}",
+            bit_set(status, 0),
+            bit_set(status, 1),
+            bit_set(status, 2)
+      );
+
+      for bank in 0..(cap & 0xFF) as u32 {
+            let msr = MC0_STATUS + bank * 4;
+            // Safety: MCG_CAP says that the bank exists
+            let status = unsafe { msr::read_raw(msr) };
+            if status & VAL == 0 {
+                  continue;
+            }
+
+            let code = status as u16;
+            print!("Bank {bank}: 0x{status:x} - {}", mca_error(code));
+            for (flag, name) in [(OVER, "OVER"), (UC, "UC"), (PCC, "PCC")] {
+                  if status & flag != 0 {
+                        print!(" {name}")
+                  }
+            }
+            println!();
+
+            // Safety: The status says that these MSRs are valid
+            if status & ADDRV != 0 {
+                  println!("      ADDR: 0x{:x}", unsafe {
+                        msr::read_raw(msr + 1)
+                  });
+            }
+            if status & MISCV != 0 {
+                  println!("      MISC: 0x{:x}", unsafe {
+                        msr::read_raw(msr + 2)
+                  });
+            }
+      }
+}
+
+/// Returns the class of the MCA error with error code `code`.
+fn mca_error(code: u16) -> &'static str {
+      /// The filter bit, which doesn't change the error's class.
+      const FILTER: u16 = 1 << 12;
+
+      match code & !FILTER {
+            0x0000 => "No error",
+            0x0001 => "Unclassified error",
+            0x0002 => "Microcode ROM parity error",
+            0x0003 => "External error",
+            0x0004 => "FRC error",
+            0x0005 => "Internal parity error",
+            0x0006 => "SMM handler code access violation",
+            0x0400 => "Internal timer error",
+            0x0401..=0x07FF => "Internal unclassified error",
+            c if c & 0xFFFC == 0x000C => "Generic cache hierarchy error",
+            c if c & 0xFFF0 == 0x0010 => "TLB error",
+            c if c & 0xFF80 == 0x0080 => "Memory controller error",
+            c if c & 0xFF00 == 0x0100 => "Cache hierarchy error",
+            c if c & 0x0800 != 0 => "Bus or interconnect error",
+            _ => "Unknown error",
+      }
+}
+
 /// Ran when a double fault occurs.
 #[unsafe(naked)]
 #[unsafe(no_mangle)]
@@ -304,78 +587,21 @@ extern "C" fn print_df_info(frame: IntStackFrame) {
 #[unsafe(naked)]
 extern "C" fn timer_handler() -> ! {
       naked_asm!(
-            savestate!(), // save for decrease_motor_time & eoi
-            "lock inc qword ptr [TIME]",
-            "call dec_floppy_motor_time", // in floppy/motor.rs
-            "mov rdi, 0",                 // timer IRQ as first arg
+            savestate!(),      // save for the calls & switching threads
+            "call tick",       // in time.rs
+            "call run_timers", // in time/timer.rs
+            "call wake_timer", // in task/wake.rs
+            "mov rdi, 0",
             "call eoi",
+            // Other threads aren't in this handler, so don't count it
+            "lock dec dword ptr int_handler_count",
+            "call preempt", // in thread/sched.rs
+            "lock inc dword ptr int_handler_count",
             restore_state!(),
             "iretq",
       );
 }
 
-/// Ran when the PS/2 keyboard generates an interrupt.
-#[unsafe(naked)]
-extern "C" fn kbd_wrapper() -> ! {
-      naked_asm!(
-            savestate!(),       // save state for kbd_handler & eoi
-            "call kbd_handler", // in interrupts/keyboard.rs, for now...
-            "mov rdi, 1",       // key pressed IRQ as first arg
-            "call eoi",         // send eoi command
-            restore_state!(),
-            "iretq",
-      );
-}
-
-/// Ran when the floppy IRQ occurs.
-#[unsafe(naked)]
-extern "C" fn floppy_handler() -> ! {
-      naked_asm!(
-            // TODO: just mask this?
-            savestate!(), // save state for eoi
-            "mov rdi, 6", // floppy IRQ as first argument
-            "call eoi",
-            restore_state!(),
-            "iretq",
-      );
-}
-
-/// Ran when the RTC generates an interrupt
-#[unsafe(naked)]
-#[rustfmt::skip]
-extern "C" fn rtc_handler() -> ! {
-      #[unsafe(no_mangle)]
-      static mut RTC_UPDATE_ENDED: u8 = 0;
-
-      naked_asm!(
-            "push dx", // backup regs
-            "push ax",
-            savestate!(),
-            "cmp byte ptr [RTC_UPDATE_ENDED], 1", // has the update ended?
-            "je rtc_ret",                         // if yes we're already done!
-
-            "mov dx, 0x70", // dx = CMOS index register
-            "mov al, 0x8C", // al = select register C
-            "out dx, al",   // register C selected!
-            "mov dx, 0x71", // dx = CMOS data register
-            "in al, dx",    // read selected register please!
-            "mov ah, al",
-            "or ah, 16",    // set bit 4
-            "cmp al, ah",   // is bit 4 of reg C set?
-            "jne rtc_ret",  // if not we'll have to try again later...
-            "mov byte ptr [RTC_UPDATE_ENDED], 1",
-            "call sync_time_to_rtc", // in time.rs
-
-            "rtc_ret:",
-            "mov rdi, 8", // RTC IRQ as first arg
-            "call eoi",
-            restore_state!(),
-            "pop ax", // restore regs
-            "pop dx",
-            "iretq"
-      );
-}
-
 #[cfg(test)]
 mod tests {
       use super::*;
@@ -385,16 +611,43 @@ mod tests {
     #[test_case]
     #[rustfmt::skip]
     fn descriptors_point_to_handlers() {
-        let idt = IDT.read().unwrap().0;
-        assert_eq!(idt[8].ptr(),              double_fault_handler as *const () as Handler);
-        assert_eq!(idt[IRQ_START + 0].ptr(),  timer_handler   as *const () as Handler);
-        assert_eq!(idt[IRQ_START + 1].ptr(),  kbd_wrapper     as *const () as Handler);
-        assert_eq!(idt[IRQ_START + 6].ptr(),  floppy_handler  as *const () as Handler);
-        assert_eq!(idt[IRQ_START + 7].ptr(),  dummy_handler   as *const () as Handler);
-        assert_eq!(idt[IRQ_START + 8].ptr(),  rtc_handler     as *const () as Handler);
-        assert_eq!(idt[IRQ_START + 15].ptr(), dummy_handler   as *const () as Handler);
+        let idt = IDT.read().unwrap();
+        assert_eq!(idt.handler(8),             double_fault_handler as *const () as Handler);
+        assert_eq!(idt.handler(IRQ_START + 0), timer_handler   as *const () as Handler);
+        assert_eq!(idt.handler(0xFF),          dummy_handler   as *const () as Handler);
     }
 
+      /// Tests that every exception vector has a handler, so none of them
+      /// can triple fault.
+      #[test_case]
+      fn every_exception_handled() {
+            let idt = IDT.read().unwrap();
+            assert!((0..IRQ_START).all(|vector| idt.handler(vector) != 0));
+      }
+
+      /// Tests that [`mca_error`] decodes both simple and compound error codes.
+      #[test_case]
+      fn mca_errors_decoded() {
+            assert_eq!(mca_error(0x0000), "No error");
+            assert_eq!(mca_error(0x0405), "Internal unclassified error");
+            assert_eq!(mca_error(0x000F), "Generic cache hierarchy error");
+            assert_eq!(mca_error(0x1014), "TLB error"); // filtered
+            assert_eq!(mca_error(0x0092), "Memory controller error");
+            assert_eq!(mca_error(0x0135), "Cache hierarchy error");
+            assert_eq!(mca_error(0x0E0B), "Bus or interconnect error");
+      }
+
+      /// Tests that every IRQ line other than the timer has it's own stub.
+      #[test_case]
+      fn irq_lines_have_stubs() {
+            let idt = IDT.read().unwrap();
+            let stubs = (IRQ_START + 1..IRQ_START + 16).map(|v| idt.handler(v));
+            for (i, stub) in stubs.clone().enumerate() {
+                  assert_ne!(stub, 0);
+                  assert!(stubs.clone().skip(i + 1).all(|s| s != stub));
+            }
+      }
+
       /// Tests that [`cont_wrapper!`] handlers actually continue
       #[test_case]
       fn cont_handlers_continue() {
@@ -0,0 +1,205 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/interrupts/irq.rs
+
+    Lets drivers register handlers for IRQ lines.
+    Contained within the interrupts module
+*/
+
+use core::ptr;
+
+use libutil::IrqSafeMutex;
+use thiserror::Error;
+
+use super::{apic, pic, stats};
+
+/// The number of ISA IRQ lines.
+pub const IRQ_LINES: usize = 16;
+
+/// The most handlers which can share a single IRQ line.
+pub const MAX_SHARED: usize = 4;
+
+/// The lines which can't be registered: the timer, which has it's own
+/// handler, and the secondary PIC's cascade.
+const RESERVED: [u8; 2] = [0, 2];
+
+/// A function ran every time it's IRQ line fires, with interrupts cleared.
+pub type IrqHandler = fn();
+
+/// The handlers registered on each line.
+static HANDLERS: IrqSafeMutex<[[Option<IrqHandler>; MAX_SHARED]; IRQ_LINES]> =
+      IrqSafeMutex::new([[None; MAX_SHARED]; IRQ_LINES]);
+
+/// Runs `handler` every time IRQ line `line` fires, after any handlers which
+/// were already registered on it.
+///
+/// Unmasks the line if it's the first handler on it.
+pub fn register_irq(line: u8, handler: IrqHandler) -> Result<(), IrqError> {
+      check_line(line)?;
+      let mut handlers = HANDLERS.lock();
+      let slots = &mut handlers[line as usize];
+
+      let first = slots.iter().all(Option::is_none);
+      let slot = slots
+            .iter_mut()
+            .find(|h| h.is_none())
+            .ok_or(IrqError::Full(line))?;
+      *slot = Some(handler);
+
+      if first {
+            super::set_irq_masked(line, false);
+      }
+      Ok(())
+}
+
+/// Stops running `handler` when IRQ line `line` fires.
+///
+/// Masks the line if it was the last handler on it.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn unregister_irq(line: u8, handler: IrqHandler) -> Result<(), IrqError> {
+      check_line(line)?;
+      let mut handlers = HANDLERS.lock();
+      let slots = &mut handlers[line as usize];
+
+      let slot = slots
+            .iter_mut()
+            .find(|h| h.is_some_and(|h| ptr::fn_addr_eq(h, handler)))
+            .ok_or(IrqError::NotRegistered(line))?;
+      *slot = None;
+
+      if slots.iter().all(Option::is_none) {
+            super::set_irq_masked(line, true);
+      }
+      Ok(())
+}
+
+/// Returns whether IRQ line `line` has any handlers registered on it.
+pub fn registered(line: u8) -> bool {
+      HANDLERS
+            .lock()
+            .get(line as usize)
+            .is_some_and(|slots| slots.iter().any(Option::is_some))
+}
+
+/// Returns an error if drivers can't register handlers on `line`.
+fn check_line(line: u8) -> Result<(), IrqError> {
+      if line as usize >= IRQ_LINES {
+            Err(IrqError::BadLine(line))
+      } else if RESERVED.contains(&line) {
+            Err(IrqError::Reserved(line))
+      } else {
+            Ok(())
+      }
+}
+
+/// Runs every handler registered on IRQ line `line`, then sends EOI.
+///
+/// Called by the IRQ stubs in idt.rs.
+#[unsafe(no_mangle)]
+extern "sysv64" fn dispatch_irq(line: u8) {
+      // Spurious IRQs aren't in service, so the PIC they came from mustn't
+      // get an EOI, although the main PIC still needs one for the cascade
+      if !apic::enabled() && pic::is_spurious(line) {
+            stats::count_spurious(line);
+            if line >= 8 {
+                  pic::eoi(2);
+            }
+            return;
+      }
+
+      // Copy the handlers out so they can (un)register handlers themselves
+      let handlers = HANDLERS.lock()[line as usize];
+      handlers.into_iter().flatten().for_each(|handler| handler());
+      super::eoi(line);
+}
+
+/// An error returned when registering or unregistering IRQ handlers.
+#[derive(Error, Debug, PartialEq)]
+pub enum IrqError {
+      #[error("IRQ line {0} doesn't exist")]
+      BadLine(u8),
+
+      #[error("IRQ line {0} is reserved")]
+      Reserved(u8),
+
+      #[error("IRQ line {0} already has {MAX_SHARED} handlers")]
+      Full(u8),
+
+      #[error("The handler isn't registered on IRQ line {0}")]
+      NotRegistered(u8),
+}
+
+#[cfg(test)]
+mod tests {
+      use core::sync::atomic::{AtomicU8, Ordering};
+
+      use super::*;
+
+      /// An IRQ line nothing uses.
+      const TEST_LINE: u8 = 5;
+
+      /// Tests that every handler registered on a shared line runs when it
+      /// fires, and stops running once unregistered.
+      #[test_case]
+      fn shared_handlers_run() {
+            static RAN: AtomicU8 = AtomicU8::new(0);
+            fn a() {
+                  RAN.fetch_add(1, Ordering::Relaxed);
+            }
+            fn b() {
+                  RAN.fetch_add(10, Ordering::Relaxed);
+            }
+
+            register_irq(TEST_LINE, a).unwrap();
+            register_irq(TEST_LINE, b).unwrap();
+            assert!(registered(TEST_LINE));
+            unsafe { core::arch::asm!("int 0x25") } // IRQ_START + TEST_LINE
+            assert_eq!(RAN.load(Ordering::Relaxed), 11);
+
+            unregister_irq(TEST_LINE, a).unwrap();
+            unsafe { core::arch::asm!("int 0x25") }
+            assert_eq!(RAN.load(Ordering::Relaxed), 21);
+
+            unregister_irq(TEST_LINE, b).unwrap();
+            assert!(!registered(TEST_LINE));
+      }
+
+      /// Tests that reserved, missing and full lines can't be registered.
+      #[test_case]
+      fn bad_lines_rejected() {
+            fn handler() {}
+
+            assert_eq!(register_irq(0, handler), Err(IrqError::Reserved(0)));
+            assert_eq!(register_irq(16, handler), Err(IrqError::BadLine(16)));
+            assert_eq!(
+                  unregister_irq(TEST_LINE, handler),
+                  Err(IrqError::NotRegistered(TEST_LINE))
+            );
+
+            (0..MAX_SHARED)
+                  .for_each(|_| register_irq(TEST_LINE, handler).unwrap());
+            assert_eq!(
+                  register_irq(TEST_LINE, handler),
+                  Err(IrqError::Full(TEST_LINE))
+            );
+            (0..MAX_SHARED)
+                  .for_each(|_| unregister_irq(TEST_LINE, handler).unwrap());
+      }
+}
@@ -27,6 +27,7 @@ use core::fmt::Display;
 use core::hint;
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
 
+use libutil::IrqSafeMutex;
 use pc_keyboard::layouts::Us104Key;
 use pc_keyboard::{
       DecodedKey, HandleControl, KeyCode, KeyEvent, KeyState, Keyboard,
@@ -42,19 +43,19 @@ use crate::sysinfo::SystemInfo;
 use crate::vga::buffers::{self, BUFFER_HEIGHT};
 use crate::vga::cursor::{CursorPos, CursorShift, shift_cursor};
 use crate::vga::{self, print};
-use crate::{PANIC, speaker};
+use crate::{PANIC, speaker, task, time};
 
-/// Circular scancode buffer where each AtomicU8 represents a scancode.
+/// Circular scancode buffer, filled by the keyboard interrupt handler.
 // The genius idea of this buffer was taken from the below video
 // (it's such a good idea I'd feel bad not crediting it):
 // https://www.youtube.com/watch?v=dL0GO9SeBh0
-static KBD_BUF: [AtomicU8; 256] = [const { AtomicU8::new(0) }; 256];
+static KBD_BUF: IrqSafeMutex<RingBuf> = IrqSafeMutex::new(RingBuf::new());
 
-/// Index into the last handled scancode in the keyboard buffer.
-static KBD_RPTR: AtomicU8 = AtomicU8::new(0);
+/// Circular buffer of ASCII characters typed, read by [`next_typed`].
+static TYPED_BUF: IrqSafeMutex<RingBuf> = IrqSafeMutex::new(RingBuf::new());
 
-/// Index into the last added scancode to the keyboard buffer.
-static KBD_WPTR: AtomicU8 = AtomicU8::new(0);
+/// The IRQ line the PS/2 keyboard uses.
+const KBD_IRQ: u8 = 1;
 
 /// The last value read from port 0x60.
 static PREV_RESPONSE: AtomicU8 = AtomicU8::new(0);
@@ -140,6 +141,10 @@ pub unsafe fn init() -> ExitCode<KbdInitError> {
       // Safety: We just initialised it above
       unsafe { startup::KBD_INIT.store(true) }
 
+      if super::register_irq(KBD_IRQ, kbd_irq).is_err() {
+            return ExitCode::Error(KbdInitError::new("Register IRQ"));
+      }
+
       ExitCode::Ok
 }
 
@@ -197,28 +202,22 @@ pub fn wait_for_response(enter_eq_true: bool) -> bool {
       }
 }
 
-/// Adds the last response from the keyboard to the keyboard buffer.
-/// # Safety
-/// Should only be ran inside of the PS/2 keyboard interrupt handler.
-#[unsafe(no_mangle)]
-unsafe fn kbd_handler() {
+/// Adds the last response from the keyboard to the keyboard buffer, then
+/// wakes the keyboard task.
+///
+/// Registered on IRQ line 1 by [`init`].
+fn kbd_irq() {
       if !startup::KBD_INIT.load() {
             return;
       }
 
-      super::cli();
-
       // Safety: It's ok to read port 0x60 inside of an interrupt handler here,
       // as besides from in startup, it's never accessed in the 'main' execution
-      // of code, also the above cli prevents any other int handlers
+      // of code, also IRQ handlers are ran with interrupts cleared
       let scancode = unsafe { ports::readb_nodummy(Port::PS2Data) };
-      let ptr = KBD_WPTR.load(Ordering::Relaxed) as usize;
-
-      KBD_WPTR.fetch_add(1, Ordering::Relaxed);
-      KBD_BUF[ptr].store(scancode, Ordering::Relaxed);
+      KBD_BUF.lock().push(scancode);
       PREV_RESPONSE.store(scancode, Ordering::Relaxed);
-
-      super::sti();
+      task::wake::KEYBOARD.fire();
 }
 
 /// Runs the corresponding action if any syscmds were inputted.
@@ -233,12 +232,15 @@ fn check_syscmd(key: KeyCode, kbd: &Modifiers) {
                   buffers::clear();
                   vga::draw_topbar();
             }
-            KeyCode::F3 => speaker::play_song(),
+            KeyCode::F3 => _ = task::spawn(speaker::play_song()),
             KeyCode::F4 => PANIC!(badbug "Triggered System Command 4 \
                                   by pressing Ctrl+Alt+F4 or SysRq+F4"),
             KeyCode::F5 => super::triple_fault(),
             KeyCode::F6 => buffers::swap(),
             KeyCode::F7 => print_help(),
+            KeyCode::F8 => crate::user::run_program(),
+            KeyCode::F9 => _ = task::spawn(super::stats::monitor()),
+            KeyCode::F10 => _ = task::spawn(time::rtc::prompt_time()),
             _ => (),
       }
 
@@ -273,7 +275,9 @@ fn check_syscmd(key: KeyCode, kbd: &Modifiers) {
          1 - Prints system information   2 - Clears the screen
          3 - Beeps the PC speaker        4 - Triggers a kernel panic
          5 - Restarts the device         6 - Swaps between text buffers
-         7 - Shows this help message"
+         7 - Shows this help message     8 - Runs the floppy's program
+         9 - Toggles the interrupt monitor
+        10 - Sets the hardware clock"
             );
       }
 }
@@ -360,42 +364,115 @@ fn keyboard_input(
                   None
             };
 
-            if let Some(shifted) = shifted &&
+            let typed = if let Some(shifted) = shifted &&
                   shift_held
             {
-                  print!("{shifted}")
+                  shifted
             } else if kbd.capslock ^ shift_held {
-                  print!("{}", key.to_ascii_uppercase())
+                  key.to_ascii_uppercase()
             } else {
-                  print!("{key}")
+                  key
+            };
+
+            print!("{typed}");
+            if typed.is_ascii() {
+                  TYPED_BUF.lock().push(typed as u8);
+            }
+      }
+}
+
+/// Returns the oldest character typed which hasn't been returned yet.
+pub fn next_typed() -> Option<u8> {
+      TYPED_BUF.lock().pop()
+}
+
+/// Drops every character typed which hasn't been returned yet, so programs
+/// only see keys typed after they start.
+pub fn clear_typed() {
+      let mut buf = TYPED_BUF.lock();
+      buf.rptr = buf.wptr;
+}
+
+/// A circular buffer of bytes.
+struct RingBuf {
+      buf:  [u8; 256],
+      /// Index into the next byte to be read.
+      rptr: u8,
+      /// Index into where the next byte is written.
+      wptr: u8,
+}
+
+impl RingBuf {
+      /// Creates a new empty buffer.
+      const fn new() -> Self {
+            RingBuf {
+                  buf:  [0; 256],
+                  rptr: 0,
+                  wptr: 0,
+            }
+      }
+
+      /// Adds `byte` to the buffer, dropping it if the buffer is full.
+      fn push(&mut self, byte: u8) {
+            let next = self.wptr.wrapping_add(1);
+            if next != self.rptr {
+                  self.buf[self.wptr as usize] = byte;
+                  self.wptr = next;
+            }
+      }
+
+      /// Removes the oldest byte from the buffer.
+      fn pop(&mut self) -> Option<u8> {
+            if self.rptr == self.wptr {
+                  return None;
             }
+
+            let byte = self.buf[self.rptr as usize];
+            self.rptr = self.rptr.wrapping_add(1);
+            Some(byte)
       }
 }
 
 /// Polls the keyboard buffer for any new keys pressed.
-pub fn poll_keyboard() {
+///
+/// Returns `false` if the buffer was empty.
+pub fn poll_keyboard() -> bool {
       /// The current state of the keyboard
       static mut KBD: Keyboard<Us104Key, ScancodeSet2> =
             Keyboard::new(ScancodeSet2::new(), Us104Key, HandleControl::Ignore);
 
-      let read_ptr = KBD_RPTR.load(Ordering::Relaxed);
-      let write_ptr = KBD_WPTR.load(Ordering::Relaxed);
-
       // Safety: This is the only time keyboard is mutated
       let kbd = unsafe { &mut *&raw mut KBD };
 
       // Return if we've reached the end of the buffer
-      if read_ptr >= write_ptr {
-            KBD_RPTR.store(write_ptr, Ordering::Relaxed);
-            return kbd.clear();
-      }
-
-      let scancode = KBD_BUF[read_ptr as usize].load(Ordering::Relaxed);
-      KBD_RPTR.fetch_add(1, Ordering::Relaxed);
+      let Some(scancode) = KBD_BUF.lock().pop() else {
+            kbd.clear();
+            return false;
+      };
 
       if let Ok(event) = kbd.add_byte(scancode) &&
             let Some(ref event) = event
       {
             keyboard_input(scancode, event, kbd);
       }
+      true
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that [`RingBuf`] returns bytes in order and drops them once
+      /// full, instead of overwriting unread ones.
+      #[test_case]
+      fn ring_buf_wraps() {
+            let mut ring = RingBuf::new();
+            assert_eq!(ring.pop(), None);
+            (0..=255).for_each(|b| ring.push(b));
+            assert_eq!(ring.pop(), Some(0));
+            ring.push(42);
+            assert!((1..=254).all(|b| ring.pop() == Some(b)));
+            assert_eq!(ring.pop(), Some(42));
+            assert_eq!(ring.pop(), None);
+      }
 }
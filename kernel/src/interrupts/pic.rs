@@ -26,15 +26,14 @@
 use core::convert::Infallible;
 
 use super::IRQ_START;
-use crate::ports::{Port, writeb};
+use crate::ports::{Port, readb, writeb};
 use crate::startup::{self, ExitCode};
 
 /// Offset to the secondary PIC from the first.
 const SECONDARY_OFFSET: u8 = 8;
 
 /// Sends the EOI command to the corresponding PIC.
-#[unsafe(no_mangle)]
-pub extern "sysv64" fn eoi(irq: u8) {
+pub fn eoi(irq: u8) {
       /// End of interrupt command, tells PIC the interrupt's over.
       const EOI_COMMAND: u8 = 0b100000;
 
@@ -55,6 +54,55 @@ pub extern "sysv64" fn eoi(irq: u8) {
       }
 }
 
+/// Masks or unmasks IRQ `irq` in the PIC it's connected to.
+pub fn set_masked(irq: u8, masked: bool) {
+      let (port, bit) = if irq >= SECONDARY_OFFSET {
+            (Port::SecondaryPicData, irq - SECONDARY_OFFSET)
+      } else {
+            (Port::MainPicData, irq)
+      };
+
+      // Safety: Reading and writing the PIC's IMR doesn't do anything else
+      unsafe {
+            let mask = readb(port);
+            let mask = if masked {
+                  mask | 1 << bit
+            } else {
+                  mask & !(1 << bit)
+            };
+            writeb(port, mask)
+      }
+}
+
+/// Returns whether IRQ `irq` is a spurious IRQ, which only IRQs 7 & 15 can
+/// be, found by checking if it's in service.
+pub fn is_spurious(irq: u8) -> bool {
+      /// OCW3 command to read the In-Service Register.
+      const READ_ISR: u8 = 0x0B;
+
+      let port = match irq {
+            7 => Port::MainPicCmd,
+            15 => Port::SecondaryPicCmd,
+            _ => return false,
+      };
+
+      // Safety: Reading the ISR doesn't affect anything
+      let isr = unsafe {
+            writeb(port, READ_ISR);
+            readb(port)
+      };
+      isr & 1 << 7 == 0 // both IRQs are bit 7 in their PIC
+}
+
+/// Masks every IRQ in both PICs.
+pub fn mask_all() {
+      // Safety: Masking IRQs can't break anything
+      unsafe {
+            writeb(Port::MainPicData, 0xFF);
+            writeb(Port::SecondaryPicData, 0xFF);
+      }
+}
+
 /// Initialises the main and secondary PICs.
 ///
 /// # Safety
@@ -84,8 +132,10 @@ pub unsafe fn init() -> ExitCode<Infallible> {
             writeb(Port::MainPicData, MODE_8086); // use 8086 mode
             writeb(Port::SecondaryPicData, MODE_8086);
 
-            writeb(Port::MainPicData, 0); // unmask to allow ints
-            writeb(Port::SecondaryPicData, 0);
+            // only unmask the timer & cascade, the rest get unmasked once
+            // their handlers are registered
+            writeb(Port::MainPicData, !(1 | 1 << FORWARD_IRQ));
+            writeb(Port::SecondaryPicData, 0xFF);
       };
 
       // Safety: Just initialised it above
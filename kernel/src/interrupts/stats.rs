@@ -0,0 +1,236 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/interrupts/stats.rs
+
+    Counts every interrupt raised, and draws them with the interrupt monitor.
+    Contained within the interrupts module
+*/
+
+use core::arch::naked_asm;
+use core::fmt::Display;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use super::IRQ_START;
+use super::apic::SPURIOUS_VECTOR;
+use crate::time::{self, KERNEL_TICKS_HZ};
+use crate::vga::buffers::{self, BUFFER_HEIGHT};
+use crate::vga::cursor::CursorPos;
+use crate::vga::{self};
+
+/// The number of vectors in the IDT.
+const VECTORS: usize = 256;
+
+/// How many bytes apart each stub in [`count_stubs`] is.
+const STUB_SIZE: u64 = 16;
+
+/// The number of times each vector has been raised.
+#[unsafe(export_name = "int_counts")]
+static COUNTS: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+
+/// The handler each vector's stub jumps to after counting it.
+#[unsafe(export_name = "int_targets")]
+static TARGETS: [AtomicU64; VECTORS] = [const { AtomicU64::new(0) }; VECTORS];
+
+/// The deepest `int_handler_count` has been, updated by `savestate!`.
+#[unsafe(export_name = "int_max_depth")]
+static MAX_DEPTH: AtomicU32 = AtomicU32::new(0);
+
+/// The number of spurious IRQ 7s and 15s from the PICs, which are dropped
+/// before reaching any handlers.
+static SPURIOUS: [AtomicU64; 2] = [const { AtomicU64::new(0) }; 2];
+
+/// Increased each time the interrupt monitor is started or stopped, it's
+/// running while this is odd.
+static MONITOR_GEN: AtomicU64 = AtomicU64::new(0);
+
+/// A snapshot of every interrupt counter.
+#[derive(Debug, Clone)]
+pub struct IntStats {
+      /// The number of times each vector has been raised.
+      pub counts:    [u64; VECTORS],
+      /// Spurious IRQ 7s and 15s from the PICs.
+      pub spurious:  [u64; 2],
+      /// The most interrupt handlers which have been nested at once.
+      pub max_depth: u32,
+}
+
+impl IntStats {
+      /// Returns the current value of every counter.
+      pub fn now() -> Self {
+            IntStats {
+                  counts:    COUNTS
+                        .each_ref()
+                        .map(|c| c.load(Ordering::Relaxed)),
+                  spurious:  SPURIOUS
+                        .each_ref()
+                        .map(|c| c.load(Ordering::Relaxed)),
+                  max_depth: MAX_DEPTH.load(Ordering::Relaxed),
+            }
+      }
+
+      /// Returns the total number of interrupts raised.
+      pub fn total(&self) -> u64 {
+            self.counts.iter().sum()
+      }
+}
+
+impl Display for IntStats {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(
+                  f,
+                  "{} raised, {} spurious IRQ 7s, {} spurious IRQ 15s, \
+                  max nesting {}",
+                  self.total(),
+                  self.spurious[0],
+                  self.spurious[1],
+                  self.max_depth
+            )
+      }
+}
+
+/// Returns the address of a stub which counts `vector` then jumps to
+/// `handler`, to be placed in the IDT instead of it.
+pub fn counted(vector: usize, handler: u64) -> u64 {
+      TARGETS[vector].store(handler, Ordering::Relaxed);
+      // The stubs start at the first 16 byte boundary in count_stubs
+      let stubs = (count_stubs as *const () as u64).next_multiple_of(STUB_SIZE);
+      stubs + vector as u64 * STUB_SIZE
+}
+
+/// Returns the handler the stub for `vector` jumps to.
+#[cfg(test)]
+pub fn target(vector: usize) -> u64 {
+      TARGETS[vector].load(Ordering::Relaxed)
+}
+
+/// Counts a spurious IRQ from the PICs on line `line`.
+pub fn count_spurious(line: u8) {
+      let idx = (line >= 8) as usize;
+      SPURIOUS[idx].fetch_add(1, Ordering::Relaxed);
+}
+
+/// A stub for every vector, each of which counts it then jumps to it's
+/// handler without touching any registers or the stack.
+#[unsafe(naked)]
+#[rustfmt::skip]
+extern "C" fn count_stubs() -> ! {
+      naked_asm!(
+            ".balign 16",
+            ".set vector, 0",
+            ".rept 256",
+            "lock inc qword ptr [rip + int_counts + 8*vector]",
+            "jmp qword ptr [rip + int_targets + 8*vector]",
+            ".balign 16",
+            ".set vector, vector + 1",
+            ".endr",
+      )
+}
+
+/// Returns a short name for `vector`, or `None` if it's an IRQ or unused.
+fn vector_name(vector: usize) -> Option<&'static str> {
+      /// The mnemonic of each exception.
+      #[rustfmt::skip]
+      const EXCEPTIONS: [&str; IRQ_START] = [
+            "#DE", "#DB", "NMI", "#BP", "#OF", "#BR", "#UD", "#NM",
+            "#DF", "CSO", "#TS", "#NP", "#SS", "#GP", "#PF", "Rsvd",
+            "#MF", "#AC", "#MC", "#XM", "#VE", "#CP", "Rsvd", "Rsvd",
+            "Rsvd", "Rsvd", "Rsvd", "Rsvd", "#HV", "#VC", "#SX", "Rsvd",
+      ];
+
+      match vector {
+            0..IRQ_START => Some(EXCEPTIONS[vector]),
+            SPURIOUS_VECTOR => Some("Spur"),
+            _ => None,
+      }
+}
+
+/// Draws a live table of every raised vector in the alt buffer every half a
+/// second, until ran again. Ran after inputting syscmd 9.
+pub async fn monitor() {
+      /// The width of each column in the table.
+      const COLUMN_WIDTH: u8 = 26;
+
+      // Swapped back here instead of by the running monitor, so it can't
+      // swap away from a monitor started before it wakes up
+      let generation = MONITOR_GEN.fetch_add(1, Ordering::Relaxed) + 1;
+      buffers::swap();
+      if generation.is_multiple_of(2) {
+            return; // stopped the running monitor
+      }
+
+      while MONITOR_GEN.load(Ordering::Relaxed) == generation {
+            let stats = IntStats::now();
+            buffers::clear();
+            vga::draw_topbar();
+            println!(fg = LightBlue, "\nInterrupt monitor");
+            println!("{stats}\n");
+
+            let raised = stats.counts.iter().enumerate().filter(|c| *c.1 > 0);
+            for (idx, (vector, count)) in raised.enumerate() {
+                  let (row, col) = CursorPos::row_col();
+                  if col == 0 && row >= BUFFER_HEIGHT - 2 {
+                        break;
+                  }
+
+                  match vector_name(vector) {
+                        Some(name) => print!("{vector:>3} {name:<6}"),
+                        None => print!(
+                              "{vector:>3} IRQ {:<2}",
+                              vector - IRQ_START
+                        ),
+                  }
+                  print!(" {count:>14}");
+
+                  if idx % 3 == 2 {
+                        println!();
+                  } else {
+                        CursorPos::set_col((idx as u8 % 3 + 1) * COLUMN_WIDTH);
+                  }
+            }
+
+            CursorPos::set_col(0);
+            CursorPos::set_row(BUFFER_HEIGHT - 1);
+            print!("Updates every 0.5s, use SysCmd 9 to stop");
+            time::sleep(KERNEL_TICKS_HZ / 2).await;
+      }
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that raising a vector counts it, both for exceptions and IRQs.
+      #[test_case]
+      fn raised_vectors_counted() {
+            let before = IntStats::now();
+            unsafe { core::arch::asm!("int3", "int 0x25") } // IRQ_START + 5
+            let after = IntStats::now();
+            assert_eq!(after.counts[3], before.counts[3] + 1);
+            assert_eq!(after.counts[0x25], before.counts[0x25] + 1);
+      }
+
+      /// Tests that each vector's stub is [`STUB_SIZE`] bytes after the last.
+      #[test_case]
+      fn stubs_evenly_spaced() {
+            let first = counted(0x30, target(0x30));
+            let second = counted(0x31, target(0x31));
+            assert_eq!(second - first, STUB_SIZE);
+      }
+}
@@ -0,0 +1,256 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/interrupts/watchdog.rs
+
+    NMI watchdog, which reports the kernel as hung if it stops being petted.
+    Contained within the interrupts module
+*/
+
+use core::arch::x86_64::__cpuid;
+use core::ffi::CStr;
+use core::hint;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use thiserror::Error;
+
+use super::IntStackFrame;
+use super::apic::{self, LapicReg};
+use crate::msr::{self, Msr};
+use crate::panic::kpanic;
+use crate::startup::ExitCode;
+use crate::time::{self, KERNEL_TICKS_HZ};
+use crate::{exit_on_err, user};
+
+/// How many seconds the kernel can go without petting the watchdog before
+/// it's reported as hung, set by `SFK_WATCHDOG_SECS` when building.
+/// Zero disables the watchdog.
+const WINDOW_SECS: u64 = crate::env_as_int!("SFK_WATCHDOG_SECS", u64);
+
+/// How many ticks worth of unhalted cycles pass between each check.
+const CHECK_TICKS: u64 = KERNEL_TICKS_HZ / 10;
+
+/// How many checks can be missed before the kernel's reported as hung.
+const MAX_MISSED: u64 = WINDOW_SECS * KERNEL_TICKS_HZ / CHECK_TICKS;
+
+/// The CPUID leaf describing architectural performance monitoring.
+const PERFMON_LEAF: u32 = 0xA;
+
+/// Counts unhalted core cycles in both user & kernel mode, raising an
+/// interrupt whenever the counter overflows.
+const EVENT_SELECT: u64 = 0x3C | 1 << 16 | 1 << 17 | EVENT_INT | 1 << 22;
+
+/// The bit in [`EVENT_SELECT`] which raises an interrupt on overflow.
+const EVENT_INT: u64 = 1 << 20;
+
+/// Delivers the LAPIC's performance counter interrupt as an NMI.
+const LVT_NMI: u32 = 0b100 << 8;
+
+/// How many cycles pass between each check.
+static PERIOD: AtomicU64 = AtomicU64::new(0);
+
+/// How many checks have passed since the watchdog was last petted.
+static MISSED: AtomicU64 = AtomicU64::new(0);
+
+/// Set while the watchdog's counter is running.
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Starts the watchdog, using the first performance counter to raise an NMI
+/// every [`CHECK_TICKS`] ticks worth of cycles the CPU isn't halted for.
+///
+/// # Safety
+/// Nothing else can use the first performance counter.
+pub unsafe fn init() -> ExitCode<WatchdogError> {
+      if WINDOW_SECS == 0 {
+            return ExitCode::Error(WatchdogError::Disabled);
+      }
+
+      if !apic::enabled() {
+            return ExitCode::Error(WatchdogError::NoApic);
+      }
+
+      // Safety: Leaf 0 always exists on x86_64, and leaf 0xA is checked
+      let perfmon = unsafe {
+            if __cpuid(0).eax < PERFMON_LEAF {
+                  return ExitCode::Error(WatchdogError::NoPerfmon);
+            }
+            __cpuid(PERFMON_LEAF)
+      };
+
+      let version = perfmon.eax & 0xFF;
+      let counters = (perfmon.eax >> 8) & 0xFF;
+      let events = (perfmon.eax >> 24) & 0xFF;
+      // A set bit means the event *isn't* available
+      if version == 0 || counters == 0 || events == 0 || perfmon.ebx & 1 != 0 {
+            return ExitCode::Error(WatchdogError::NoPerfmon);
+      }
+
+      let period = exit_on_err!(calibrate().ok_or(WatchdogError::Calibrate));
+      PERIOD.store(period, Ordering::Relaxed);
+
+      super::without_interrupts(|| {
+            apic::lapic_write(LapicReg::LvtPerf, LVT_NMI);
+            // Safety: The caller ensures nothing else uses the counter
+            unsafe {
+                  reload();
+                  msr::write(Msr::PerfEvtSel0, EVENT_SELECT);
+            }
+            RUNNING.store(true, Ordering::Relaxed);
+      });
+
+      dbg_info!("watchdog: checking every {period} cycles");
+      ExitCode::Ok
+}
+
+/// Lets the watchdog know that the kernel isn't hung.
+pub fn pet() {
+      MISSED.store(0, Ordering::Relaxed);
+}
+
+/// Stops the watchdog, so that it doesn't report panics as hangs.
+pub fn stop() {
+      if RUNNING.swap(false, Ordering::Relaxed) {
+            // Safety: The counter was started in init
+            unsafe { msr::write(Msr::PerfEvtSel0, 0) }
+      }
+}
+
+/// Returns how many cycles the CPU runs in [`CHECK_TICKS`] ticks, or `None`
+/// if the counter didn't count.
+fn calibrate() -> Option<u64> {
+      /// How many ticks to measure the counter over.
+      const TICKS: u64 = 10;
+
+      // Start measuring right after a tick
+      let prev = time::get_time();
+      while time::get_time() == prev {
+            hint::spin_loop()
+      }
+
+      // Safety: init checked that the counter exists
+      unsafe {
+            msr::write(Msr::Pmc0, 0);
+            msr::write(Msr::PerfEvtSel0, EVENT_SELECT & !EVENT_INT);
+      }
+
+      let start = time::get_time();
+      while time::get_time() - start < TICKS {
+            hint::spin_loop()
+      }
+
+      // Safety: Same as above
+      let cycles = unsafe {
+            let cycles = msr::read(Msr::Pmc0);
+            msr::write(Msr::PerfEvtSel0, 0);
+            cycles
+      };
+
+      // Counters can only be written with sign extended 32 bit values
+      let period = (cycles / TICKS * CHECK_TICKS).min(i32::MAX as u64);
+      Some(period).filter(|period| *period != 0)
+}
+
+/// Sets the counter to overflow after another [`PERIOD`] cycles.
+///
+/// # Safety
+/// The counter must exist.
+unsafe fn reload() {
+      let period = PERIOD.load(Ordering::Relaxed) as i64;
+      // Safety: The caller ensures the counter exists
+      unsafe { msr::write(Msr::Pmc0, period.wrapping_neg() as u64) }
+}
+
+/// Returns whether the counter has overflowed since it was last reloaded.
+fn overflowed() -> bool {
+      // Safety: The watchdog's only running if the counter exists
+      let count = unsafe { msr::read(Msr::Pmc0) };
+      count < PERIOD.load(Ordering::Relaxed)
+}
+
+/// Checks that the watchdog's been petted if it raised the NMI, reporting the
+/// kernel as hung if it hasn't in [`WINDOW_SECS`], or panics for other NMIs.
+pub extern "x86-interrupt" fn nmi_handler(frame: IntStackFrame) {
+      if RUNNING.load(Ordering::Relaxed) && overflowed() {
+            // Safety: The counter exists since the watchdog's running
+            unsafe { reload() }
+            // The LVT entry is masked whenever it raises an NMI
+            apic::lapic_write(LapicReg::LvtPerf, LVT_NMI);
+
+            if MISSED.fetch_add(1, Ordering::Relaxed) + 1 < MAX_MISSED {
+                  return;
+            }
+            nmi_panic(c"KERNEL HUNG", &frame, true)
+      }
+
+      if frame.is_user() {
+            user::kill(c"NMI", &frame, || ())
+      }
+      nmi_panic(c"NMI", &frame, false)
+}
+
+/// Panics with `cause` at where the NMI was raised, printing a hang report if
+/// `hung` is set.
+fn nmi_panic(cause: &'static CStr, frame: &IntStackFrame, hung: bool) -> ! {
+      static IP: AtomicU64 = AtomicU64::new(0);
+      static HUNG: AtomicBool = AtomicBool::new(false);
+
+      extern "sysv64" fn info() {
+            let ip = IP.load(Ordering::Relaxed);
+            println!("Instruction: 0x{ip:x}");
+            if HUNG.load(Ordering::Relaxed) {
+                  println!(
+                        "The watchdog wasn't petted for {WINDOW_SECS}s, so \
+                        the kernel's probably stuck near the above instruction"
+                  );
+            }
+      }
+
+      IP.store(frame.ip, Ordering::Relaxed);
+      HUNG.store(hung, Ordering::Relaxed);
+      // Safety: Called from the NMI handler, the same as PANIC's handlers
+      unsafe { kpanic(cause.as_ptr(), frame.sp, info) }
+}
+
+#[derive(Error, Debug)]
+pub enum WatchdogError {
+      #[error("The watchdog was disabled when building")]
+      Disabled,
+
+      #[error("The watchdog needs the local APIC")]
+      NoApic,
+
+      #[error("The CPU doesn't have a cycle counting performance counter")]
+      NoPerfmon,
+
+      #[error("The performance counter didn't count any cycles")]
+      Calibrate,
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that petting the watchdog resets the missed checks.
+      #[test_case]
+      fn petting_resets_missed() {
+            MISSED.store(3, Ordering::Relaxed);
+            pet();
+            assert_eq!(MISSED.load(Ordering::Relaxed), 0);
+      }
+}
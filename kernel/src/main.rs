@@ -28,17 +28,24 @@
 #![reexport_test_harness_main = "tests"]
 #![forbid(static_mut_refs)] // clippy::undocumented_unsafe_blocks)]
 #![feature(
-      abi_x86_interrupt, sync_unsafe_cell, yeet_expr, custom_test_frameworks
+      abi_x86_interrupt, sync_unsafe_cell, yeet_expr, custom_test_frameworks,
+      alloc_error_handler
 )]
 #![allow(
       clippy::unusual_byte_groupings, clippy::deref_addrof, clippy::identity_op
 )]
 
+extern crate alloc;
+
 #[macro_use]
 mod vga;
+mod acpi;
+mod dma;
 mod floppy;
 mod gdt;
 mod interrupts;
+mod memory;
+mod msr;
 #[macro_use]
 mod panic;
 mod ports;
@@ -47,8 +54,13 @@ mod speaker;
 mod startup;
 #[macro_use]
 mod sysinfo;
+mod task;
 #[cfg(test)] mod tests;
+mod thread;
 mod time;
+mod user;
+
+use bootloader::BootInfo;
 
 // Warn anyone just running `cargo build` to just use seeder
 #[cfg(any(debug_assertions, not(feature = "bootimage")))]
@@ -57,28 +69,43 @@ compile_error!(
       in the main sunflower directory for help"
 );
 
-/// The kernel entry point.
+/// The kernel entry point, with `boot_info` passed by the bootloader.
 /// # Safety
 /// Please don't run the kernel twice.
 #[unsafe(export_name = "_start")]
 #[rustfmt::skip]
-pub unsafe extern "C" fn kmain() -> ! {
+pub unsafe extern "C" fn kmain(boot_info: &'static BootInfo) -> ! {
+      // Ignore possible error as frames::init checks this later
+      _ = memory::BOOT_INFO.init(boot_info);
+
       // Safety: Considering that this is the kernel entry point,
       // I'm pretty sure these startup tasks are only being ran once
       unsafe {
             startup::run("Connected VGA", vga::init);
             startup::run("Loaded IDT", interrupts::load_idt);
+            startup::run("Built frame allocator", memory::frames::init);
+            startup::run("Allocated DMA buffer", dma::init);
+            startup::run("Initialised heap", memory::heap::init);
+            startup::run("Found kernel stack", memory::stacks::init);
             startup::run("Prepared TSS load", gdt::setup_tss);
             startup::run("Loaded GDT", gdt::load_gdt);
             startup::run("Finished TSS load", gdt::load_tss);
+            startup::run("Started scheduler", thread::init);
+            startup::run("Enabled syscalls", user::init_syscalls);
             startup::run("Initialised PIC", interrupts::init_pic);
             startup::run("Prepared RTC sync", time::setup_rtc_int);
             startup::run("Set PIT frequency", time::set_timer_interval);
+            startup::run("Calibrated TSC", time::clock::init);
+            startup::run("Parsed ACPI tables", acpi::init);
+            startup::run("Initialised APIC", interrupts::init_apic);
+            startup::run("Started HPET", time::hpet::init);
+            startup::run("Started NMI watchdog", interrupts::init_watchdog);
             startup::run("Initialised keyboard", interrupts::init_kbd);
             startup::run("Checked CPUID", sysinfo::check_cpuid);
             startup::run("Finished RTC sync", time::wait_for_rtc_sync);
             startup::run("Initialised floppy drive", floppy::init_wrapper);
             startup::run("Initialised floppyfs",floppy::floppyfs::init_floppyfs);
+            startup::run("Spawned kernel tasks", task::spawn_tasks);
       }
 
       #[cfg(test)]
@@ -88,7 +115,7 @@ pub unsafe extern "C" fn kmain() -> ! {
       println!(fg = Green, "\nAll startup tasks completed! \u{1}\n");
       vga::cursor::update_visual_pos();
       speaker::play_chime();
-      interrupts::kbd_poll_loop()
+      task::run()
 }
 
 /// Hangs forever, never returning.
@@ -0,0 +1,56 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory.rs
+
+    The memory module handles physical and virtual memory.
+    This file is responsible for storing the info passed by the bootloader.
+
+    Contains 6 submodules:
+    * extable.rs - Lets faulting kernel accesses resume at a fixup
+    * frames.rs - Physical frame allocator built from the memory map
+    * heap.rs - The kernel heap and global allocator
+    * paging.rs - Walks and edits the active page tables
+    * slab.rs - Per-type object caches
+    * stacks.rs - Kernel stacks with guard pages
+*/
+
+use bootloader::BootInfo;
+use libutil::{InitError, InitLater};
+
+pub mod extable;
+pub mod frames;
+pub mod heap;
+pub mod paging;
+pub mod slab;
+pub mod stacks;
+
+/// The size of a page and physical frame, in bytes.
+pub const PAGE_SIZE: u64 = 4096;
+
+/// The boot info passed to [`kmain`](crate::kmain) by the bootloader.
+pub static BOOT_INFO: InitLater<&'static BootInfo> = InitLater::uninit();
+
+/// Where the bootloader mapped all of physical memory into virtual memory.
+pub static PHYS_OFFSET: InitLater<u64> = InitLater::uninit();
+
+/// Returns a pointer to physical address `phys` in virtual memory.
+pub fn phys_to_virt(phys: u64) -> Result<*mut u8, InitError<u64>> {
+      Ok((phys + *PHYS_OFFSET.read()?) as *mut u8)
+}
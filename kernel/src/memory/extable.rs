@@ -0,0 +1,170 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory/extable.rs
+
+    The exception table, which lets faulting kernel accesses resume at a
+    fixup instead of panicking.
+    Contained within the memory module
+*/
+
+use core::arch::naked_asm;
+
+/// A region of kernel code which can fault, and where to resume if it does.
+struct Fixup {
+      /// The first instruction which can fault.
+      start: *const u8,
+      /// The instruction after the last one which can fault.
+      end:   *const u8,
+      fixup: *const u8,
+}
+
+// Safety: The pointers are only used as addresses
+unsafe impl Sync for Fixup {}
+
+// Labels defined in the naked functions below
+unsafe extern "C" {
+      static probe_read_start: u8;
+      static probe_read_end: u8;
+      static probe_read_fixup: u8;
+      static copy_checked_start: u8;
+      static copy_checked_end: u8;
+      static copy_checked_fixup: u8;
+}
+
+/// Every region of kernel code allowed to fault.
+static EXTABLE: [Fixup; 2] = [
+      Fixup {
+            start: &raw const probe_read_start,
+            end:   &raw const probe_read_end,
+            fixup: &raw const probe_read_fixup,
+      },
+      Fixup {
+            start: &raw const copy_checked_start,
+            end:   &raw const copy_checked_end,
+            fixup: &raw const copy_checked_fixup,
+      },
+];
+
+/// Returns where to resume after an exception at `ip`, or 0 if it isn't
+/// allowed to fault.
+///
+/// Called by the page & general protection fault handlers.
+#[unsafe(no_mangle)]
+pub extern "sysv64" fn search_fixups(ip: u64) -> u64 {
+      EXTABLE
+            .iter()
+            .find(|f| (f.start as u64..f.end as u64).contains(&ip))
+            .map_or(0, |f| f.fixup as u64)
+}
+
+/// Returns the u64 at `addr`, or `None` if reading it faults.
+pub fn probe_read_u64(addr: u64) -> Option<u64> {
+      let mut val = 0;
+      // Safety: Faults while reading are caught by the fixup
+      let faulted = unsafe { probe_read_asm(addr, &mut val) };
+      (faulted == 0).then_some(val)
+}
+
+/// Copies `len` bytes from `src` to `dst`, stopping at the first fault.
+///
+/// Returns the number of bytes which couldn't be copied if it faulted.
+///
+/// # Safety
+/// Any memory `dst` points to must be fine to overwrite.
+pub unsafe fn copy_checked(
+      dst: *mut u8, src: *const u8, len: usize,
+) -> Result<(), usize> {
+      // Safety: The caller ensures `dst` can be overwritten, and faults are
+      // caught by the fixup
+      match unsafe { copy_checked_asm(dst, src, len) } {
+            0 => Ok(()),
+            left => Err(left),
+      }
+}
+
+/// Reads the u64 at `addr` into `val`, returning 1 if it faulted.
+#[unsafe(naked)]
+#[rustfmt::skip]
+unsafe extern "sysv64" fn probe_read_asm(addr: u64, val: *mut u64) -> u64 {
+      naked_asm!(
+            ".global probe_read_start, probe_read_end, probe_read_fixup",
+            "probe_read_start:",
+            "mov rax, [rdi]",
+            "probe_read_end:",
+            "mov [rsi], rax",
+            "xor eax, eax",
+            "ret",
+
+            "probe_read_fixup:",
+            "mov eax, 1",
+            "ret",
+      )
+}
+
+/// Copies `len` bytes from `src` to `dst`, returning how many bytes were left
+/// when it faulted.
+#[unsafe(naked)]
+#[rustfmt::skip]
+unsafe extern "sysv64" fn copy_checked_asm(
+      dst: *mut u8, src: *const u8, len: usize,
+) -> usize {
+      naked_asm!(
+            ".global copy_checked_start, copy_checked_end, copy_checked_fixup",
+            "mov rcx, rdx",
+            "copy_checked_start:",
+            "rep movsb",
+            "copy_checked_end:",
+            "xor eax, eax",
+            "ret",
+
+            "copy_checked_fixup:",
+            "mov rax, rcx", // rep leaves the bytes left in rcx
+            "ret",
+      )
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that probing reads mapped memory, and doesn't panic on
+      /// unmapped or non-canonical addresses.
+      #[test_case]
+      fn probe_read_recovers() {
+            let val = 0xF00D_u64;
+            assert_eq!(probe_read_u64(&raw const val as u64), Some(0xF00D));
+            assert_eq!(probe_read_u64(0x_5555_0000_0000), None); // page fault
+            assert_eq!(probe_read_u64(0x_8000_0000_0000), None); // gp fault
+      }
+
+      /// Tests that checked copies stop at the first unmapped byte.
+      #[test_case]
+      fn copy_checked_stops_at_fault() {
+            let mut dst = [0u8; 16];
+            let src = [0x42u8; 16];
+            unsafe {
+                  copy_checked(dst.as_mut_ptr(), src.as_ptr(), 16).unwrap();
+                  assert_eq!(dst, src);
+
+                  let src = 0x_5555_0000_0000 as *const u8;
+                  assert_eq!(copy_checked(dst.as_mut_ptr(), src, 16), Err(16));
+            }
+      }
+}
@@ -0,0 +1,510 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory/frames.rs
+
+    Physical frame allocator built from the bootloader's memory map.
+    Contained within the memory module
+*/
+
+use core::fmt::Display;
+use core::slice;
+
+use bootloader::bootinfo::{MemoryRegion, MemoryRegionType};
+use libutil::{ExclusiveMap, InitError};
+use thiserror::Error;
+
+use super::{BOOT_INFO, PAGE_SIZE, PHYS_OFFSET};
+use crate::startup::ExitCode;
+use crate::{exit_on_err, interrupts};
+
+/// The max number of regions which can be tracked, the same as the max number
+/// of entries in the bootloader's memory map.
+const MAX_REGIONS: usize = 64;
+
+/// The number of frames tracked by each word in the bitmap.
+const WORD_FRAMES: u64 = u64::BITS as u64;
+
+/// The physical frame allocator.
+static FRAMES: ExclusiveMap<FrameAllocator> =
+      ExclusiveMap::new(FrameAllocator::empty());
+
+/// A 4 KiB aligned frame of physical memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct PhysFrame(u64);
+
+impl PhysFrame {
+      /// Returns the frame containing physical address `addr`.
+      pub const fn containing(addr: u64) -> Self {
+            PhysFrame(addr & !(PAGE_SIZE - 1))
+      }
+
+      /// Returns the physical address of the start of the frame.
+      pub const fn addr(&self) -> u64 {
+            self.0
+      }
+
+      /// Returns the frame's number, or it's index in physical memory.
+      pub const fn number(&self) -> u64 {
+            self.0 / PAGE_SIZE
+      }
+}
+
+/// What a region of physical memory is being used for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+      /// Free to be handed out by the allocator.
+      Usable,
+
+      /// Occupied by the kernel, it's stack, page tables or the bootloader.
+      Kernel,
+
+      /// Reserved by the firmware or hardware, never allocated.
+      Reserved,
+}
+
+impl From<MemoryRegionType> for RegionKind {
+      fn from(ty: MemoryRegionType) -> Self {
+            match ty {
+                  MemoryRegionType::Usable => RegionKind::Usable,
+                  MemoryRegionType::InUse |
+                  MemoryRegionType::Kernel |
+                  MemoryRegionType::KernelStack |
+                  MemoryRegionType::PageTable |
+                  MemoryRegionType::Bootloader |
+                  MemoryRegionType::BootInfo |
+                  MemoryRegionType::Package => RegionKind::Kernel,
+                  _ => RegionKind::Reserved,
+            }
+      }
+}
+
+/// A range of physical frames, `start..end`.
+#[derive(Debug, Clone, Copy)]
+pub struct Region {
+      /// The number of the first frame in the region.
+      pub start: u64,
+      /// The number of the frame after the last frame in the region.
+      pub end:   u64,
+      pub kind:  RegionKind,
+}
+
+impl Region {
+      /// An empty region, used to fill the unused slots in the region table.
+      const EMPTY: Region = Region {
+            start: 0,
+            end:   0,
+            kind:  RegionKind::Reserved,
+      };
+
+      /// Returns the number of frames in the region.
+      pub const fn frames(&self) -> u64 {
+            self.end - self.start
+      }
+}
+
+/// A bitmap allocator handing out 4 KiB physical frames.
+pub struct FrameAllocator {
+      /// One bit per frame, which is set if the frame is in use.
+      bitmap:        &'static mut [u64],
+      /// The regions found in the memory map.
+      regions:       [Region; MAX_REGIONS],
+      /// How many entries in `regions` are used.
+      count:         usize,
+      /// The word in the bitmap to start searching for a free frame from.
+      next:          usize,
+      /// The number of frames currently free.
+      free:          u64,
+      /// The number of frames used to store the bitmap.
+      bitmap_frames: u64,
+}
+
+/// Statistics about physical memory, measured in frames.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrameStats {
+      /// Frames which can be handed out, both free and allocated.
+      pub usable:   u64,
+      /// Usable frames which are currently free.
+      pub free:     u64,
+      /// Frames occupied by the kernel, bootloader or the allocator itself.
+      pub kernel:   u64,
+      /// Frames reserved by the firmware or hardware.
+      pub reserved: u64,
+}
+
+impl FrameAllocator {
+      /// Creates an allocator without any memory, which fails every allocation.
+      const fn empty() -> Self {
+            FrameAllocator {
+                  bitmap:        &mut [],
+                  regions:       [Region::EMPTY; MAX_REGIONS],
+                  count:         0,
+                  next:          0,
+                  free:          0,
+                  bitmap_frames: 0,
+            }
+      }
+
+      /// Builds an allocator out of the bootloader's memory map, storing it's
+      /// bitmap in the first usable region big enough to hold it.
+      ///
+      /// # Safety
+      /// `offset` must be where physical memory is mapped to and all usable
+      /// regions in `map` must actually be unused.
+      unsafe fn new(
+            map: &[MemoryRegion], offset: u64,
+      ) -> Result<Self, FrameInitError> {
+            let mut alloc = FrameAllocator::empty();
+
+            for region in map.iter().filter(|r| !r.range.is_empty()) {
+                  if alloc.count == MAX_REGIONS {
+                        return Err(FrameInitError::TooManyRegions);
+                  }
+
+                  alloc.regions[alloc.count] = Region {
+                        start: region.range.start_frame_number,
+                        end:   region.range.end_frame_number,
+                        kind:  region.region_type.into(),
+                  };
+                  alloc.count += 1;
+            }
+
+            // Only frames up to the end of the last usable region are tracked
+            let end = alloc
+                  .regions()
+                  .iter()
+                  .filter(|r| r.kind == RegionKind::Usable)
+                  .map(|r| r.end)
+                  .max()
+                  .ok_or(FrameInitError::NoUsableMemory)?;
+            let words = end.div_ceil(WORD_FRAMES);
+            let bitmap_frames =
+                  (words * size_of::<u64>() as u64).div_ceil(PAGE_SIZE);
+
+            let home = alloc
+                  .regions()
+                  .iter()
+                  .find(|r| {
+                        r.kind == RegionKind::Usable &&
+                              r.frames() >= bitmap_frames
+                  })
+                  .ok_or(FrameInitError::NoSpaceForBitmap(bitmap_frames))?
+                  .start;
+
+            let bitmap = (home * PAGE_SIZE + offset) as *mut u64;
+            // Safety: The caller must ensure that the usable region is unused
+            // and mapped at offset, with it being big enough to hold the bitmap
+            alloc.bitmap =
+                  unsafe { slice::from_raw_parts_mut(bitmap, words as usize) };
+            alloc.bitmap.fill(u64::MAX);
+            alloc.bitmap_frames = bitmap_frames;
+
+            // Free all usable frames, besides frame zero since it looks null
+            for idx in 0..alloc.count {
+                  let region = alloc.regions[idx];
+                  if region.kind == RegionKind::Usable {
+                        (region.start.max(1)..region.end)
+                              .for_each(|f| alloc.set_used(f, false));
+                  }
+            }
+
+            (home..home + bitmap_frames).for_each(|f| alloc.set_used(f, true));
+            alloc.next = (home / WORD_FRAMES) as usize;
+            Ok(alloc)
+      }
+
+      /// Returns the used regions in the region table.
+      pub fn regions(&self) -> &[Region] {
+            &self.regions[..self.count]
+      }
+
+      /// Returns if frame `frame` is currently in use, treating untracked
+      /// frames as always in use.
+      fn is_used(&self, frame: u64) -> bool {
+            match self.bitmap.get((frame / WORD_FRAMES) as usize) {
+                  Some(word) => word & (1 << (frame % WORD_FRAMES)) != 0,
+                  None => true,
+            }
+      }
+
+      /// Marks frame `frame` as either used or free, updating the free count.
+      fn set_used(&mut self, frame: u64, used: bool) {
+            let was_used = self.is_used(frame);
+            let Some(word) =
+                  self.bitmap.get_mut((frame / WORD_FRAMES) as usize)
+            else {
+                  return;
+            };
+            let bit = 1 << (frame % WORD_FRAMES);
+
+            if used && !was_used {
+                  *word |= bit;
+                  self.free -= 1;
+            } else if !used && was_used {
+                  *word &= !bit;
+                  self.free += 1;
+            }
+      }
+
+      /// Hands out the next free frame.
+      fn alloc(&mut self) -> Option<PhysFrame> {
+            let words = self.bitmap.len();
+
+            for idx in (self.next..words).chain(0..self.next) {
+                  let word = self.bitmap[idx];
+                  if word != u64::MAX {
+                        let frame = idx as u64 * WORD_FRAMES +
+                              (!word).trailing_zeros() as u64;
+                        self.set_used(frame, true);
+                        self.next = idx;
+                        return Some(PhysFrame(frame * PAGE_SIZE));
+                  }
+            }
+
+            None
+      }
+
+      /// Hands out `count` free frames in a row ending below frame `limit`,
+      /// with the first frame being a multiple of `count`.
+      fn alloc_contiguous(
+            &mut self, count: u64, limit: u64,
+      ) -> Option<PhysFrame> {
+            let end = limit.min(self.bitmap.len() as u64 * WORD_FRAMES);
+            let start = (count..end)
+                  .step_by(count as usize)
+                  .take_while(|start| start + count <= end)
+                  .find(|start| {
+                        (*start..start + count).all(|f| !self.is_used(f))
+                  })?;
+
+            (start..start + count).for_each(|f| self.set_used(f, true));
+            Some(PhysFrame(start * PAGE_SIZE))
+      }
+
+      /// Takes back frame `frame`, allowing it to be handed out again.
+      fn free(&mut self, frame: PhysFrame) -> Result<(), FreeFrameError> {
+            let num = frame.number();
+            let usable = self.regions().iter().any(|r| {
+                  r.kind == RegionKind::Usable &&
+                        (r.start..r.end).contains(&num)
+            });
+
+            if !usable || num == 0 {
+                  Err(FreeFrameError::NotUsable(frame.addr()))
+            } else if !self.is_used(num) {
+                  Err(FreeFrameError::DoubleFree(frame.addr()))
+            } else {
+                  self.set_used(num, false);
+                  Ok(())
+            }
+      }
+
+      /// Returns statistics about the frames managed by the allocator.
+      fn stats(&self) -> FrameStats {
+            let count = |kind| {
+                  self.regions()
+                        .iter()
+                        .filter(|r| r.kind == kind)
+                        .map(Region::frames)
+                        .sum::<u64>()
+            };
+
+            FrameStats {
+                  usable:   count(RegionKind::Usable) - self.bitmap_frames,
+                  free:     self.free,
+                  kernel:   count(RegionKind::Kernel) + self.bitmap_frames,
+                  reserved: count(RegionKind::Reserved),
+            }
+      }
+}
+
+/// Loads the physical memory offset and builds the frame allocator from the
+/// bootloader's memory map.
+///
+/// # Safety
+/// Only run this once, after [`BOOT_INFO`] has been set.
+pub unsafe fn init() -> ExitCode<FrameInitError> {
+      let info = *exit_on_err!(BOOT_INFO.read());
+      let offset = *exit_on_err!(PHYS_OFFSET.init(info.physical_memory_offset));
+      dbg_info!("physical memory mapped at 0x{offset:x}");
+
+      // Safety: The bootloader ensures that the memory map and offset are valid
+      let alloc = exit_on_err!(unsafe {
+            FrameAllocator::new(&info.memory_map, offset)
+      });
+      let _stats = alloc.stats();
+      dbg_info!(
+            "frames: {} free, bitmap at 0x{:x}",
+            _stats.free,
+            alloc.bitmap.as_ptr() as u64
+      );
+
+      if FRAMES.map(|f| *f = alloc).is_none() {
+            return ExitCode::Error(FrameInitError::Contended);
+      }
+
+      ExitCode::Ok
+}
+
+/// Hands out a free physical frame.
+///
+/// Fails if there are no free frames or the allocator is being used somewhere
+/// else.
+pub fn alloc_frame() -> Option<PhysFrame> {
+      interrupts::without_interrupts(|| FRAMES.map(|f| f.alloc())).flatten()
+}
+
+/// Hands out `count` free frames in a row, all below physical address
+/// `limit`, returning the first.
+///
+/// The first frame is aligned to `count` frames, so a power of two `count` of
+/// at most 16 never crosses a 64 KiB boundary, as required by ISA DMA.
+pub fn alloc_contiguous(count: u64, limit: u64) -> Option<PhysFrame> {
+      if count == 0 {
+            return None;
+      }
+
+      interrupts::without_interrupts(|| {
+            FRAMES.map(|f| f.alloc_contiguous(count, limit / PAGE_SIZE))
+      })
+      .flatten()
+}
+
+/// Takes back `frame`, allowing it to be handed out again.
+///
+/// # Safety
+/// The frame must have been handed out by [`alloc_frame`] or
+/// [`alloc_contiguous`] and must not be used
+/// anywhere after being freed.
+pub unsafe fn free_frame(frame: PhysFrame) -> Result<(), FreeFrameError> {
+      interrupts::without_interrupts(|| FRAMES.map(|f| f.free(frame)))
+            .unwrap_or(Err(FreeFrameError::Contended))
+}
+
+/// Returns statistics about physical memory, if the allocator isn't being
+/// used somewhere else.
+pub fn stats() -> Option<FrameStats> {
+      FRAMES.map(|f| f.stats())
+}
+
+impl Display for FrameStats {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            const KIB_PER_FRAME: u64 = PAGE_SIZE / 1024;
+            write!(
+                  f,
+                  "{} / {} KiB free, {} KiB kernel, {} KiB reserved",
+                  self.free * KIB_PER_FRAME,
+                  self.usable * KIB_PER_FRAME,
+                  self.kernel * KIB_PER_FRAME,
+                  self.reserved * KIB_PER_FRAME
+            )
+      }
+}
+
+/// An error created when building the frame allocator.
+#[derive(Error, Debug)]
+pub enum FrameInitError {
+      #[error(transparent)]
+      NoBootInfo(#[from] InitError<&'static bootloader::BootInfo>),
+
+      #[error(transparent)]
+      NoOffset(#[from] InitError<u64>),
+
+      #[error("The memory map has more than {MAX_REGIONS} regions!")]
+      TooManyRegions,
+
+      #[error("The memory map has no usable memory!")]
+      NoUsableMemory,
+
+      #[error("No usable region can fit the {0} frame bitmap!")]
+      NoSpaceForBitmap(u64),
+
+      #[error("The frame allocator is being used somewhere else!")]
+      Contended,
+}
+
+/// An error created when freeing a frame.
+#[derive(Error, Debug, PartialEq)]
+pub enum FreeFrameError {
+      #[error("frame 0x{0:x} isn't in usable memory")]
+      NotUsable(u64),
+
+      #[error("frame 0x{0:x} was freed twice")]
+      DoubleFree(u64),
+
+      #[error("the frame allocator is being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that allocated frames are unique, aligned and can be freed.
+      #[test_case]
+      fn frames_alloc_and_free() {
+            let free = stats().unwrap().free;
+            let fst = alloc_frame().unwrap();
+            let snd = alloc_frame().unwrap();
+
+            assert_ne!(fst, snd);
+            assert_eq!(fst.addr() % PAGE_SIZE, 0);
+            assert_eq!(stats().unwrap().free, free - 2);
+
+            unsafe {
+                  free_frame(fst).unwrap();
+                  free_frame(snd).unwrap();
+            }
+            assert_eq!(stats().unwrap().free, free);
+      }
+
+      /// Tests that contiguous frames are aligned and below the limit.
+      #[test_case]
+      fn frames_alloc_contiguous() {
+            const LIMIT: u64 = 16 * 1024 * 1024;
+            let fst = alloc_contiguous(4, LIMIT).unwrap();
+
+            assert_eq!(fst.number() % 4, 0);
+            assert!(fst.addr() + 4 * PAGE_SIZE <= LIMIT);
+            assert_eq!(alloc_contiguous(0, LIMIT), None);
+
+            for num in fst.number()..fst.number() + 4 {
+                  let frame = PhysFrame::containing(num * PAGE_SIZE);
+                  unsafe { free_frame(frame).unwrap() }
+            }
+      }
+
+      /// Tests that frames can't be freed twice or outside of usable memory.
+      #[test_case]
+      fn frames_bad_free() {
+            let frame = alloc_frame().unwrap();
+            unsafe {
+                  free_frame(frame).unwrap();
+                  assert_eq!(
+                        free_frame(frame),
+                        Err(FreeFrameError::DoubleFree(frame.addr()))
+                  );
+                  assert_eq!(
+                        free_frame(PhysFrame::containing(0)),
+                        Err(FreeFrameError::NotUsable(0))
+                  );
+            }
+      }
+}
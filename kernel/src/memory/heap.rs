@@ -0,0 +1,398 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory/heap.rs
+
+    The kernel heap, a first-fit free list which grows by mapping more pages.
+    Contained within the memory module
+*/
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::Display;
+use core::ptr;
+
+use libutil::ExclusiveMap;
+use thiserror::Error;
+
+use super::paging::{self, MapError, PageFlags};
+use super::{PAGE_SIZE, frames};
+use crate::startup::ExitCode;
+use crate::{PANIC, interrupts};
+
+/// Where the heap starts in virtual memory.
+pub const HEAP_START: u64 = 0x_4444_4444_0000;
+
+/// The size of the heap when it's first created.
+const HEAP_INIT_SIZE: u64 = 64 * 1024;
+
+/// The largest size the heap can grow to.
+pub const HEAP_MAX_SIZE: u64 = 16 * 1024 * 1024;
+
+/// The smallest block which can be allocated or stored in the free list,
+/// all allocations are rounded up to a multiple of this.
+const MIN_BLOCK: usize = size_of::<FreeBlock>();
+
+/// The allocator used by `alloc`'s collections.
+#[global_allocator]
+static ALLOCATOR: KernelAllocator = KernelAllocator;
+
+/// The kernel heap.
+static HEAP: ExclusiveMap<Heap> = ExclusiveMap::new(Heap::empty());
+
+/// The header written at the start of every free block.
+#[repr(C)]
+struct FreeBlock {
+      /// The size of the block, including this header.
+      size: usize,
+      /// The address of the next free block, or 0 if this is the last.
+      next: usize,
+}
+
+/// A free list of blocks sorted by address.
+struct Heap {
+      /// The address of the first free block, or 0 if there aren't any.
+      head:  usize,
+      /// The address after the last mapped page of the heap.
+      end:   u64,
+      /// Statistics about the heap.
+      stats: HeapStats,
+}
+
+/// Statistics about the kernel heap.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+      /// The number of bytes mapped for the heap.
+      pub size:   u64,
+      /// The number of bytes currently allocated.
+      pub used:   u64,
+      /// The most bytes which have been allocated at once.
+      pub peak:   u64,
+      /// The number of allocations which haven't been freed yet.
+      pub allocs: u64,
+      /// The number of allocations which couldn't be fulfilled.
+      pub failed: u64,
+}
+
+/// The `#[global_allocator]`, forwarding everything to [`HEAP`].
+struct KernelAllocator;
+
+impl Heap {
+      /// Creates a heap without any mapped memory.
+      const fn empty() -> Self {
+            Heap {
+                  head:  0,
+                  end:   HEAP_START,
+                  stats: HeapStats {
+                        size:   0,
+                        used:   0,
+                        peak:   0,
+                        allocs: 0,
+                        failed: 0,
+                  },
+            }
+      }
+
+      /// Returns the size and alignment actually used for `layout`.
+      fn fit(layout: Layout) -> (usize, usize) {
+            let size = layout.size().max(1).next_multiple_of(MIN_BLOCK);
+            (size, layout.align().max(MIN_BLOCK))
+      }
+
+      /// Adds the block at `addr` to the free list, merging it with any
+      /// neighbouring blocks.
+      ///
+      /// # Safety
+      /// The block must be mapped, unused and at least [`MIN_BLOCK`] bytes.
+      unsafe fn add_free(&mut self, addr: usize, size: usize) {
+            let mut prev = 0;
+            let mut next = self.head;
+            while next != 0 && next < addr {
+                  prev = next;
+                  // Safety: Every address in the list points to a free block
+                  next = unsafe { (*(next as *const FreeBlock)).next };
+            }
+
+            // Safety: The caller ensures that the block is ours to write to,
+            // and every address in the list points to a free block
+            unsafe {
+                  let block = if prev != 0 &&
+                        prev + (*(prev as *const FreeBlock)).size == addr
+                  {
+                        (*(prev as *mut FreeBlock)).size += size;
+                        prev
+                  } else {
+                        (addr as *mut FreeBlock)
+                              .write(FreeBlock { size, next });
+                        match prev {
+                              0 => self.head = addr,
+                              _ => (*(prev as *mut FreeBlock)).next = addr,
+                        }
+                        addr
+                  };
+
+                  let block = &mut *(block as *mut FreeBlock);
+                  if next != 0 && block as *mut _ as usize + block.size == next
+                  {
+                        let next = &*(next as *const FreeBlock);
+                        block.size += next.size;
+                        block.next = next.next;
+                  }
+            }
+      }
+
+      /// Removes and returns the first free block which can fit `layout`,
+      /// returning any left over space back to the free list.
+      fn take_block(&mut self, layout: Layout) -> Option<usize> {
+            let (size, align) = Self::fit(layout);
+            let mut prev = 0;
+            let mut cur = self.head;
+
+            while cur != 0 {
+                  // Safety: Every address in the list points to a free block
+                  let FreeBlock { size: len, next } =
+                        unsafe { (cur as *const FreeBlock).read() };
+
+                  // leave enough space before start to store a free block
+                  let mut start = cur.next_multiple_of(align);
+                  if start != cur && start - cur < MIN_BLOCK {
+                        start = (cur + MIN_BLOCK).next_multiple_of(align);
+                  }
+
+                  let end = start + size;
+                  if end <= cur + len {
+                        match prev {
+                              0 => self.head = next,
+                              // Safety: See safety comment above
+                              _ => unsafe {
+                                    (*(prev as *mut FreeBlock)).next = next
+                              },
+                        }
+
+                        // Safety: Both are parts of the block we just took,
+                        // and are multiples of MIN_BLOCK in size
+                        unsafe {
+                              if start > cur {
+                                    self.add_free(cur, start - cur);
+                              }
+                              if end < cur + len {
+                                    self.add_free(end, cur + len - end);
+                              }
+                        }
+                        return Some(start);
+                  }
+
+                  prev = cur;
+                  cur = next;
+            }
+
+            None
+      }
+
+      /// Maps enough pages onto the end of the heap to fit at least `bytes`.
+      fn grow(&mut self, bytes: u64) -> Result<(), HeapError> {
+            let bytes = bytes.next_multiple_of(PAGE_SIZE);
+            if self.end + bytes > HEAP_START + HEAP_MAX_SIZE {
+                  return Err(HeapError::Full(bytes));
+            }
+
+            let start = self.end;
+            let mut res = Ok(());
+            while self.end < start + bytes {
+                  let Some(frame) = frames::alloc_frame() else {
+                        res = Err(HeapError::NoFrames);
+                        break;
+                  };
+
+                  let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+                  // Safety: Nothing else uses the heap's virtual memory
+                  if let Err(e) = unsafe { paging::map(self.end, frame, flags) }
+                  {
+                        // Safety: The frame was never mapped anywhere
+                        _ = unsafe { frames::free_frame(frame) };
+                        res = Err(e.into());
+                        break;
+                  }
+                  self.end += PAGE_SIZE;
+            }
+
+            // Keep whatever pages we managed to map
+            if self.end > start {
+                  self.stats.size += self.end - start;
+                  // Safety: The pages were just mapped and are unused
+                  unsafe {
+                        self.add_free(
+                              start as usize,
+                              (self.end - start) as usize,
+                        )
+                  };
+            }
+
+            res
+      }
+
+      /// Allocates a block fitting `layout`, growing the heap if needed.
+      fn alloc(&mut self, layout: Layout) -> *mut u8 {
+            let (size, align) = Self::fit(layout);
+            let block = self.take_block(layout).or_else(|| {
+                  self.grow((size + align) as u64).ok()?;
+                  self.take_block(layout)
+            });
+
+            let Some(addr) = block else {
+                  self.stats.failed += 1;
+                  return ptr::null_mut();
+            };
+
+            self.stats.used += size as u64;
+            self.stats.peak = self.stats.peak.max(self.stats.used);
+            self.stats.allocs += 1;
+            addr as *mut u8
+      }
+
+      /// Returns the block at `ptr` to the free list.
+      ///
+      /// # Safety
+      /// `ptr` must have been allocated by [`Heap::alloc`] with `layout`.
+      unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+            let (size, _) = Self::fit(layout);
+            // Safety: The caller ensures that the block came from the heap
+            unsafe { self.add_free(ptr as usize, size) };
+            self.stats.used -= size as u64;
+            self.stats.allocs -= 1;
+      }
+}
+
+// Safety: The heap is only ever accessed through ExclusiveMap with interrupts
+// disabled, so allocations can never overlap
+unsafe impl GlobalAlloc for KernelAllocator {
+      unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            interrupts::without_interrupts(|| HEAP.map(|h| h.alloc(layout)))
+                  .unwrap_or(ptr::null_mut())
+      }
+
+      unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            // Safety: The caller ensures that ptr was allocated with layout
+            let freed = interrupts::without_interrupts(|| {
+                  HEAP.map(|h| unsafe { h.dealloc(ptr, layout) })
+            });
+
+            if freed.is_none() {
+                  PANIC!(badbug "Failed freeing {ptr:?} as the heap was in use")
+            }
+      }
+}
+
+/// Ran when an allocation from `alloc` fails.
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+      match stats() {
+            Some(stats) => PANIC!(badbug "Failed allocating {} bytes aligned \
+                  to {}\nHeap: {stats}", layout.size(), layout.align()),
+            None => PANIC!(badbug "Failed allocating {} bytes aligned to {} \
+                  as the heap was in use", layout.size(), layout.align()),
+      }
+}
+
+/// Maps the initial pages of the heap.
+///
+/// # Safety
+/// Only run this once, after the frame allocator has been built.
+pub unsafe fn init() -> ExitCode<HeapError> {
+      match HEAP.map(|h| h.grow(HEAP_INIT_SIZE)) {
+            Some(Ok(())) => ExitCode::Ok,
+            Some(Err(e)) => ExitCode::Error(e),
+            None => ExitCode::Error(HeapError::Contended),
+      }
+}
+
+/// Returns statistics about the heap, if it isn't being used somewhere else.
+pub fn stats() -> Option<HeapStats> {
+      interrupts::without_interrupts(|| HEAP.map(|h| h.stats))
+}
+
+impl Display for HeapStats {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(
+                  f,
+                  "{} / {} bytes used by {} allocs, {} bytes peak, {} failed",
+                  self.used, self.size, self.allocs, self.peak, self.failed
+            )
+      }
+}
+
+/// An error created when growing the heap.
+#[derive(Error, Debug)]
+pub enum HeapError {
+      #[error(transparent)]
+      Map(#[from] MapError),
+
+      #[error("ran out of frames to grow the heap with")]
+      NoFrames,
+
+      #[error("growing by {0} bytes would make the heap too big")]
+      Full(u64),
+
+      #[error("the heap is being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use alloc::boxed::Box;
+      use alloc::collections::BTreeMap;
+      use alloc::string::String;
+      use alloc::vec::Vec;
+
+      use super::*;
+
+      /// Tests that `alloc`'s collections can be used.
+      #[test_case]
+      fn heap_collections_work() {
+            let boxed = Box::new(0xF00D_u64);
+            let mut vec: Vec<u64> = (0..1000).collect();
+            let mut map = BTreeMap::new();
+            let mut string = String::from("sun");
+
+            vec.push(*boxed);
+            map.insert(1, "one");
+            string.push_str("flower");
+
+            assert_eq!(vec.iter().sum::<u64>(), 499500 + 0xF00D);
+            assert_eq!(map.get(&1), Some(&"one"));
+            assert_eq!(string, "sunflower");
+      }
+
+      /// Tests that freed memory is reused and large allocations grow the heap.
+      #[test_case]
+      fn heap_reuses_and_grows() {
+            let used = stats().unwrap().used;
+            let fst = Box::new([0_u8; 64]);
+            let addr = &*fst as *const _ as usize;
+            drop(fst);
+
+            let snd = Box::new([0_u8; 64]);
+            assert_eq!(&*snd as *const _ as usize, addr);
+            drop(snd);
+
+            let big = Vec::<u8>::with_capacity(HEAP_INIT_SIZE as usize * 2);
+            assert!(stats().unwrap().size > HEAP_INIT_SIZE);
+            drop(big);
+            assert_eq!(stats().unwrap().used, used);
+      }
+}
@@ -0,0 +1,404 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory/paging.rs
+
+    Walks and edits the active 4-level page tables.
+    Contained within the memory module
+*/
+
+use core::arch::asm;
+use core::fmt::Display;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use bitflags::bitflags;
+use libutil::{InitError, IrqSafeMutex};
+use thiserror::Error;
+
+use super::frames::{self, PhysFrame};
+use super::{PAGE_SIZE, phys_to_virt};
+
+/// The number of entries in each page table.
+const ENTRIES: usize = 512;
+
+/// The bits of a page table entry which hold the address it points to.
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// The name of each level of page table, starting from the PML4.
+const LEVELS: [&str; 4] = ["PML4", "PDPT", "PD", "PT"];
+
+/// Where device memory mapped by [`map_mmio`] starts in virtual memory.
+pub const MMIO_START: u64 = 0x_7777_0000_0000;
+
+/// The next free page for device memory to be mapped to.
+static NEXT_MMIO: AtomicU64 = AtomicU64::new(MMIO_START);
+
+/// Held while changing the active page tables, so that two threads can't
+/// create the same table or change an entry another is walking.
+static TABLES_LOCK: IrqSafeMutex<()> = IrqSafeMutex::new(());
+
+/// A page table at any level, from the PML4 down to the PT.
+type PageTable = [u64; ENTRIES];
+
+/// Every entry used to translate an address, from the PML4 down to the PT.
+#[derive(Debug, Clone, Copy)]
+pub struct PageWalk {
+      /// The index used into each level of page table.
+      pub indexes: [usize; 4],
+      /// The entry found at each level, or `None` if the walk stopped before
+      /// reaching it.
+      pub entries: [Option<u64>; 4],
+}
+
+bitflags! {
+      /// The flags which can be set in a page table entry.
+      #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+      pub struct PageFlags: u64 {
+            const PRESENT       = 1 << 0;
+            const WRITABLE      = 1 << 1;
+            const USER          = 1 << 2;
+            const WRITE_THROUGH = 1 << 3;
+            const NO_CACHE      = 1 << 4;
+            const ACCESSED      = 1 << 5;
+            const DIRTY         = 1 << 6;
+            const HUGE          = 1 << 7;
+            const GLOBAL        = 1 << 8;
+            const NO_EXECUTE    = 1 << 63;
+      }
+}
+
+/// Returns the physical address of the active PML4.
+fn active_pml4() -> u64 {
+      let cr3: u64;
+      // Safety: Just reading CR3
+      unsafe { asm!("mov {}, cr3", out(reg) cr3) };
+      cr3 & ADDR_MASK
+}
+
+/// Returns a pointer to the page table stored in frame `phys`.
+fn table_at(phys: u64) -> Result<*mut PageTable, MapError> {
+      Ok(phys_to_virt(phys & ADDR_MASK)?.cast())
+}
+
+/// Returns the index into each level of page table used to translate `virt`,
+/// starting from the PML4.
+fn indexes(virt: u64) -> [usize; 4] {
+      let idx = |level: u64| ((virt >> (12 + 9 * level)) & 0x1FF) as usize;
+      [idx(3), idx(2), idx(1), idx(0)]
+}
+
+/// Flushes the TLB entry for `page`.
+fn flush(page: u64) {
+      // Safety: Invalidating a TLB entry never breaks anything
+      unsafe { asm!("invlpg [{}]", in(reg) page, options(nostack)) }
+}
+
+/// Maps `page` to `frame` with flags `flags` in the active page tables,
+/// creating any missing tables along the way.
+///
+/// # Safety
+/// The caller must ensure that mapping `frame` to `page` doesn't alias any
+/// memory which is being used somewhere else.
+pub unsafe fn map(
+      page: u64, frame: PhysFrame, flags: PageFlags,
+) -> Result<(), MapError> {
+      if !page.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned(page));
+      }
+
+      let _lock = TABLES_LOCK.lock();
+      let [pml4, pdpt, pd, pt] = indexes(page);
+      let mut table = table_at(active_pml4())?;
+
+      for idx in [pml4, pdpt, pd] {
+            // Safety: Each table is either one created by the bootloader or
+            // one we've just made, which are both mapped at the phys offset
+            let entry = unsafe { &mut (*table)[idx] };
+
+            if *entry & PageFlags::PRESENT.bits() == 0 {
+                  let new = frames::alloc_frame().ok_or(MapError::NoFrames)?;
+                  // Safety: The frame was just handed to us, so it's unused
+                  unsafe { table_at(new.addr())?.write([0; ENTRIES]) };
+                  *entry = new.addr() |
+                        (PageFlags::PRESENT | PageFlags::WRITABLE).bits();
+            } else if *entry & PageFlags::HUGE.bits() != 0 {
+                  return Err(MapError::HugePage(page));
+            }
+
+            // the final entry decides if the page is actually user accessible
+            if flags.contains(PageFlags::USER) {
+                  *entry |= PageFlags::USER.bits();
+            }
+            table = table_at(*entry)?;
+      }
+
+      // Safety: See safety comment above
+      let entry = unsafe { &mut (*table)[pt] };
+      if *entry & PageFlags::PRESENT.bits() != 0 {
+            return Err(MapError::AlreadyMapped(page));
+      }
+
+      *entry = frame.addr() | (flags | PageFlags::PRESENT).bits();
+      flush(page);
+      Ok(())
+}
+
+/// Returns a pointer to the PT entry mapping `page`, without creating any
+/// missing tables.
+fn leaf_entry(page: u64) -> Result<*mut u64, MapError> {
+      let walk = walk(page)?;
+      for (level, entry) in walk.entries.into_iter().enumerate() {
+            let entry = entry.ok_or(MapError::NotMapped(page))?;
+            if entry & PageFlags::PRESENT.bits() == 0 {
+                  return Err(MapError::NotMapped(page));
+            } else if entry & PageFlags::HUGE.bits() != 0 && level != 3 {
+                  return Err(MapError::HugePage(page));
+            }
+      }
+
+      // Safety: The walk above ensures that every table down to the PT exists
+      let table = table_at(walk.entries[2].unwrap_or_default())?;
+      Ok(unsafe { &raw mut (*table)[walk.indexes[3]] })
+}
+
+/// Walks the active page tables for `virt`, stopping at the first entry which
+/// isn't present or is a huge page.
+pub fn walk(virt: u64) -> Result<PageWalk, MapError> {
+      let indexes = indexes(virt);
+      let mut entries = [None; 4];
+      let mut table = table_at(active_pml4())?;
+
+      for (level, idx) in indexes.into_iter().enumerate() {
+            // Safety: Every present entry above the PT points to a table,
+            // which are all mapped at the phys offset
+            let entry = unsafe { (*table)[idx] };
+            entries[level] = Some(entry);
+
+            let present = entry & PageFlags::PRESENT.bits() != 0;
+            let huge = entry & PageFlags::HUGE.bits() != 0 && level != 3;
+            if !present || huge || level == 3 {
+                  break;
+            }
+            table = table_at(entry)?;
+      }
+
+      Ok(PageWalk { indexes, entries })
+}
+
+/// Returns the physical address `virt` is mapped to, if it's mapped at all.
+pub fn translate(virt: u64) -> Option<u64> {
+      let walk = walk(virt).ok()?;
+      let (level, entry) = walk
+            .entries
+            .into_iter()
+            .enumerate()
+            .filter_map(|(level, e)| Some((level, e?)))
+            .next_back()?;
+
+      if entry & PageFlags::PRESENT.bits() == 0 {
+            return None;
+      }
+
+      // Each level up maps 512 times more memory, so has 9 more offset bits
+      let offset_bits = 12 + 9 * (3 - level as u64);
+      let offset_mask = (1 << offset_bits) - 1;
+      Some((entry & ADDR_MASK & !offset_mask) | (virt & offset_mask))
+}
+
+/// Unmaps `page` from the active page tables, returning the frame it was
+/// mapped to.
+///
+/// # Safety
+/// The caller must ensure that nothing uses `page` after it's unmapped.
+pub unsafe fn unmap(page: u64) -> Result<PhysFrame, MapError> {
+      if !page.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned(page));
+      }
+
+      let _lock = TABLES_LOCK.lock();
+      let entry = leaf_entry(page)?;
+      // Safety: leaf_entry returns a valid pointer to the page's PT entry
+      let frame = unsafe { PhysFrame::containing(entry.read() & ADDR_MASK) };
+      unsafe { entry.write(0) };
+      flush(page);
+      Ok(frame)
+}
+
+/// Changes the flags of the already mapped `page` to `flags`.
+///
+/// # Safety
+/// The caller must ensure that nothing relies on the page's old flags, such as
+/// by writing to a page which is now read only.
+#[cfg_attr(not(test), allow(dead_code))]
+pub unsafe fn protect(page: u64, flags: PageFlags) -> Result<(), MapError> {
+      if !page.is_multiple_of(PAGE_SIZE) {
+            return Err(MapError::Unaligned(page));
+      }
+
+      let _lock = TABLES_LOCK.lock();
+      let entry = leaf_entry(page)?;
+      if flags.contains(PageFlags::USER) {
+            let mut table = table_at(active_pml4())?;
+            for idx in &indexes(page)[..3] {
+                  // Safety: leaf_entry found every table down to the PT, which
+                  // are all mapped at the phys offset
+                  let entry = unsafe { &mut (*table)[*idx] };
+                  *entry |= PageFlags::USER.bits();
+                  table = table_at(*entry)?;
+            }
+      }
+
+      // Safety: leaf_entry returns a valid pointer to the page's PT entry
+      unsafe {
+            let addr = entry.read() & ADDR_MASK;
+            entry.write(addr | (flags | PageFlags::PRESENT).bits());
+      }
+      flush(page);
+      Ok(())
+}
+
+/// Maps the `len` bytes of device memory at `phys` into uncached virtual
+/// memory, returning a pointer to where `phys` was mapped.
+///
+/// # Safety
+/// `phys` must point to device registers, not memory used for anything else.
+pub unsafe fn map_mmio(phys: u64, len: u64) -> Result<*mut u8, MapError> {
+      let offset = phys % PAGE_SIZE;
+      let pages = (offset + len).div_ceil(PAGE_SIZE);
+      let virt = NEXT_MMIO.fetch_add(pages * PAGE_SIZE, Ordering::Relaxed);
+      let flags = PageFlags::WRITABLE |
+            PageFlags::NO_CACHE |
+            PageFlags::WRITE_THROUGH |
+            PageFlags::NO_EXECUTE;
+
+      for page in 0..pages {
+            let frame = PhysFrame::containing(phys - offset + page * PAGE_SIZE);
+            // Safety: The virtual range was just reserved for this mapping and
+            // the caller ensures that the frame is device memory
+            unsafe { map(virt + page * PAGE_SIZE, frame, flags)? }
+      }
+
+      Ok((virt + offset) as *mut u8)
+}
+
+impl Display for PageWalk {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            write!(f, "Walk:")?;
+            let levels = LEVELS.iter().zip(self.indexes).zip(self.entries);
+            for (level, ((name, idx), entry)) in levels.enumerate() {
+                  match entry {
+                        Some(entry) => write!(f, " {name}[{idx}]={entry:#x}"),
+                        None => write!(f, " {name}[{idx}]=-"),
+                  }?;
+
+                  // fit two levels on each line
+                  if level == 1 {
+                        write!(f, "\n     ")?;
+                  }
+            }
+            Ok(())
+      }
+}
+
+/// An error created when changing the page tables.
+#[derive(Error, Debug)]
+pub enum MapError {
+      #[error(transparent)]
+      NoOffset(#[from] InitError<u64>),
+
+      #[error("page 0x{0:x} isn't page aligned")]
+      Unaligned(u64),
+
+      #[error("ran out of frames to create page tables with")]
+      NoFrames,
+
+      #[error("page 0x{0:x} is inside a huge page")]
+      HugePage(u64),
+
+      #[error("page 0x{0:x} is already mapped")]
+      AlreadyMapped(u64),
+
+      #[error("page 0x{0:x} isn't mapped")]
+      NotMapped(u64),
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// An unused page to test mapping with.
+      const TEST_PAGE: u64 = 0x_5555_0000_0000;
+
+      /// Tests that mapped pages translate to their frame and can be unmapped.
+      #[test_case]
+      fn paging_map_translate_unmap() {
+            let frame = frames::alloc_frame().unwrap();
+            let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+
+            unsafe {
+                  map(TEST_PAGE, frame, flags).unwrap();
+                  (TEST_PAGE as *mut u64).write_volatile(0xF00D);
+                  let phys = phys_to_virt(frame.addr()).unwrap() as *mut u64;
+                  assert_eq!(phys.read_volatile(), 0xF00D);
+            }
+
+            assert_eq!(translate(TEST_PAGE + 8), Some(frame.addr() + 8));
+            assert!(matches!(
+                  unsafe { map(TEST_PAGE, frame, flags) },
+                  Err(MapError::AlreadyMapped(TEST_PAGE))
+            ));
+
+            assert_eq!(unsafe { unmap(TEST_PAGE) }.unwrap(), frame);
+            assert_eq!(translate(TEST_PAGE), None);
+            unsafe { frames::free_frame(frame).unwrap() };
+      }
+
+      /// Tests that protect changes a page's flags without moving it.
+      #[test_case]
+      fn paging_protect() {
+            let frame = frames::alloc_frame().unwrap();
+            let page = TEST_PAGE + PAGE_SIZE;
+
+            unsafe {
+                  map(page, frame, PageFlags::WRITABLE).unwrap();
+                  protect(page, PageFlags::NO_EXECUTE).unwrap();
+            }
+
+            let entry = walk(page).unwrap().entries[3].unwrap();
+            assert_eq!(entry & PageFlags::WRITABLE.bits(), 0);
+            assert_ne!(entry & PageFlags::NO_EXECUTE.bits(), 0);
+            assert_eq!(translate(page), Some(frame.addr()));
+
+            unsafe {
+                  unmap(page).unwrap();
+                  frames::free_frame(frame).unwrap();
+            }
+      }
+
+      /// Tests that protecting an unmapped page fails without mapping it.
+      #[test_case]
+      fn paging_protect_unmapped() {
+            let page = TEST_PAGE + 2 * PAGE_SIZE;
+            assert!(matches!(
+                  unsafe { protect(page, PageFlags::USER) },
+                  Err(MapError::NotMapped(_))
+            ));
+            assert_eq!(translate(page), None);
+      }
+}
@@ -0,0 +1,187 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory/slab.rs
+
+    Per-type object caches, built from libutil's slab allocator.
+    Contained within the memory module
+*/
+
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use libfs::INode;
+use libutil::{CacheStats, ExclusiveMap, PageSource, SlabCache};
+
+use super::frames::{self, PhysFrame};
+use super::{PHYS_OFFSET, phys_to_virt};
+use crate::interrupts;
+
+/// Cache for the copies of inodes used while reading and writing floppyfs
+/// files.
+pub static INODE_CACHE: ObjectCache<INode> =
+      ObjectCache::new("inode", INode::zeroed);
+
+/// Every object cache, shown in `SystemInfo`.
+pub static CACHES: [&(dyn CacheInfo + Sync); CACHE_COUNT] = [&INODE_CACHE];
+
+/// The number of object caches.
+pub const CACHE_COUNT: usize = 1;
+
+/// Hands out frames to slabs through the physical memory offset.
+pub struct FramePages;
+
+impl PageSource for FramePages {
+      fn alloc_page(&mut self) -> Option<NonNull<u8>> {
+            let frame = frames::alloc_frame()?;
+            NonNull::new(phys_to_virt(frame.addr()).ok()?)
+      }
+
+      unsafe fn free_page(&mut self, page: NonNull<u8>) {
+            let Ok(offset) = PHYS_OFFSET.read() else {
+                  return;
+            };
+            let frame = PhysFrame::containing(page.as_ptr() as u64 - offset);
+            // Safety: The caller ensures the page came from alloc_page
+            if let Err(_e) = unsafe { frames::free_frame(frame) } {
+                  warn!("slab page leaked: {_e}");
+            }
+      }
+}
+
+/// A cache of objects of type `T`, which can be shared between threads.
+pub struct ObjectCache<T> {
+      name:  &'static str,
+      cache: ExclusiveMap<SlabCache<T, FramePages>>,
+}
+
+/// An object allocated from an [`ObjectCache`], which is returned to it once
+/// dropped.
+pub struct Cached<T: Send + 'static> {
+      obj:   NonNull<T>,
+      cache: &'static ObjectCache<T>,
+}
+
+// Safety: Cached objects are owned like a Box, so can be sent if T can
+unsafe impl<T: Send> Send for Cached<T> {}
+
+/// Allows getting statistics about a cache without knowing it's type.
+pub trait CacheInfo {
+      /// Returns the name of the cache.
+      fn name(&self) -> &'static str;
+
+      /// Returns statistics about the cache, if it isn't being used somewhere
+      /// else.
+      fn stats(&self) -> Option<CacheStats>;
+}
+
+impl<T: Send> ObjectCache<T> {
+      /// Creates a new cache named `name`, using `ctor` to create objects.
+      pub const fn new(name: &'static str, ctor: fn() -> T) -> Self {
+            ObjectCache {
+                  name,
+                  cache: ExclusiveMap::new(SlabCache::new(
+                        name, ctor, FramePages,
+                  )),
+            }
+      }
+
+      /// Returns a new object from the cache.
+      ///
+      /// Fails if there's no memory left or the cache is being used somewhere
+      /// else.
+      pub fn alloc(&'static self) -> Option<Cached<T>> {
+            let obj = interrupts::without_interrupts(|| {
+                  self.cache.map(|c| c.alloc())
+            })
+            .flatten()?;
+            Some(Cached { obj, cache: self })
+      }
+
+      /// Returns `obj` to the cache, returning it back if the cache is being
+      /// used somewhere else.
+      ///
+      /// # Safety
+      /// `obj` must have been allocated by this cache and not be used after
+      /// this.
+      unsafe fn free(&self, obj: NonNull<T>) -> Result<(), NonNull<T>> {
+            // Safety: The caller ensures obj came from this cache
+            interrupts::without_interrupts(|| {
+                  self.cache.map(|c| unsafe { c.free(obj) })
+            })
+            .ok_or(obj)
+      }
+}
+
+impl<T: Send> Deref for Cached<T> {
+      type Target = T;
+
+      fn deref(&self) -> &T {
+            // Safety: The object is owned by self until it's dropped
+            unsafe { self.obj.as_ref() }
+      }
+}
+
+impl<T: Send> DerefMut for Cached<T> {
+      fn deref_mut(&mut self) -> &mut T {
+            // Safety: See deref
+            unsafe { self.obj.as_mut() }
+      }
+}
+
+impl<T: Send> Drop for Cached<T> {
+      fn drop(&mut self) {
+            // Safety: The object came from the cache and isn't used again
+            if unsafe { self.cache.free(self.obj) }.is_err() {
+                  warn!("slab: leaked object from cache {}", self.cache.name);
+            }
+      }
+}
+
+impl<T: Send> CacheInfo for ObjectCache<T> {
+      fn name(&self) -> &'static str {
+            self.name
+      }
+
+      fn stats(&self) -> Option<CacheStats> {
+            interrupts::without_interrupts(|| self.cache.map(|c| c.stats()))
+      }
+}
+
+/// Returns statistics about every cache in [`CACHES`].
+pub fn stats() -> [Option<CacheStats>; CACHE_COUNT] {
+      CACHES.map(|c| c.stats())
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that objects allocated from caches show up in their stats.
+      #[test_case]
+      fn slab_caches_track_objects() {
+            let in_use = INODE_CACHE.stats().unwrap().in_use;
+            let nod = INODE_CACHE.alloc().unwrap();
+            assert_eq!(INODE_CACHE.stats().unwrap().in_use, in_use + 1);
+            assert!(nod.is_available());
+
+            drop(nod);
+            assert_eq!(INODE_CACHE.stats().unwrap().in_use, in_use);
+      }
+}
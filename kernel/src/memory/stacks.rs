@@ -0,0 +1,199 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/memory/stacks.rs
+
+    Allocates kernel stacks with an unmapped guard page below each one, and
+    keeps track of them to detect stack overflows.
+    Contained within the memory module
+*/
+
+use alloc::vec::Vec;
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use libutil::ExclusiveMap;
+use thiserror::Error;
+
+use super::PAGE_SIZE;
+use super::frames::{self, FreeFrameError};
+use super::paging::{self, MapError, PageFlags};
+use crate::startup::ExitCode;
+
+/// Where stacks start being allocated in virtual memory.
+pub const STACKS_START: u64 = 0x_3333_0000_0000;
+
+/// The most pages the kernel stack is searched for, in either direction.
+const MAX_SEARCH_PAGES: u64 = 4096;
+
+/// Where the next stack's guard page will be placed.
+static NEXT_STACK: AtomicU64 = AtomicU64::new(STACKS_START);
+
+/// Every stack which has a guard page below it.
+static STACKS: ExclusiveMap<Vec<KernelStack>> = ExclusiveMap::new(Vec::new());
+
+/// A stack with an unmapped guard page directly below it.
+#[derive(Debug, Clone, Copy)]
+pub struct KernelStack {
+      /// The name of the stack, used when reporting overflows.
+      pub name:   &'static str,
+      /// The lowest mapped address of the stack.
+      pub bottom: u64,
+      /// The address after the highest mapped address of the stack.
+      pub top:    u64,
+}
+
+impl KernelStack {
+      /// Returns the address of the guard page.
+      pub const fn guard(&self) -> u64 {
+            self.bottom - PAGE_SIZE
+      }
+}
+
+/// Finds the bounds of the kernel stack the bootloader gave us, so that
+/// overflows of it can be detected.
+///
+/// # Safety
+/// Only run this once, before any other stacks are switched to.
+pub unsafe fn init() -> ExitCode<StackError> {
+      let rsp: u64;
+      // Safety: Just reading the stack pointer
+      unsafe { asm!("mov {}, rsp", out(reg) rsp) };
+
+      let page = rsp & !(PAGE_SIZE - 1);
+      let mapped = |n: u64| paging::translate(page - n * PAGE_SIZE).is_some();
+      let Some(below) = (0..MAX_SEARCH_PAGES).find(|&n| !mapped(n)) else {
+            return ExitCode::Error(StackError::NoGuard);
+      };
+
+      let above = (0..MAX_SEARCH_PAGES)
+            .find(|&n| paging::translate(page + n * PAGE_SIZE).is_none())
+            .unwrap_or(MAX_SEARCH_PAGES);
+
+      let stack = KernelStack {
+            name:   "kernel stack",
+            bottom: page - (below - 1) * PAGE_SIZE,
+            top:    page + above * PAGE_SIZE,
+      };
+      dbg_info!("kernel stack at 0x{:x} to 0x{:x}", stack.bottom, stack.top);
+
+      match register(stack) {
+            Ok(()) => ExitCode::Ok,
+            Err(e) => ExitCode::Error(e),
+      }
+}
+
+/// Allocates a new stack `pages` pages big with a guard page below it.
+pub fn alloc_stack(
+      name: &'static str, pages: u64,
+) -> Result<KernelStack, StackError> {
+      let guard =
+            NEXT_STACK.fetch_add((pages + 1) * PAGE_SIZE, Ordering::Relaxed);
+      let stack = KernelStack {
+            name,
+            bottom: guard + PAGE_SIZE,
+            top: guard + (pages + 1) * PAGE_SIZE,
+      };
+
+      for page in (stack.bottom..stack.top).step_by(PAGE_SIZE as usize) {
+            let frame = frames::alloc_frame().ok_or(StackError::NoFrames)?;
+            let flags = PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+            // Safety: Every stack gets it's own unused virtual memory
+            unsafe { paging::map(page, frame, flags)? };
+      }
+
+      register(stack)?;
+      Ok(stack)
+}
+
+/// Unmaps `stack` and frees it's frames, leaving the guard page's address
+/// unused.
+///
+/// # Safety
+/// `stack` must have been returned by [`alloc_stack`] and not be used after
+/// this.
+pub unsafe fn free_stack(stack: KernelStack) -> Result<(), StackError> {
+      STACKS.map(|s| s.retain(|s| s.bottom != stack.bottom))
+            .ok_or(StackError::Contended)?;
+
+      for page in (stack.bottom..stack.top).step_by(PAGE_SIZE as usize) {
+            // Safety: The caller ensures the stack isn't used anymore
+            unsafe {
+                  let frame = paging::unmap(page)?;
+                  frames::free_frame(frame)?;
+            }
+      }
+
+      Ok(())
+}
+
+/// Adds `stack` to the list of stacks checked for overflows.
+fn register(stack: KernelStack) -> Result<(), StackError> {
+      STACKS.map(|s| s.push(stack)).ok_or(StackError::Contended)
+}
+
+/// Returns the name of the stack whose guard page contains `addr`, if any.
+pub fn overflowed_stack(addr: u64) -> Option<&'static str> {
+      STACKS.map(|stacks| {
+            let page = addr & !(PAGE_SIZE - 1);
+            stacks.iter().find(|s| s.guard() == page).map(|s| s.name)
+      })
+      .flatten()
+}
+
+/// An error created when allocating or finding a stack.
+#[derive(Error, Debug)]
+pub enum StackError {
+      #[error(transparent)]
+      Map(#[from] MapError),
+
+      #[error(transparent)]
+      Free(#[from] FreeFrameError),
+
+      #[error("ran out of frames to allocate the stack with")]
+      NoFrames,
+
+      #[error("couldn't find the kernel stack's guard page")]
+      NoGuard,
+
+      #[error("the stack list is being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that allocated stacks are writable, have a guard page and can be
+      /// freed.
+      #[test_case]
+      fn stacks_have_guard_pages() {
+            let stack = alloc_stack("test stack", 2).unwrap();
+            assert_eq!(stack.top - stack.bottom, 2 * PAGE_SIZE);
+            assert_eq!(paging::translate(stack.guard()), None);
+
+            unsafe { ((stack.top - 8) as *mut u64).write_volatile(0xF00D) };
+            assert_eq!(overflowed_stack(stack.guard() + 8), Some("test stack"));
+            assert_eq!(overflowed_stack(stack.bottom), None);
+
+            unsafe { free_stack(stack).unwrap() };
+            assert_eq!(paging::translate(stack.bottom), None);
+            assert_eq!(overflowed_stack(stack.guard()), None);
+      }
+}
@@ -0,0 +1,91 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/msr.rs
+
+    Allows reading and writing model specific registers.
+*/
+
+use core::arch::asm;
+
+/// A model specific register.
+#[derive(Debug, Clone, Copy)]
+#[repr(u32)]
+pub enum Msr {
+      /// The local APIC's base address and enable bit
+      ApicBase    = 0x1B,
+
+      /// The first performance counter
+      Pmc0        = 0xC1,
+
+      /// The number of MCA banks and which MCA features exist
+      McgCap      = 0x179,
+
+      /// The state of the processor after a machine check
+      McgStatus   = 0x17A,
+
+      /// Selects the event counted by the first performance counter
+      PerfEvtSel0 = 0x186,
+
+      /// Extended feature enables, including the syscall enable bit
+      Efer        = 0xC000_0080,
+
+      /// The segments loaded by `syscall` and `sysret`
+      Star        = 0xC000_0081,
+
+      /// The address `syscall` jumps to in long mode
+      Lstar       = 0xC000_0082,
+
+      /// The rflags bits cleared by `syscall`
+      Sfmask      = 0xC000_0084,
+}
+
+/// Returns the value in `msr`.
+/// # Safety
+/// The MSR must exist on the CPU, or a GP fault is raised.
+pub unsafe fn read(msr: Msr) -> u64 {
+      // Safety: The caller ensures the MSR exists
+      unsafe { read_raw(msr as u32) }
+}
+
+/// Returns the value in the MSR with address `msr`, for MSRs which come in
+/// ranges such as the MCA banks.
+/// # Safety
+/// The MSR must exist on the CPU, or a GP fault is raised.
+pub unsafe fn read_raw(msr: u32) -> u64 {
+      let (low, high): (u32, u32);
+      // Safety: The caller ensures the MSR exists
+      unsafe { asm!("rdmsr", in("ecx") msr, out("eax") low, out("edx") high) }
+      (high as u64) << 32 | low as u64
+}
+
+/// Writes `val` into `msr`.
+/// # Safety
+/// The MSR must exist on the CPU and writing `val` into it must be safe.
+pub unsafe fn write(msr: Msr, val: u64) {
+      // Safety: The caller ensures writing to the MSR is safe
+      unsafe {
+            asm!(
+                  "wrmsr",
+                  in("ecx") msr as u32,
+                  in("eax") val as u32,
+                  in("edx") (val >> 32) as u32
+            )
+      }
+}
@@ -22,13 +22,15 @@
     Handles kernel panics and the [`PANIC!`] macro.
 */
 
-use core::arch::asm;
+use core::arch::{asm, naked_asm};
 use core::ffi::{CStr, c_char, c_void};
+use core::fmt::Display;
 use core::hint;
 use core::panic::PanicInfo;
 use core::sync::atomic::{AtomicU64, Ordering};
 
 use crate::floppy::motor;
+use crate::memory::extable::probe_read_u64;
 use crate::ports::{self, Port};
 use crate::sysinfo::SystemInfo;
 use crate::vga::{buffers, cursor};
@@ -48,10 +50,11 @@ use crate::{interrupts, speaker};
 /// Creates a handler function for exceptions, taking the cause of the error as
 /// a `&CStr`, and a `fn(u64)` function pointer to print information about the
 /// error code.
+/// Exceptions raised by user programs kill the program instead.
 ///
 /// ### exception noerror
-/// Same as `exception` except without the error code and it's `fn(u64)`
-/// function pointer.
+/// Same as `exception` except without the error code, optionally taking a
+/// `fn()` function pointer to print information about the error instead.
 ///
 /// ### const
 /// Const available panicking, taking an `&'static str` as it's cause.
@@ -64,12 +67,17 @@ macro_rules! PANIC {
             use $crate::panic::kpanic;
             use core::ffi::c_char;
 
+            // Kill user programs instead of the whole kernel
+            if stackframe.is_user() {
+                  $crate::user::kill($cause, &stackframe, || ($info)(errcode))
+            }
+
             // The last test ran by run_tests, checks that stack overflows
-            // cause page faults, so we need to exit running tests if so
+            // are detected, so we need to exit running tests if so
             #[cfg(test)]
             {
-                  if $cause == c"PAGE FAULT" {
-                        println!("test stack overflow causes #PF - passed");
+                  if $cause == c"STACK OVERFLOW" {
+                        println!("test stack overflow is detected - passed");
                         println!("\nIt looks like you didn't break anything!");
                         $crate::tests::exit_qemu(false);
                   }
@@ -84,7 +92,7 @@ macro_rules! PANIC {
                   // Safety: The statics are only ever written to once
                   unsafe {
                         let ip = IP;
-                        println!("Instruction: 0x{ip}");
+                        println!("Instruction: 0x{ip:x}");
                         errcode(ERRCODE)
                   }
             }
@@ -100,6 +108,36 @@ macro_rules! PANIC {
       wrapper as *const () as u64
       }};
 
+      (exception noerror $cause:expr, $info:expr) => {{
+            extern "x86-interrupt" fn wrapper(
+                  stackframe: $crate::interrupts::IntStackFrame
+            ) -> ! {
+            use $crate::panic::kpanic;
+            use core::ffi::c_char;
+
+            if stackframe.is_user() {
+                  $crate::user::kill($cause, &stackframe, $info)
+            }
+
+            static mut IP: u64 = 0;
+            extern "sysv64" fn info() {
+                  let info = $info;
+
+                  // Safety: The static's only ever written to once
+                  unsafe { let ip = IP; println!("Instruction: 0x{ip:x}") }
+                  info()
+            }
+
+            let cause = $cause as *const _ as *const c_char;
+            unsafe {
+                  IP = stackframe.ip;
+                  kpanic(cause, stackframe.sp, info);
+            }
+        }
+
+      wrapper as *const () as u64
+      }};
+
       (exception noerror $cause:expr) => {{
             extern "x86-interrupt" fn wrapper(
                   stackframe: $crate::interrupts::IntStackFrame
@@ -107,10 +145,14 @@ macro_rules! PANIC {
             use $crate::panic::kpanic;
             use core::ffi::c_char;
 
+            if stackframe.is_user() {
+                  $crate::user::kill($cause, &stackframe, || ())
+            }
+
             static mut IP: u64 = 0;
             extern "sysv64" fn info() {
                   // Safety: The static's only ever written to once
-                  unsafe { let ip = IP; println!("Instruction: 0x{ip}") }
+                  unsafe { let ip = IP; println!("Instruction: 0x{ip:x}") }
             }
 
             let cause = $cause as *const _ as *const c_char;
@@ -169,18 +211,48 @@ macro_rules! PANIC {
       };
 }
 
-/// Triggers a kernel panic.
+/// The size of the stack panics run on.
+const PANIC_STACK_SIZE: usize = 16 * 1024;
+
+/// The stack panics run on.
+#[repr(C, align(16))]
+struct PanicStack([u8; PANIC_STACK_SIZE]);
+
+/// The stack panics run on, so that faults while probing memory can't
+/// overwrite the stack of the exception which caused the panic, as they
+/// might both use the same IST.
+static mut PANIC_STACK: PanicStack = PanicStack([0; PANIC_STACK_SIZE]);
+
+/// Triggers a kernel panic, switching to the panic stack first.
 /// # Safety
 /// This function should only be called via the [`PANIC`] macro.
 #[cfg_attr(test, allow(unused))]
+#[unsafe(naked)]
 #[unsafe(no_mangle)]
 pub unsafe extern "sysv64" fn kpanic(
       cause: *const c_char, sp: *const c_void, info: extern "sysv64" fn(),
+) -> ! {
+      naked_asm!(
+            "lea rsp, [rip + {stack} + {size}]",
+            "call {kpanic}", // rbp is kept, so stack traces still work
+            "jmp hang",
+            stack = sym PANIC_STACK,
+            size = const PANIC_STACK_SIZE,
+            kpanic = sym kpanic_inner,
+      )
+}
+
+/// Prints the panic screen then waits for ESC to be pressed, ran by
+/// [`kpanic`] on the panic stack.
+#[cfg_attr(test, allow(unused))]
+unsafe extern "sysv64" fn kpanic_inner(
+      cause: *const c_char, sp: *const c_void, info: extern "sysv64" fn(),
 ) -> ! {
       /// The total number of panics which have occurred,
       /// useful for debugging problems with [`PANIC`] & [`kpanic`].
       static PANICS: AtomicU64 = AtomicU64::new(0);
 
+      interrupts::stop_watchdog(); // so the panic screen isn't a hang
       speaker::stop(); // prevent it from playing forever if it was on
       motor::force_disable(); // in case it was on
       cursor::ALLOW_ROW_0.store(true, Ordering::Relaxed);
@@ -226,13 +298,11 @@ pub unsafe extern "sysv64" fn kpanic(
       info();
       stack_trace(6);
 
-      // Print the top few elements on the stack
-      // Safety: PANIC should have (hopefully) sent through a valid SP
-      let valof =
-            |offset| unsafe { *((sp as *const u64).wrapping_add(offset)) };
+      // Print the top few elements on the stack, which might not be readable
+      // if the SP was bad
+      let valof = |offset: u64| Probed(probe_read_u64(sp as u64 + offset * 8));
       println!(
-            "\nStack (SP=0x{sp:?}):\n  {:#18x}  {:#18x}  {:#18x}\n  \
-            {:#18x}  {:#18x}  {:#18x}",
+            "\nStack (SP=0x{sp:?}):\n  {}  {}  {}\n  {}  {}  {}",
             valof(0),
             valof(1),
             valof(2),
@@ -261,35 +331,44 @@ pub unsafe extern "sysv64" fn kpanic(
       }
 }
 
+/// A value read with [`probe_read_u64`], printed as `??` if it couldn't be.
+struct Probed(Option<u64>);
+
+impl Display for Probed {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self.0 {
+                  Some(val) => write!(f, "{val:#18x}"),
+                  None => write!(f, "{:>18}", "??"),
+            }
+      }
+}
+
 /// Prints a stack trace at most `frames` stackframes up.
 #[unsafe(no_mangle)]
 #[inline(never)]
 fn stack_trace(frames: u32) {
-      #[repr(C)]
-      #[derive(Clone, Copy)]
-      struct Stackframe {
-            next: *const Stackframe,
-            rip:  u64,
-      }
-
-      let mut stack: *const Stackframe;
+      let mut stack: u64;
       // Safety: RBP (should) always point to the last stackframe,
       // even after interrupt handlers have been fired
       unsafe { asm!("mov {0}, rbp", out(reg) stack) }
 
-      println!("\nStack trace (BP=0x{stack:?}):");
+      println!("\nStack trace (BP=0x{stack:x}):");
       for idx in 0..frames {
-            // Safety: See safety comment above
-            let sf = unsafe { *stack };
-            stack = sf.next;
+            // Each stackframe holds the previous BP, then the return address
+            let (Some(next), Some(rip)) =
+                  (probe_read_u64(stack), probe_read_u64(stack + 8))
+            else {
+                  return println!("  {idx}  unreadable stackframe");
+            };
+            stack = next;
 
             // bootloader nicely ends the stackframe list with a null for us
-            if stack.is_null() {
+            if stack == 0 {
                   return;
             }
 
-            if sf.rip != 0 {
-                  println!("  {idx}  {:#8x}", sf.rip)
+            if rip != 0 {
+                  println!("  {idx}  {rip:#8x}")
             }
       }
 }
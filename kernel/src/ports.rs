@@ -72,6 +72,28 @@ pub enum Port {
       /// CMOS register selected by `CMOSIndex`, read & write
       CMOSData         = 0x71,
 
+      // --- DMA ports ---
+      /// Start address of DMA channel 2, written low byte then high byte
+      DMAChannel2Addr  = 0x04,
+
+      /// Transfer count of DMA channel 2, written low byte then high byte
+      DMAChannel2Count = 0x05,
+
+      /// Masks or unmasks a single channel of the 8 bit DMA controller,
+      /// write only
+      DMASingleMask    = 0x0A,
+
+      /// Sets the transfer mode of a channel of the 8 bit DMA controller,
+      /// write only
+      DMAMode          = 0x0B,
+
+      /// Resets the low / high byte flip-flop of the 8 bit DMA controller,
+      /// write only
+      DMAFlipFlop      = 0x0C,
+
+      /// Bits 16-23 of the address of DMA channel 2, read & write
+      DMAChannel2Page  = 0x81,
+
       // --- QEMU ports ---
       /// When written to inside of QEMU causes it to immediately exit
       /// (actually Disk Controller status register).
@@ -22,8 +22,11 @@
     Allows playing sounds through the PC speaker
 */
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use crate::ports::{self, Port};
-use crate::{startup, time};
+use crate::time::{self, KERNEL_TICKS_HZ};
+use crate::{startup, task};
 
 /// The bits required for the PC speaker to play sound through PIT channel 2.
 const PLAY_BITS: u8 = 0b11;
@@ -61,13 +64,20 @@ pub fn stop() {
       }
 }
 
-/// Plays `freq` for `time` milliseconds.
+/// Plays `freq` for `time` milliseconds, blocking until it's done.
 ///
 /// Repeatedly plays then stops playing at 100ms intervals if `repeat` is set.
 pub fn play_special(freq: u32, millis: u64, repeat: bool) {
+      task::block_on(play_note(freq, millis, repeat))
+}
+
+/// Plays `freq` for `time` milliseconds, without blocking other tasks.
+///
+/// Repeatedly plays then stops playing at 100ms intervals if `repeat` is set.
+pub async fn play_note(freq: u32, millis: u64, repeat: bool) {
       // FIXME: make this actually convert from milliseconds by dividing by 10
       // (yet still have 'songs' sound good)
-      let ticks = millis / 13; // convert millis to ticks
+      let ticks = millis * KERNEL_TICKS_HZ / 1300; // convert millis to ticks
 
       if !startup::PIT_INIT.load() {
             warn!("pcspeaker: attempted playing special without a PIT");
@@ -75,16 +85,16 @@ pub fn play_special(freq: u32, millis: u64, repeat: bool) {
       }
 
       if repeat {
-            const PULSE_LENGTH: u64 = 6;
+            const PULSE_LENGTH: u64 = KERNEL_TICKS_HZ * 6 / 100;
             for _ in 0..ticks / (PULSE_LENGTH * 2) {
                   play(freq);
-                  time::wait(PULSE_LENGTH);
+                  time::sleep(PULSE_LENGTH).await;
                   stop();
-                  time::wait(PULSE_LENGTH);
+                  time::sleep(PULSE_LENGTH).await;
             }
       } else {
             play(freq);
-            time::wait(ticks);
+            time::sleep(ticks).await;
             stop();
       }
 }
@@ -98,65 +108,76 @@ pub fn play_chime() {
       play_special(780, 900, false);
 }
 
-/// Plays after inputting syscmd 3.
-pub fn play_song() {
+/// Plays after inputting syscmd 3, as a task.
+///
+/// Returns immediately if the song is already playing.
+pub async fn play_song() {
+      /// Whether the song is being played.
+      static PLAYING: AtomicBool = AtomicBool::new(false);
+
+      if PLAYING.swap(true, Ordering::Relaxed) {
+            return;
+      }
+
       // Set 1 - Rising
-      play_special(400, 300, false);
-      play_special(430, 300, false);
-      play_special(450, 300, false);
-      play_special(500, 250, false);
-      play_special(550, 250, false);
+      play_note(400, 300, false).await;
+      play_note(430, 300, false).await;
+      play_note(450, 300, false).await;
+      play_note(500, 250, false).await;
+      play_note(550, 250, false).await;
 
       // Set 2 - Beeping 1
-      play_special(450, 100, false);
-      play_special(400, 150, false);
-      play_special(500, 250, false);
-      play_special(550, 250, false);
+      play_note(450, 100, false).await;
+      play_note(400, 150, false).await;
+      play_note(500, 250, false).await;
+      play_note(550, 250, false).await;
 
       // Set 3 - Beeping 2
-      play_special(600, 100, false);
-      play_special(620, 100, false);
-      play_special(600, 100, false);
-      play_special(620, 100, false);
-      play_special(600, 100, false);
-      play_special(620, 100, false);
-      play_special(500, 100, false);
-      play_special(480, 100, false);
+      play_note(600, 100, false).await;
+      play_note(620, 100, false).await;
+      play_note(600, 100, false).await;
+      play_note(620, 100, false).await;
+      play_note(600, 100, false).await;
+      play_note(620, 100, false).await;
+      play_note(500, 100, false).await;
+      play_note(480, 100, false).await;
 
       // Set 2 - Beeping 1
-      play_special(450, 100, false);
-      play_special(400, 150, false);
-      play_special(500, 250, false);
-      play_special(550, 250, false);
+      play_note(450, 100, false).await;
+      play_note(400, 150, false).await;
+      play_note(500, 250, false).await;
+      play_note(550, 250, false).await;
 
       // Set 1 - Rising
-      play_special(400, 300, false);
-      play_special(430, 300, false);
-      play_special(450, 300, false);
-      play_special(500, 250, false);
-      play_special(550, 250, false);
+      play_note(400, 300, false).await;
+      play_note(430, 300, false).await;
+      play_note(450, 300, false).await;
+      play_note(500, 250, false).await;
+      play_note(550, 250, false).await;
 
       // Set 2 - Beeping 1
-      play_special(450, 100, false);
-      play_special(400, 150, false);
-      play_special(500, 250, false);
-      play_special(550, 250, false);
+      play_note(450, 100, false).await;
+      play_note(400, 150, false).await;
+      play_note(500, 250, false).await;
+      play_note(550, 250, false).await;
 
       // Set 4 - Uh oh
-      play_special(600, 900, true);
-      play_special(500, 800, false);
-      play_special(600, 900, true);
+      play_note(600, 900, true).await;
+      play_note(500, 800, false).await;
+      play_note(600, 900, true).await;
 
       // Set 5 - Fade out
-      play_special(550, 100, false);
-      play_special(540, 100, false);
-      play_special(530, 100, false);
-      play_special(520, 100, false);
-      play_special(510, 100, false);
-      play_special(500, 100, false);
-      play_special(490, 100, false);
-      play_special(480, 100, false);
-      play_special(470, 100, false);
-      play_special(460, 100, false);
-      play_special(450, 1350, false);
+      play_note(550, 100, false).await;
+      play_note(540, 100, false).await;
+      play_note(530, 100, false).await;
+      play_note(520, 100, false).await;
+      play_note(510, 100, false).await;
+      play_note(500, 100, false).await;
+      play_note(490, 100, false).await;
+      play_note(480, 100, false).await;
+      play_note(470, 100, false).await;
+      play_note(460, 100, false).await;
+      play_note(450, 1350, false).await;
+
+      PLAYING.store(false, Ordering::Relaxed);
 }
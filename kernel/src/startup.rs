@@ -26,6 +26,8 @@ use core::fmt::Display;
 
 use libutil::UnsafeFlag;
 
+use crate::interrupts;
+use crate::time::Instant;
 use crate::vga::print::{self, Color};
 
 // Whether or not the GDT has been initialised yet
@@ -40,6 +42,13 @@ pub static GDT_INIT: UnsafeFlag = UnsafeFlag::new(false);
 /// they're ready to be initialised.
 pub static PIC_INIT: UnsafeFlag = UnsafeFlag::new(false);
 
+/// Whether or not the local APIC and I/O APICs are being used instead of the
+/// PICs.
+/// # Flag
+/// Falsely setting this flag to true causes IRQs to be masked in I/O APICs
+/// which might not exist.
+pub static APIC_INIT: UnsafeFlag = UnsafeFlag::new(false);
+
 /// Whether or not the PIT has been initialised yet
 /// # Flag
 /// Falsely setting this flag to true causes `time::wait` to loop forever and
@@ -58,6 +67,12 @@ pub static KBD_INIT: UnsafeFlag = UnsafeFlag::new(false);
 /// assume that they've been initialised.
 pub static FLOPPY_INIT: UnsafeFlag = UnsafeFlag::new(false);
 
+/// Whether or not the scheduler has been initialised yet.
+/// # Flag
+/// Falsely setting this flag to true causes the timer to switch threads before
+/// the current thread has been registered.
+pub static SCHED_INIT: UnsafeFlag = UnsafeFlag::new(false);
+
 /// Has the Real Time Clock IRQ been initialised yet?
 /// # Flag
 /// Falsely setting this flag in startup causes
@@ -91,8 +106,11 @@ pub unsafe fn run<E>(name: &str, task: unsafe fn() -> ExitCode<E>)
 where
       E: Display,
 {
+      let _start = Instant::now();
       // Safety: The caller must ensure that the task is safe to run
       unsafe { handle_exitcode(name, task()) }
+      dbg_info!("{name} took {:?}", _start.elapsed());
+      interrupts::pet_watchdog();
 }
 
 /// Handles [`ExitCode`] `exitcode`, printing it's result and hitting a
@@ -27,13 +27,17 @@ use core::arch::asm;
 use core::fmt::Display;
 use core::sync::atomic::Ordering;
 
-use libutil::{InitError, TableDescriptor};
+use libutil::{CacheStats, InitError, TableDescriptor};
 
 use crate::floppy::{self, disk, floppyfs};
 use crate::gdt::{self, Gdt};
-use crate::interrupts::{self, Idt};
+use crate::interrupts::{self, Idt, IntStats};
+use crate::memory::frames::{self, FrameStats};
+use crate::memory::heap::{self, HeapStats};
+use crate::memory::slab::{self, CACHE_COUNT};
 use crate::startup::{self, ExitCode};
-use crate::time::{self, Time};
+use crate::time::clock::{self, ClockSource};
+use crate::time::{self, TickSource, Time, tickless};
 
 /// Parses an environment variable as an int an compile time.
 #[macro_export]
@@ -131,17 +135,29 @@ pub struct SystemInfo {
       pub floppyfs_init:        bool,
       pub floppy_read_bytes:    u64,
       pub floppy_written_bytes: u64,
+      pub floppy_throughput:    Option<u64>,
+      pub floppy_dma:           bool,
+
+      // Memory
+      pub frames: Option<FrameStats>,
+      pub heap:   Option<HeapStats>,
+      pub caches: [Option<CacheStats>; CACHE_COUNT],
 
       // Time
       pub time:      u64,
       pub time_secs: u64,
       pub date:      Result<&'static Time, InitError<Time>>,
+      pub now:       Option<Time>,
+      pub clock:     ClockSource,
+      pub ticks:     TickSource,
+      pub skipped:   u64,
 
       // Descriptors and such
       pub gdt_init:       bool,
       pub gdt_descriptor: TableDescriptor<Gdt>,
       pub idt_init:       bool,
       pub idt_descriptor: TableDescriptor<Idt>,
+      pub interrupts:     IntStats,
 
       // Misc flags
       pub pic_init:      bool,
@@ -172,15 +188,26 @@ impl SystemInfo {
                   floppy_read_bytes: disk::READ_BYTES.load(Ordering::Relaxed),
                   floppy_written_bytes: disk::WRITTEN_BYTES
                         .load(Ordering::Relaxed),
+                  floppy_throughput: disk::throughput(),
+                  floppy_dma: floppy::DMA_MODE.load(Ordering::Relaxed),
+
+                  frames: frames::stats(),
+                  heap: heap::stats(),
+                  caches: slab::stats(),
 
                   time,
-                  time_secs: time / 100,
+                  time_secs: time / time::KERNEL_TICKS_HZ,
                   date: time::LAUNCH_TIME.read(),
+                  now: time::now(),
+                  clock: clock::source(),
+                  ticks: time::tick_source(),
+                  skipped: tickless::skipped(),
 
                   gdt_init: gdt::GDT.read().is_ok(),
                   gdt_descriptor: gdt::gdt_register(),
                   idt_init: interrupts::IDT.read().is_ok(),
                   idt_descriptor: interrupts::idt_register(),
+                  interrupts: IntStats::now(),
 
                   disable_enter: cfg!(feature = "disable_enter"),
                   pic_init: startup::PIC_INIT.load(),
@@ -206,20 +233,34 @@ Launch time: ",
                   Err(ref e) => writeln!(f, "Failed fetching time - {e}"),
             }?;
 
+            match self.now {
+                  Some(now) => {
+                        writeln!(f, "Current time: {} {now}", now.weekday())
+                  }
+                  None => writeln!(f, "Current time: unknown"),
+            }?;
+
             write!(
                   f,
                   "Uptime: {} ({}h {}m {}s)
+Clock source: {}
+Tick source: {} at {} Hz, {} skipped while idle
 
 Disable enter: {}
 PIC initialised: {}
 PIT initialised: {}
 KBD initialised: {}
 GDT init: {} with {}
-IDT init: {} with {}\n",
+IDT init: {} with {}
+Interrupts: {}\n\nMemory: ",
                   self.time,
                   self.time_secs / 3600,      // hours
                   (self.time_secs / 60) % 60, // mins
                   self.time_secs % 60,        // secs
+                  self.clock,
+                  self.ticks,
+                  time::KERNEL_TICKS_HZ,
+                  self.skipped,
                   self.disable_enter,
                   self.pic_init,
                   self.pit_init,
@@ -228,8 +269,30 @@ IDT init: {} with {}\n",
                   self.gdt_descriptor,
                   self.idt_init,
                   self.idt_descriptor,
+                  self.interrupts,
             )?;
 
+            match self.frames {
+                  Some(ref stats) => writeln!(f, "{stats}"),
+                  None => writeln!(f, "Frame allocator in use"),
+            }?;
+
+            match self.heap {
+                  Some(ref stats) => writeln!(f, "Heap: {stats}"),
+                  None => writeln!(f, "Heap: In use"),
+            }?;
+
+            write!(f, "Caches:")?;
+            for (idx, stats) in self.caches.iter().enumerate() {
+                  match stats {
+                        Some(stats) => write!(f, " {stats}"),
+                        None => {
+                              write!(f, " {} in use", slab::CACHES[idx].name())
+                        }
+                  }?;
+            }
+            writeln!(f)?;
+
             // Write floppy
             write!(
                   f,
@@ -239,14 +302,18 @@ Floppy drive number: {}
 Floppy init: {}
 Floppyfs init: {}
 Floppy bytes read: {}
-Floppy bytes written: {}",
+Floppy bytes written: {}
+Floppy throughput: {} B/s
+Floppy DMA: {}",
                   self.floppy_offset.as_ref().unwrap_or(&&0),
                   self.floppy_space.as_ref().unwrap_or(&&0),
                   self.floppy_drive,
                   self.fdc_init,
                   self.floppyfs_init,
                   self.floppy_read_bytes,
-                  self.floppy_written_bytes
+                  self.floppy_written_bytes,
+                  self.floppy_throughput.unwrap_or(0),
+                  self.floppy_dma
             )
       }
 }
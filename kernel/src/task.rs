@@ -0,0 +1,45 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/task.rs
+
+    The task module runs the kernel's background activities as futures.
+    This file is responsible for spawning the kernel's built-in tasks.
+
+    Contains 2 submodules:
+    * executor.rs - Polls woken tasks, halting when none are ready
+    * wake.rs - Events fired by IRQs which tasks can wait on
+*/
+
+pub use executor::{SpawnError, block_on, run, spawn};
+
+use crate::startup::ExitCode;
+use crate::{interrupts, time, vga};
+
+pub mod executor;
+pub mod wake;
+
+/// Spawns the tasks which handle the keyboard, keep the screen up to date and
+/// run deferred timers.
+pub fn spawn_tasks() -> ExitCode<SpawnError> {
+      exit_on_err!(spawn(interrupts::kbd_task()));
+      exit_on_err!(spawn(vga::refresh_task()));
+      exit_on_err!(spawn(time::timer::timer_task()));
+      ExitCode::Ok
+}
@@ -0,0 +1,206 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/task/executor.rs
+
+    A cooperative executor which polls tasks once they've been woken, and idles
+    the CPU until the next interrupt when none are.
+    Contained within the task module
+*/
+
+use alloc::boxed::Box;
+use core::pin::{Pin, pin};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use libutil::ExclusiveMap;
+use thiserror::Error;
+
+use crate::{interrupts, time};
+
+/// The most tasks which can exist at once.
+pub const MAX_TASKS: usize = 32;
+
+/// A spawned future, which is polled until it completes.
+struct Task(Pin<Box<dyn Future<Output = ()> + Send>>);
+
+// Safety: Tasks are only stored in `TASKS`, which never hands out `&`
+// references to them
+unsafe impl Sync for Task {}
+
+/// Every spawned task, indexed by it's id.
+///
+/// Only accessed with external interrupts disabled, so that the thread using
+/// it can't be preempted by another which would find it contended.
+static TASKS: ExclusiveMap<[Option<Task>; MAX_TASKS]> =
+      ExclusiveMap::new([const { None }; MAX_TASKS]);
+
+/// Whether each task has been woken since it was last polled.
+static WOKEN: [AtomicBool; MAX_TASKS] =
+      [const { AtomicBool::new(false) }; MAX_TASKS];
+
+/// The id of the task being polled, as it's slot is empty while it is.
+static RUNNING: AtomicUsize = AtomicUsize::new(NOT_RUNNING);
+
+/// Stored in [`RUNNING`] when no task is being polled.
+const NOT_RUNNING: usize = usize::MAX;
+
+/// The vtable of every waker given to tasks, their data is the task's id.
+static VTABLE: RawWakerVTable =
+      RawWakerVTable::new(clone_waker, wake_raw, wake_raw, drop_waker);
+
+/// Adds `future` to the list of tasks, returning it's id.
+pub fn spawn(
+      future: impl Future<Output = ()> + Send + 'static,
+) -> Result<usize, SpawnError> {
+      let future = Box::pin(future);
+      let id = interrupts::without_interrupts(|| {
+            TASKS.map(|tasks| {
+                  let running = RUNNING.load(Ordering::Relaxed);
+                  let id = (0..MAX_TASKS)
+                        .find(|&id| tasks[id].is_none() && id != running)?;
+                  tasks[id] = Some(Task(future));
+                  Some(id)
+            })
+      })
+      .ok_or(SpawnError::Contended)?
+      .ok_or(SpawnError::Full)?;
+
+      dbg_info!("spawned task {id}");
+      wake(id);
+      Ok(id)
+}
+
+/// Marks task `id` as ready to be polled.
+///
+/// Safe to call from IRQ handlers.
+pub fn wake(id: usize) {
+      if let Some(woken) = WOKEN.get(id) {
+            woken.store(true, Ordering::Release)
+      }
+}
+
+/// Returns the id of the task `waker` wakes, if it was created by the
+/// executor.
+pub fn task_id(waker: &Waker) -> Option<usize> {
+      (waker.vtable() == &VTABLE).then(|| waker.data() as usize)
+}
+
+/// Polls woken tasks forever, idling until the next interrupt whenever none
+/// are woken.
+pub fn run() -> ! {
+      loop {
+            interrupts::pet_watchdog();
+            for (id, woken) in WOKEN.iter().enumerate() {
+                  if woken.swap(false, Ordering::Acquire) {
+                        poll(id)
+                  }
+            }
+
+            // Check with interrupts cleared so an IRQ can't wake a task
+            // between checking and halting
+            interrupts::cli();
+            if WOKEN.iter().any(|w| w.load(Ordering::Acquire)) {
+                  interrupts::sti();
+            } else {
+                  time::tickless::idle()
+            }
+      }
+}
+
+/// Polls `future` until it completes, halting in between polls.
+///
+/// Never returns if external interrupts are disabled and `future` doesn't
+/// complete on it's first poll.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+      let mut future = pin!(future);
+      let mut cx = Context::from_waker(Waker::noop());
+      loop {
+            if let Poll::Ready(res) = future.as_mut().poll(&mut cx) {
+                  return res;
+            }
+            interrupts::hlt();
+      }
+}
+
+/// Polls task `id`, removing it if it completes.
+fn poll(id: usize) {
+      let task = interrupts::without_interrupts(|| {
+            TASKS.map(|tasks| tasks[id].take()).flatten()
+      });
+      let Some(mut task) = task else {
+            return;
+      };
+
+      // Safety: The vtable's functions don't care about the data
+      let waker =
+            unsafe { Waker::from_raw(RawWaker::new(id as *const (), &VTABLE)) };
+      let mut cx = Context::from_waker(&waker);
+
+      RUNNING.store(id, Ordering::Relaxed);
+      let res = task.0.as_mut().poll(&mut cx);
+      RUNNING.store(NOT_RUNNING, Ordering::Relaxed);
+
+      if res.is_ready() {
+            dbg_info!("task {id} finished");
+      } else if interrupts::without_interrupts(|| {
+            TASKS.map(|tasks| tasks[id] = Some(task)).is_none()
+      }) {
+            PANIC!(badbug "Task {id} was lost as the task list was in use")
+      }
+}
+
+fn clone_waker(data: *const ()) -> RawWaker {
+      RawWaker::new(data, &VTABLE)
+}
+
+fn wake_raw(data: *const ()) {
+      wake(data as usize)
+}
+
+fn drop_waker(_: *const ()) {}
+
+/// An error created when spawning a task.
+#[derive(Error, Debug)]
+pub enum SpawnError {
+      #[error("already running the max of {MAX_TASKS} tasks")]
+      Full,
+
+      #[error("the task list is being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that spawned tasks are woken and waking only works for valid
+      /// ids.
+      #[test_case]
+      fn spawned_tasks_are_woken() {
+            let id = spawn(async {}).unwrap();
+            assert!(WOKEN[id].swap(false, Ordering::Relaxed));
+            poll(id);
+            assert!(TASKS.map(|t| t[id].is_none()).unwrap());
+
+            wake(MAX_TASKS); // shouldn't panic
+            wake(id);
+            assert!(WOKEN[id].swap(false, Ordering::Relaxed));
+      }
+}
@@ -0,0 +1,149 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/task/wake.rs
+
+    Events fired by IRQ handlers which wake the tasks waiting on them.
+    Contained within the task module
+*/
+
+use core::pin::Pin;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use super::executor::{self, MAX_TASKS};
+use crate::time::tickless;
+
+/// Fired every time the kernel ticks.
+///
+/// Tasks waiting on it should call [`tickless::wake_by`], otherwise they may
+/// not be woken until ticks restart.
+pub static TIMER: IrqEvent = IrqEvent::new();
+
+/// Fired every time the keyboard sends a scancode.
+pub static KEYBOARD: IrqEvent = IrqEvent::new();
+
+/// Fired every time the floppy controller raises an IRQ.
+pub static FLOPPY: IrqEvent = IrqEvent::new();
+
+// Each task needs a bit in `IrqEvent::waiting`
+const _: () = assert!(MAX_TASKS <= u64::BITS as usize);
+
+/// An event which can be waited on by tasks.
+pub struct IrqEvent {
+      /// The number of times the event has fired.
+      fired:   AtomicU64,
+      /// A bitmap of the ids of the tasks waiting on the event.
+      waiting: AtomicU64,
+}
+
+impl IrqEvent {
+      /// Creates a new event which hasn't fired yet.
+      pub const fn new() -> Self {
+            IrqEvent {
+                  fired:   AtomicU64::new(0),
+                  waiting: AtomicU64::new(0),
+            }
+      }
+
+      /// Wakes every task waiting on the event.
+      pub fn fire(&self) {
+            self.fired.fetch_add(1, Ordering::Release);
+            let mut waiting = self.waiting.swap(0, Ordering::AcqRel);
+            while waiting != 0 {
+                  executor::wake(waiting.trailing_zeros() as usize);
+                  waiting &= waiting - 1; // clear lowest bit
+            }
+      }
+
+      /// Wakes the task `waker` belongs to the next time the event fires.
+      ///
+      /// Does nothing for wakers not created by the executor, as
+      /// [`executor::block_on`] polls after every interrupt anyway.
+      pub fn register(&self, waker: &Waker) {
+            if let Some(id) = executor::task_id(waker) {
+                  self.waiting.fetch_or(1 << id, Ordering::AcqRel);
+            }
+      }
+
+      /// Returns a future which completes the next time the event fires after
+      /// this is called.
+      pub fn wait(&self) -> IrqWait<'_> {
+            IrqWait {
+                  event: self,
+                  start: self.fired.load(Ordering::Acquire),
+            }
+      }
+}
+
+/// Completes once it's event fires, returned by [`IrqEvent::wait`].
+pub struct IrqWait<'a> {
+      event: &'a IrqEvent,
+      /// How many times the event had fired when this was created.
+      start: u64,
+}
+
+impl Future for IrqWait<'_> {
+      type Output = ();
+
+      fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            let fired =
+                  || self.event.fired.load(Ordering::Acquire) != self.start;
+            if fired() {
+                  return Poll::Ready(());
+            }
+
+            // Check again in case the event fired while registering
+            self.event.register(cx.waker());
+            if fired() {
+                  Poll::Ready(())
+            } else {
+                  Poll::Pending
+            }
+      }
+}
+
+/// Fires the timer event.
+///
+/// Called by the timer handler before sending EOI, and after idling through
+/// skipped ticks.
+#[unsafe(no_mangle)]
+pub extern "sysv64" fn wake_timer() {
+      // The woken tasks tell it when they need waking again once polled
+      tickless::clear_wakes();
+      TIMER.fire()
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+      use crate::task;
+
+      /// Tests that waiting on an event completes once it fires.
+      #[test_case]
+      fn irq_events_complete_when_fired() {
+            static EVENT: IrqEvent = IrqEvent::new();
+            let wait = EVENT.wait();
+            EVENT.fire();
+            task::block_on(wait);
+
+            // The timer fires by itself
+            task::block_on(TIMER.wait());
+      }
+}
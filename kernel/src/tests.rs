@@ -28,6 +28,7 @@ use core::arch::asm;
 
 use uart_16550::SerialPort;
 
+use crate::interrupts;
 use crate::ports::{self, Port};
 
 /// Test functions marked with the `#[test_case]` attribute
@@ -74,12 +75,15 @@ pub fn exit_qemu(error: bool) -> ! {
 pub fn run_tests(tests: &[&dyn Test]) -> ! {
       serial_port1().init();
       println!("\nRunning unit tests...");
-      tests.iter().for_each(|f| f.test());
-
-      // Tests that stack overflows cause a page fault.
-      // Since this 'test' causes a page fault and prevents all other tests
-      // from being run, PANIC! exits QEMU when hitting page faults in test
-      // builds
+      tests.iter().for_each(|f| {
+            interrupts::pet_watchdog();
+            f.test()
+      });
+
+      // Tests that stack overflows are detected by the kernel stack's guard
+      // page. Since this 'test' causes a page fault and prevents all other
+      // tests from being run, PANIC! exits QEMU when hitting stack overflows
+      // in test builds
       loop {
             unsafe { asm!("push rax") }
       }
@@ -0,0 +1,327 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/thread.rs
+
+    The thread module runs kernel threads, switching between them on every
+    timer tick.
+    This file is responsible for spawning, sleeping and joining threads.
+
+    Contains 2 submodules:
+    * sched.rs - Picks which thread runs next and switches to it
+    * switch.rs - Saves and restores the registers of threads
+*/
+
+use alloc::boxed::Box;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+
+use sched::{State, THREADS, Thread};
+use thiserror::Error;
+
+use crate::memory::stacks::{self, StackError};
+use crate::startup::{self, ExitCode};
+use crate::{interrupts, time};
+
+pub mod sched;
+mod switch;
+
+/// The number of pages in each thread's stack.
+const STACK_PAGES: u64 = 4;
+
+/// The function a thread runs.
+type Entry = Box<dyn FnOnce() + Send>;
+
+/// Identifies a thread, reused once the thread has been joined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThreadId(usize);
+
+/// Threads with a higher priority run before lower priority ones, unless the
+/// lower priority ones have been waiting to run for too long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+      /// Only runs when nothing else can.
+      Idle,
+      /// Runs every so often even while the executor's busy.
+      #[cfg_attr(not(test), allow(dead_code))]
+      Low,
+      Normal,
+      #[allow(dead_code)]
+      High,
+}
+
+/// Registers the current thread and spawns the idle thread.
+///
+/// The current thread goes on to run the executor at [`Priority::Normal`], so
+/// tasks are stopped whenever it sleeps or waits on another thread.
+///
+/// # Safety
+/// Only run this once, from the kernel's entry point.
+pub unsafe fn init() -> ExitCode<ThreadError> {
+      let kmain = Thread {
+            priority: Priority::Normal,
+            state:    State::Running,
+            rsp:      0, // set when first switched away from
+            stack:    None,
+            user_rsp: 0,
+            starved:  0,
+      };
+      exit_on_err!(
+            THREADS
+                  .map(|t| t[0] = Some(kmain))
+                  .ok_or(ThreadError::Contended)
+      );
+
+      // Safety: The current thread was just registered
+      unsafe { startup::SCHED_INIT.store(true) }
+
+      exit_on_err!(spawn("idle thread", Priority::Idle, || loop {
+            interrupts::cli();
+            time::tickless::idle()
+      }));
+
+      ExitCode::Ok
+}
+
+/// Spawns a new thread named `name` running `f`.
+///
+/// The thread's slot isn't freed until it's been joined.
+pub fn spawn(
+      name: &'static str, priority: Priority, f: impl FnOnce() + Send + 'static,
+) -> Result<ThreadId, ThreadError> {
+      if !startup::SCHED_INIT.load() {
+            return Err(ThreadError::NoScheduler);
+      }
+
+      let stack = interrupts::without_interrupts(|| {
+            stacks::alloc_stack(name, STACK_PAGES)
+      })?;
+      let entry = Box::into_raw(Box::new(Box::new(f) as Entry));
+
+      let thread = Thread {
+            priority,
+            state: State::Ready,
+            // Safety: The stack was just allocated and isn't used by anything
+            rsp: unsafe { switch::prepare_stack(stack.top, entry) },
+            stack: Some(stack),
+            user_rsp: 0,
+            starved: 0,
+      };
+
+      match interrupts::without_interrupts(|| sched::insert(thread)) {
+            Ok(id) => Ok(ThreadId(id)),
+            Err(e) => {
+                  // Safety: The thread never ran, so nothing else uses these
+                  unsafe {
+                        drop(Box::from_raw(entry));
+                        stacks::free_stack(stack)?;
+                  }
+                  Err(e)
+            }
+      }
+}
+
+/// Lets other threads with the same or higher priority run.
+pub fn yield_now() {
+      if startup::SCHED_INIT.load() {
+            interrupts::without_interrupts(sched::schedule)
+      }
+}
+
+/// Puts the current thread to sleep for `ticks` ticks
+/// (`ticks / KERNEL_TICKS_HZ` seconds).
+///
+/// Halts instead if the scheduler hasn't been initialised yet. Sleeping on the
+/// kernel's main thread stops every task until it wakes, as it runs the
+/// executor, so tasks should use [`time::sleep`] instead.
+pub fn sleep(ticks: u64) {
+      if !startup::PIT_INIT.load() {
+            warn!("thread: attempted sleeping ({ticks}) without a PIT!");
+            return;
+      }
+
+      let target = time::get_time().saturating_add(ticks).saturating_add(1);
+      while time::get_time() < target {
+            if !startup::SCHED_INIT.load() {
+                  interrupts::hlt();
+                  continue;
+            }
+
+            interrupts::without_interrupts(|| {
+                  sched::set_state(State::Sleeping(target));
+                  sched::schedule();
+            });
+      }
+}
+
+/// Polls `future` until it completes, sleeping for a tick in between polls so
+/// that other threads, including the one running the executor, keep running.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+      let mut future = pin!(future);
+      let mut cx = Context::from_waker(Waker::noop());
+      loop {
+            if let Poll::Ready(res) = future.as_mut().poll(&mut cx) {
+                  return res;
+            }
+            sleep(1)
+      }
+}
+
+/// Waits for thread `id` to finish, freeing it's stack.
+pub fn join(id: ThreadId) -> Result<(), ThreadError> {
+      if id == current() {
+            return Err(ThreadError::JoinSelf);
+      }
+
+      loop {
+            let finished = interrupts::without_interrupts(|| {
+                  let res = THREADS.map(|threads| {
+                        let slot = threads
+                              .get_mut(id.0)
+                              .filter(|t| t.is_some())
+                              .ok_or(ThreadError::NoThread)?;
+                        Ok::<_, ThreadError>(
+                              slot.take_if(|t| t.state == State::Finished),
+                        )
+                  });
+
+                  let finished = res.ok_or(ThreadError::Contended)??;
+                  if finished.is_none() {
+                        sched::set_state(State::Joining(id.0));
+                        sched::schedule();
+                  }
+                  Ok::<_, ThreadError>(finished)
+            })?;
+
+            if let Some(thread) = finished {
+                  if let Some(stack) = thread.stack {
+                        // Safety: The thread has finished using it's stack
+                        unsafe { stacks::free_stack(stack)? }
+                  }
+                  return Ok(());
+            }
+      }
+}
+
+/// Returns the id of the current thread.
+pub fn current() -> ThreadId {
+      ThreadId(sched::current())
+}
+
+/// Finishes the current thread, never returning.
+pub fn exit() -> ! {
+      interrupts::cli();
+      sched::set_state(State::Finished);
+      sched::schedule();
+      PANIC!(badbug "Thread {} ran after it finished", sched::current())
+}
+
+/// Ran by new threads after being switched to for the first time.
+#[unsafe(no_mangle)]
+extern "sysv64" fn thread_start(entry: *mut Entry) -> ! {
+      interrupts::sti();
+
+      // Safety: spawn gave the thread ownership of entry
+      let f = unsafe { Box::from_raw(entry) };
+      f();
+      exit()
+}
+
+/// An error created when using threads.
+#[derive(Error, Debug)]
+pub enum ThreadError {
+      #[error(transparent)]
+      Stack(#[from] StackError),
+
+      #[error("the scheduler hasn't been initialised")]
+      NoScheduler,
+
+      #[error("already running the max of {} threads", sched::MAX_THREADS)]
+      Full,
+
+      #[error("there isn't a thread with that id")]
+      NoThread,
+
+      #[error("a thread can't join itself")]
+      JoinSelf,
+
+      #[error("the thread list is being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use alloc::sync::Arc;
+      use core::sync::atomic::{AtomicU64, Ordering};
+
+      use super::*;
+
+      /// Tests that spawned threads run and can be joined.
+      #[test_case]
+      fn threads_run_and_join() {
+            let count = Arc::new(AtomicU64::new(0));
+            let ids = [1, 2, 3].map(|n| {
+                  let count = count.clone();
+                  spawn("test thread", Priority::Normal, move || {
+                        sleep(n);
+                        count.fetch_add(n, Ordering::Relaxed);
+                  })
+                  .unwrap()
+            });
+
+            ids.into_iter().for_each(|id| join(id).unwrap());
+            assert_eq!(count.load(Ordering::Relaxed), 6);
+            assert!(matches!(join(ids[0]), Err(ThreadError::NoThread)));
+            assert!(matches!(join(current()), Err(ThreadError::JoinSelf)));
+      }
+
+      /// Tests that low priority threads still run while the main thread
+      /// never blocks.
+      #[test_case]
+      fn low_priority_threads_run() {
+            let ran = Arc::new(AtomicU64::new(0));
+            let ran2 = ran.clone();
+            let id = spawn("test starved", Priority::Low, move || {
+                  ran2.store(1, Ordering::Relaxed)
+            })
+            .unwrap();
+
+            let end = time::get_time() + 4 * sched::MAX_STARVED as u64;
+            while ran.load(Ordering::Relaxed) == 0 && time::get_time() < end {
+                  core::hint::spin_loop()
+            }
+            assert_eq!(ran.load(Ordering::Relaxed), 1);
+            join(id).unwrap();
+      }
+
+      /// Tests that sleeping lets other threads run.
+      #[test_case]
+      fn sleeping_lets_others_run() {
+            let ran = Arc::new(AtomicU64::new(0));
+            let ran2 = ran.clone();
+            let id = spawn("test spinner", Priority::Low, move || {
+                  ran2.store(time::get_time(), Ordering::Relaxed)
+            })
+            .unwrap();
+
+            sleep(2);
+            assert_ne!(ran.load(Ordering::Relaxed), 0);
+            join(id).unwrap();
+      }
+}
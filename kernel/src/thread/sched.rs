@@ -0,0 +1,253 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/thread/sched.rs
+
+    Round robin scheduling between threads of the same priority, ran on every
+    timer tick and whenever a thread blocks.
+    Contained within the thread module
+*/
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use libutil::ExclusiveMap;
+
+use super::{Priority, ThreadError, switch};
+use crate::memory::stacks::KernelStack;
+use crate::{gdt, interrupts, startup, time};
+
+/// The most threads which can exist at once.
+pub const MAX_THREADS: usize = 32;
+
+/// Every thread, indexed by it's id.
+///
+/// Only accessed with external interrupts disabled, so that a thread is never
+/// switched away from while using it.
+pub static THREADS: ExclusiveMap<[Option<Thread>; MAX_THREADS]> =
+      ExclusiveMap::new([const { None }; MAX_THREADS]);
+
+/// How many times a thread can be passed over for higher priority ones before
+/// it runs anyway, so that lower priority threads aren't starved by the main
+/// thread which never blocks.
+pub const MAX_STARVED: u32 = 8;
+
+/// The id of the running thread.
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+
+/// A kernel thread.
+#[derive(Debug)]
+pub struct Thread {
+      pub priority: Priority,
+      pub state:    State,
+      /// The thread's stack pointer, only valid while it isn't running.
+      pub rsp:      u64,
+      /// The thread's stack, or `None` for the bootloader's stack.
+      pub stack:    Option<KernelStack>,
+      /// Where the thread saved it's kernel state before entering user mode,
+      /// used as `rsp0`, or 0 if it isn't running user code.
+      pub user_rsp: u64,
+      /// How many times the thread has been passed over in a row while able
+      /// to run.
+      pub starved:  u32,
+}
+
+/// What a thread is currently doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+      Ready,
+      Running,
+      /// Waiting for the kernel to have been running for this many ticks.
+      Sleeping(u64),
+      /// Waiting for the thread with this id to finish.
+      Joining(usize),
+      Finished,
+}
+
+/// Adds `thread` to the first free slot, returning it's id.
+pub fn insert(thread: Thread) -> Result<usize, ThreadError> {
+      THREADS
+            .map(|threads| {
+                  let id = threads
+                        .iter()
+                        .position(|t| t.is_none())
+                        .ok_or(ThreadError::Full)?;
+                  threads[id] = Some(thread);
+                  Ok(id)
+            })
+            .ok_or(ThreadError::Contended)?
+}
+
+/// Returns the id of the running thread.
+pub fn current() -> usize {
+      CURRENT.load(Ordering::Relaxed)
+}
+
+/// Sets the state of the running thread.
+///
+/// Must be called with external interrupts disabled.
+pub fn set_state(state: State) {
+      THREADS.map(|threads| {
+            if let Some(thread) = &mut threads[current()] {
+                  thread.state = state
+            }
+      });
+}
+
+/// Returns a pointer to the running thread's `user_rsp`, if it has it's own
+/// stack.
+///
+/// The pointer stays valid for as long as the thread is running.
+pub fn user_rsp_ptr() -> Option<*mut u64> {
+      interrupts::without_interrupts(|| {
+            THREADS.map(|threads| {
+                  let thread = threads[current()].as_mut()?;
+                  thread.stack.is_some().then_some(&raw mut thread.user_rsp)
+            })
+      })
+      .flatten()
+}
+
+/// Switches to the highest priority thread which can run, going round robin
+/// between threads with the same priority.
+///
+/// Must be called with external interrupts disabled.
+pub fn schedule() {
+      let switch = THREADS.map(|threads| {
+            let cur = current();
+            let next = pick_next(threads, cur)?;
+            age(threads, next);
+            let next_thread = threads[next].as_mut()?;
+            next_thread.state = State::Running;
+            if next == cur {
+                  return None;
+            }
+
+            let next_rsp = next_thread.rsp;
+            if next_thread.user_rsp != 0 {
+                  gdt::set_rsp0(next_thread.user_rsp)
+            }
+
+            let prev = threads[cur].as_mut()?;
+            if prev.state == State::Running {
+                  prev.state = State::Ready
+            }
+
+            CURRENT.store(next, Ordering::Relaxed);
+            Some((&raw mut prev.rsp, next_rsp))
+      });
+
+      if let Some(Some((prev_rsp, next_rsp))) = switch {
+            // Safety: Threads are never removed while running, so the previous
+            // thread's slot stays valid until it's switched back to
+            unsafe { switch::switch_context(prev_rsp, next_rsp) }
+      }
+}
+
+/// Returns the tick the next sleeping thread wakes on, or `None` if none are
+/// sleeping.
+///
+/// Returns the current tick if another thread is waiting to be preempted to,
+/// or the thread list is being used somewhere else.
+///
+/// Must be called with external interrupts disabled.
+pub fn next_wakeup() -> Option<u64> {
+      let now = time::get_time();
+      THREADS
+            .map(|threads| {
+                  let mut next = None;
+                  for (id, thread) in threads.iter().enumerate() {
+                        let Some(thread) = thread else { continue };
+                        if let State::Sleeping(target) = thread.state {
+                              next =
+                                    Some(next.map_or(target, |n: u64| {
+                                          n.min(target)
+                                    }));
+                        } else if id != current() &&
+                              thread.priority != Priority::Idle &&
+                              runnable(threads, thread.state)
+                        {
+                              return Some(now);
+                        }
+                  }
+                  next
+            })
+            .unwrap_or(Some(now))
+}
+
+/// Ran by the timer handler after sending EOI.
+#[unsafe(no_mangle)]
+extern "sysv64" fn preempt() {
+      if startup::SCHED_INIT.load() {
+            schedule()
+      }
+}
+
+/// Returns the id of the highest priority thread which can run, checking the
+/// threads after `cur` first.
+///
+/// Threads which have been starved for [`MAX_STARVED`] schedules run first.
+fn pick_next(
+      threads: &[Option<Thread>; MAX_THREADS], cur: usize,
+) -> Option<usize> {
+      let mut best: Option<(usize, Priority)> = None;
+      for id in (1..=MAX_THREADS).map(|n| (cur + n) % MAX_THREADS) {
+            let Some(thread) = &threads[id] else { continue };
+            if !runnable(threads, thread.state) {
+                  continue;
+            } else if thread.starved >= MAX_STARVED {
+                  return Some(id);
+            } else if best.is_none_or(|(_, p)| thread.priority > p) {
+                  best = Some((id, thread.priority))
+            }
+      }
+      best.map(|(id, _)| id)
+}
+
+/// Counts the schedules each thread other than `next` which could've ran was
+/// passed over for.
+///
+/// Idle threads are never counted, as they should only run when nothing else
+/// can.
+fn age(threads: &mut [Option<Thread>; MAX_THREADS], next: usize) {
+      for id in 0..MAX_THREADS {
+            let able = threads[id]
+                  .as_ref()
+                  .is_some_and(|t| runnable(threads, t.state));
+            let Some(thread) = &mut threads[id] else {
+                  continue;
+            };
+            if id == next || !able || thread.priority == Priority::Idle {
+                  thread.starved = 0;
+            } else {
+                  thread.starved += 1;
+            }
+      }
+}
+
+/// Returns whether a thread in `state` is able to run.
+fn runnable(threads: &[Option<Thread>; MAX_THREADS], state: State) -> bool {
+      match state {
+            State::Ready | State::Running => true,
+            State::Sleeping(target) => time::get_time() >= target,
+            State::Joining(id) => threads[id]
+                  .as_ref()
+                  .is_none_or(|t| t.state == State::Finished),
+            State::Finished => false,
+      }
+}
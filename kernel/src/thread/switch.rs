@@ -0,0 +1,78 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/thread/switch.rs
+
+    Saves and restores the registers of threads when switching between them.
+    Caller-saved registers are saved by whatever calls `switch_context`,
+    either the timer handler or the compiler.
+    Contained within the thread module
+*/
+
+use core::arch::naked_asm;
+
+use super::Entry;
+
+/// The number of registers `switch_context` pops, including the return
+/// address.
+const SAVED_REGS: usize = 7;
+
+/// Saves the callee-saved registers and stack pointer of the current thread to
+/// `prev`, then switches to the stack at `next` and restores it's registers.
+///
+/// # Safety
+/// `next` must be a stack saved by this function or [`prepare_stack`], and
+/// external interrupts must be disabled.
+#[unsafe(naked)]
+pub unsafe extern "sysv64" fn switch_context(prev: *mut u64, next: u64) {
+      naked_asm!(
+            "push rbp", "push rbx", "push r12", "push r13", "push r14",
+            "push r15",
+            "mov [rdi], rsp", // save the current thread's stack
+            "mov rsp, rsi",   // and switch to the next's
+            "pop r15", "pop r14", "pop r13", "pop r12", "pop rbx", "pop rbp",
+            "ret",
+      )
+}
+
+/// Sets up the stack ending at `top` so that switching to it runs `entry`,
+/// returning the stack pointer to switch to.
+///
+/// # Safety
+/// `top` must be the 16 byte aligned top of an unused stack.
+pub unsafe fn prepare_stack(top: u64, entry: *mut Entry) -> u64 {
+      let mut regs = [0; SAVED_REGS]; // r15, r14, r13, r12, rbx, rbp, ret
+      regs[3] = entry as u64;
+      regs[6] = thread_entry as *const () as u64;
+
+      let rsp = top - (SAVED_REGS * size_of::<u64>()) as u64;
+      // Safety: The caller ensures the stack is big enough and unused
+      unsafe { (rsp as *mut [u64; SAVED_REGS]).write(regs) };
+      rsp
+}
+
+/// Returned to by `switch_context` when a thread is first switched to.
+#[unsafe(naked)]
+extern "sysv64" fn thread_entry() -> ! {
+      naked_asm!(
+            "mov rdi, r12",      // entry as first arg
+            "call thread_start", // in thread.rs
+            "ud2",               // thread_start never returns
+      )
+}
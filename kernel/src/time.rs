@@ -19,54 +19,149 @@
 /*!
     kernel/src/time.rs
 
-    Handles the i8253/i8254 PIT and the RTC
+    The time module keeps track of time and runs callbacks once it passes.
+    This file is responsible for handling the i8253/i8254 PIT and syncing the
+    wall-clock time with the RTC.
+
+    Contains 5 submodules:
+    * clock.rs - A monotonic clock with nanosecond resolution
+    * hpet.rs - High Precision Event Timer driver
+    * rtc.rs - CMOS real-time clock driver
+    * tickless.rs - Stops kernel ticks while the CPU is idle
+    * timer.rs - A timer wheel running one shot and periodic callbacks
 */
 
 use core::arch::naked_asm;
 use core::fmt::Display;
-use core::sync::atomic::{AtomicBool, AtomicU16, Ordering};
+use core::pin::Pin;
+use core::sync::atomic::{
+      AtomicBool, AtomicU8, AtomicU16, AtomicU64, Ordering,
+};
+use core::task::{Context, Poll};
 use core::{hint, ptr};
 
 use libutil::InitLater;
 use thiserror::Error;
 
-use crate::interrupts;
 use crate::ports::{self, Port};
 use crate::startup::{self, ExitCode};
+use crate::task::wake;
 use crate::vga::print::{Color, Corner, VGAChar};
+use crate::{interrupts, thread};
+
+pub mod clock;
+pub mod hpet;
+pub mod rtc;
+pub mod tickless;
+pub mod timer;
+
+pub use clock::Instant;
+pub use rtc::{init as setup_rtc_int, read_cmos_reg};
 
 /// The base frequency of the PIT.
 pub const PIT_BASE_FREQ: u64 = 1193180;
 
+/// How many kernel ticks we want per second, set by `SFK_TICKS_HZ` when
+/// building.
+pub const KERNEL_TICKS_HZ: u64 = crate::env_as_int!("SFK_TICKS_HZ", u64);
+
+// The PIT's count has to fit in 16 bits, and the watchdog checks every tenth
+// of a second
+const _: () = assert!(
+      KERNEL_TICKS_HZ >= 20 && KERNEL_TICKS_HZ <= 1000,
+      "SFK_TICKS_HZ must be between 20 and 1000"
+);
+
+/// The PIT channel 0 count which raises a tick every `1 / KERNEL_TICKS_HZ`
+/// seconds.
+const TICK_INTERVAL: u16 =
+      ((PIT_BASE_FREQ + KERNEL_TICKS_HZ / 2) / KERNEL_TICKS_HZ) as u16;
+
+/// How many ticks the kernel has been running for, read by [`get_time`].
+#[unsafe(no_mangle)]
+static TIME: AtomicU64 = AtomicU64::new(0);
+
+/// The timer which should raise kernel ticks, set by `SFK_TIMER` when
+/// building. The PIT keeps ticking if it can't be started.
+pub const PREFERRED_TICKS: TickSource =
+      match crate::env_as_int!("SFK_TIMER", u8) {
+            1 => TickSource::Apic,
+            2 => TickSource::Hpet,
+            _ => TickSource::Pit,
+      };
+
+/// The timer currently raising kernel ticks, as a [`TickSource`].
+static TICK_SOURCE: AtomicU8 = AtomicU8::new(TickSource::Pit as u8);
+
+/// A timer which can raise kernel ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TickSource {
+      /// Channel 0 of the PIT, set up by [`set_timer_interval`].
+      Pit  = 0,
+      /// The local APIC's timer.
+      Apic = 1,
+      /// Comparator 0 of the HPET.
+      Hpet = 2,
+}
+
 /// The time the kernel was launched.
 pub static LAUNCH_TIME: InitLater<Time> = InitLater::uninit();
 
 /// Whether the time has been loaded into `LAUNCH_TIME` or not.
 static RTC_SYNC_DONE: AtomicBool = AtomicBool::new(false);
 
-/// CMOS register B.
-const CMOS_REG_B: u8 = 0x8B;
+/// The number of ticks between the Unix epoch and when the kernel launched,
+/// updated each time the RTC is synced.
+///
+/// Adding [`get_time`] gives the current time in ticks since the epoch.
+static EPOCH_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// How often the RTC is resynced with, in seconds.
+const RESYNC_SECS: u64 = 60;
 
 /// The waiting character is only able to be toggled when this static is.
 pub static WAITING_CHAR: AtomicBool = AtomicBool::new(true);
 
-/// Sets the timer interval in channel 0 to 10 ms.
-#[rustfmt::skip]
+/// Sets the timer interval in channel 0 to [`KERNEL_TICKS_HZ`].
 pub fn set_timer_interval() -> ExitCode<&'static str> {
-      /// How many kernel ticks we want per second.
-      const KERNEL_TICKS_HZ: u64 = 100;
-
-      const TICK_INTERVAL: u16 =
-         ((PIT_BASE_FREQ + KERNEL_TICKS_HZ/2) / KERNEL_TICKS_HZ) as u16;
-
-      /// Binary mode, square wave, low & high byte, channel 0
-      const COMMAND: u8 = 0b0_111_11_00;
-
       if !startup::PIC_INIT.load() {
             return ExitCode::Error("The PIC isn't init!");
       }
 
       interrupts::sti();
+      start_pit();
+
+      // Safety: Was just initialised above
+      unsafe { startup::PIT_INIT.store(true) }
+
+      ExitCode::Infallible
+}
+
+/// Returns the timer currently raising kernel ticks.
+pub fn tick_source() -> TickSource {
+      match TICK_SOURCE.load(Ordering::Relaxed) {
+            1 => TickSource::Apic,
+            2 => TickSource::Hpet,
+            _ => TickSource::Pit,
+      }
+}
+
+/// Records that `source` now raises kernel ticks, after it's been started and
+/// the previous source stopped.
+pub fn set_tick_source(source: TickSource) {
+      TICK_SOURCE.store(source as u8, Ordering::Relaxed);
+}
+
+/// Interrupt on terminal count, low & high byte, channel 0, which doesn't
+/// start counting until given a count.
+const PIT_ONE_SHOT: u8 = 0b00_11_000_0;
+
+/// Makes PIT channel 0 raise a tick every [`TICK_INTERVAL`] cycles.
+#[rustfmt::skip]
+fn start_pit() {
+      /// Binary mode, square wave, low & high byte, channel 0
+      const COMMAND: u8 = 0b0_111_11_00;
 
       // Safety: Sending valid command
       unsafe {
@@ -74,24 +169,61 @@ pub fn set_timer_interval() -> ExitCode<&'static str> {
             ports::writeb(Port::PITChannel0, TICK_INTERVAL as u8); // low byte
             ports::writeb(Port::PITChannel0, (TICK_INTERVAL >> 8) as u8); // high byte
       }
+}
 
-      // Safety: Was just initialised above
-      unsafe { startup::PIT_INIT.store(true) }
+/// Makes PIT channel 0 raise a single tick after `nanos` nanoseconds, or as
+/// many as fit in it's 16 bit count.
+fn one_shot_pit(nanos: u64) {
+      let count = nanos * TICK_INTERVAL as u64 / clock::NANOS_PER_TICK;
+      let count = count.min(u16::MAX as u64) as u16;
 
-      ExitCode::Infallible
+      // Safety: Channel 0 only raises kernel ticks
+      unsafe {
+            ports::writeb(Port::PITCmd, PIT_ONE_SHOT);
+            ports::writeb(Port::PITChannel0, count as u8); // low byte
+            ports::writeb(Port::PITChannel0, (count >> 8) as u8); // high byte
+      }
+}
+
+/// Stops PIT channel 0 from raising IRQ 0, so that another timer can use the
+/// line without ticks being counted twice.
+pub fn stop_pit() {
+      // Safety: Channel 0 only raises IRQ 0, which is taken over by the caller
+      unsafe { ports::writeb(Port::PITCmd, PIT_ONE_SHOT) }
 }
 
 /// Returns how many ticks the kernel has been running for.
-/// Increases every 10 ms or 100 Hz.
+/// Increases [`KERNEL_TICKS_HZ`] times a second.
 #[unsafe(naked)]
 pub extern "sysv64" fn get_time() -> u64 {
-      #[unsafe(no_mangle)]
-      static mut TIME: u64 = 0;
-
       // Safety: Just checking the time
       naked_asm!("mov rax, [TIME]", "ret")
 }
 
+/// Moves the time forward a tick, or by however many ticks passed while they
+/// were stopped by [`tickless::idle`].
+///
+/// Called by the timer handler before running timers.
+#[unsafe(no_mangle)]
+extern "sysv64" fn tick() {
+      let ticks = tickless::resume().unwrap_or(1);
+      tickless::advance(ticks.max(1))
+}
+
+/// Returns the current wall-clock time, or `None` if the RTC hasn't been synced
+/// yet.
+///
+/// Derived from the last RTC sync plus the ticks since then, so it only moves
+/// in whole seconds.
+pub fn now() -> Option<Time> {
+      if !RTC_SYNC_DONE.load(Ordering::Relaxed) {
+            return None;
+      }
+
+      let ticks = EPOCH_TICKS.load(Ordering::Relaxed) + get_time();
+      Time::from_unix((ticks / KERNEL_TICKS_HZ) as i64)
+}
+
 /// Toggles the waiting character on or off.
 pub fn set_waiting_char(show: bool) {
       if !WAITING_CHAR.load(Ordering::Relaxed) {
@@ -119,9 +251,12 @@ pub fn set_waiting_char(show: bool) {
       }
 }
 
-/// Waits for `ticks` ticks (`ticks / 100` seconds).
+/// Waits for `ticks` ticks (`ticks / KERNEL_TICKS_HZ` seconds), letting other
+/// threads run in the meantime.
 ///
-/// Never returns if external interrupts are disabled.
+/// Never returns if external interrupts are disabled before the scheduler has
+/// been initialised. Every task is stopped while the kernel's main thread
+/// waits, as it runs the executor.
 pub fn wait(ticks: u64) {
       if !startup::PIT_INIT.load() {
             warn!("pit: attempted waiting ({ticks}) without a PIT!");
@@ -129,22 +264,58 @@ pub fn wait(ticks: u64) {
       }
 
       set_waiting_char(true);
+      thread::sleep(ticks);
+      set_waiting_char(false);
+}
 
-      // wait...
-      let target_time = get_time() + ticks + 1;
-      while get_time() < target_time {
-            interrupts::hlt();
+/// Returns a future which completes after `ticks` ticks
+/// (`ticks / KERNEL_TICKS_HZ` seconds), without blocking other tasks.
+pub fn sleep(ticks: u64) -> Sleep {
+      Sleep {
+            target: get_time() + ticks + 1,
       }
+}
 
-      set_waiting_char(false);
+/// Completes once the kernel has been running for `target` ticks, returned by
+/// [`sleep`].
+pub struct Sleep {
+      target: u64,
+}
+
+impl Future for Sleep {
+      type Output = ();
+
+      fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if !startup::PIT_INIT.load() || get_time() >= self.target {
+                  return Poll::Ready(());
+            }
+
+            // Check again in case the timer ticked while registering
+            tickless::wake_by(self.target);
+            wake::TIMER.register(cx.waker());
+            if get_time() >= self.target {
+                  Poll::Ready(())
+            } else {
+                  Poll::Pending
+            }
+      }
 }
 
 /// The century the kernel was complied.
 /// Only updated when the kernel is built so isn't too precise.
 const CENTURY: u16 = crate::env_as_int!("SFK_TIME_CENTURY", u16);
 
+/// The number of days between 0000-03-01 and the Unix epoch.
+const EPOCH_DAYS: i64 = 719468;
+
+/// The number of days in a 400 year cycle of the Gregorian calendar.
+const ERA_DAYS: i64 = 146097;
+
+/// The number of seconds in a day.
+const DAY_SECS: i64 = 86400;
+
 /// Second-precise time value.
-#[derive(Debug, Default, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Time {
       /// The current year, 0-65535
       year:  u16,
@@ -160,69 +331,182 @@ pub struct Time {
       sec:   u8,
 }
 
+/// A day of the week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weekday {
+      Monday,
+      Tuesday,
+      Wednesday,
+      Thursday,
+      Friday,
+      Saturday,
+      Sunday,
+}
+
 impl Time {
-      /// Returns the current time in the RTC.
-      fn now() -> Self {
-            // Safety: Reading from valid registers.
-            unsafe {
-                  Time {
-                        year:  read_cmos_reg(0x9) as u16,
-                        month: read_cmos_reg(0x8),
-                        day:   read_cmos_reg(0x7),
-                        hour:  read_cmos_reg(0x4),
-                        min:   read_cmos_reg(0x2),
-                        sec:   read_cmos_reg(0x0),
+      /// Returns the time `secs` seconds after the Unix epoch, or `None` if
+      /// it's outside of years 0-65535.
+      ///
+      /// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+      pub fn from_unix(secs: i64) -> Option<Self> {
+            let days = secs.div_euclid(DAY_SECS) + EPOCH_DAYS;
+            let day_secs = secs.rem_euclid(DAY_SECS);
+
+            // Years start in March, so the leap day is the last day
+            let era = days.div_euclid(ERA_DAYS);
+            let era_day = days.rem_euclid(ERA_DAYS);
+            let era_year = (era_day - era_day / 1460 + era_day / 36524 -
+                  era_day / 146096) /
+                  365;
+            let year_day =
+                  era_day - (365 * era_year + era_year / 4 - era_year / 100);
+            let month_idx = (5 * year_day + 2) / 153; // from March
+            let day = year_day - (153 * month_idx + 2) / 5 + 1;
+            let month = if month_idx < 10 {
+                  month_idx + 3
+            } else {
+                  month_idx - 9
+            };
+            let year = era * 400 + era_year + (month <= 2) as i64;
+
+            Some(Time {
+                  year:  u16::try_from(year).ok()?,
+                  month: month as u8,
+                  day:   day as u8,
+                  hour:  (day_secs / 3600) as u8,
+                  min:   (day_secs / 60 % 60) as u8,
+                  sec:   (day_secs % 60) as u8,
+            })
+      }
+
+      /// Parses a time formatted in ISO 8601 like it's displayed, e.g.
+      /// `2026-02-01T09:05:03`, returning `None` if it's malformed or invalid.
+      pub fn from_iso(iso: &str) -> Option<Self> {
+            let bytes = iso.as_bytes();
+            if bytes.len() != 19 {
+                  return None;
+            }
+
+            // Parses the digits in start..end, which have to be followed by
+            // sep unless they're at the end
+            let field = |start: usize, end: usize, sep: u8| {
+                  if bytes.get(end).is_some_and(|&b| b != sep) {
+                        return None;
                   }
+                  let digits = iso.get(start..end)?;
+                  if !digits.bytes().all(|b| b.is_ascii_digit()) {
+                        return None;
+                  }
+                  digits.parse::<u16>().ok()
+            };
+
+            let time = Time {
+                  year:  field(0, 4, b'-')?,
+                  month: field(5, 7, b'-')? as u8,
+                  day:   field(8, 10, b'T')? as u8,
+                  hour:  field(11, 13, b':')? as u8,
+                  min:   field(14, 16, b':')? as u8,
+                  sec:   field(17, 19, 0)? as u8,
+            };
+            time.is_valid().then_some(time)
+      }
+
+      /// Returns the number of seconds since the Unix epoch, which is negative
+      /// before 1970.
+      ///
+      /// See http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+      pub fn to_unix(self) -> i64 {
+            let month = self.month as i64;
+            let year = self.year as i64 - (month <= 2) as i64;
+            let era = year.div_euclid(400);
+            let era_year = year.rem_euclid(400);
+            let month_idx = if month > 2 { month - 3 } else { month + 9 };
+            let year_day = (153 * month_idx + 2) / 5 + self.day as i64 - 1;
+            let era_day =
+                  era_year * 365 + era_year / 4 - era_year / 100 + year_day;
+            let days = era * ERA_DAYS + era_day - EPOCH_DAYS;
+
+            days * DAY_SECS +
+                  self.hour as i64 * 3600 +
+                  self.min as i64 * 60 +
+                  self.sec as i64
+      }
+
+      /// Returns the day of the week.
+      pub fn weekday(&self) -> Weekday {
+            const DAYS: [Weekday; 7] = [
+                  Weekday::Monday,
+                  Weekday::Tuesday,
+                  Weekday::Wednesday,
+                  Weekday::Thursday,
+                  Weekday::Friday,
+                  Weekday::Saturday,
+                  Weekday::Sunday,
+            ];
+
+            // The epoch was on a Thursday
+            let days = self.to_unix().div_euclid(DAY_SECS);
+            DAYS[(days + 3).rem_euclid(7) as usize]
+      }
+
+      /// Returns the number of days in the time's month.
+      pub fn days_in_month(&self) -> u8 {
+            match self.month {
+                  2 if is_leap_year(self.year) => 29,
+                  2 => 28,
+                  4 | 6 | 9 | 11 => 30,
+                  _ => 31,
             }
       }
+
+      /// Returns whether every field is in range, including the day being in
+      /// the month.
+      pub fn is_valid(&self) -> bool {
+            (1..=12).contains(&self.month) &&
+                  (1..=self.days_in_month()).contains(&self.day) &&
+                  self.hour < 24 &&
+                  self.min < 60 &&
+                  self.sec < 60
+      }
 }
 
+/// Returns whether `year` has a 29th of February.
+pub const fn is_leap_year(year: u16) -> bool {
+      year.is_multiple_of(4) &&
+            (!year.is_multiple_of(100) || year.is_multiple_of(400))
+}
+
+/// Formats the time in ISO 8601, e.g. `2026-02-01T09:05:03`.
 impl Display for Time {
       fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
             write!(
                   f,
-                  "{}:{}:{} {}/{}/{}",
+                  "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                  self.year,
+                  self.month,
+                  self.day,
                   self.hour,
                   self.min,
-                  self.sec,
-                  self.day,
-                  self.month,
-                  self.year
+                  self.sec
             )
       }
 }
 
-/// Returns the current value of CMOS register `reg`.
-/// # Safety
-/// Reads and writes to I/O ports.
-pub unsafe fn read_cmos_reg(reg: u8) -> u8 {
-      unsafe {
-            ports::writeb(Port::CMOSIndex, reg);
-            ports::readb(Port::CMOSData)
+impl Display for TickSource {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            let name = match self {
+                  TickSource::Pit => "PIT",
+                  TickSource::Apic => "LAPIC timer",
+                  TickSource::Hpet => "HPET",
+            };
+            write!(f, "{name}")
       }
 }
 
-/// Sets up RTC interrupts in IRQ 8.
-pub fn setup_rtc_int() -> ExitCode<&'static str> {
-      if !startup::PIC_INIT.load() {
-            return ExitCode::Error("The PIC isn't init!");
-      }
-
-      interrupts::cli();
-
-      // Set bit 6 in register B to enable interrupts.
-      // Safety: Sending a valid command with external interrupts disabled
-      unsafe {
-            let prev = read_cmos_reg(CMOS_REG_B);
-            ports::writeb(Port::CMOSIndex, CMOS_REG_B);
-            ports::writeb(Port::CMOSData, prev | 0b1000000);
+impl Display for Weekday {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            core::fmt::Debug::fmt(self, f)
       }
-
-      // Safety: Just enabled it above!
-      unsafe { startup::RTC_IRQ_INIT.store(true) }
-
-      interrupts::sti();
-      ExitCode::Infallible
 }
 
 /// Waits for the RTC sync to finish then checks if `LAUNCH_TIME` has been
@@ -255,52 +539,48 @@ pub enum RtcSyncWaitError {
       NoStatic(u8),
 }
 
-/// Ran by RTC handler when the update ended interrupt occurs,
-/// stores the current time into [`LAUNCH_TIME`].
+/// Syncs the time once the RTC has finished updating, and every
+/// [`RESYNC_SECS`] updates after that.
 ///
-/// See https://wiki.osdev.org/CMOS#The_Real-Time_Clock
-#[unsafe(no_mangle)]
-extern "sysv64" fn sync_time_to_rtc() {
-      /// The 24 hour time / 12 hour time flag in the hours value.
-      const FLAG_24_HR: u8 = 0b10000000;
-
-      let mut time = Time::now();
-      let reg_b = unsafe { read_cmos_reg(CMOS_REG_B) };
-      let mut hour = time.hour;
-
-      // If BCD mode (bit 2 clear), convert values to binary using the formula
-      // Binary = ((BCD / 16) * 10) + (BCD & 0xF)
-      if reg_b != reg_b | 0b100 {
-            time.sec = bcd_to_bin(time.sec);
-            time.min = bcd_to_bin(time.min);
-            time.day = bcd_to_bin(time.day);
-            time.month = bcd_to_bin(time.month);
-            time.year = bcd_to_bin(time.year as u8) as u16;
-
-            // Preserve 24 hour flag
-            hour = ((hour & 0x0F) + (((hour & 0x70) / 16) * 10)) |
-                  (hour & FLAG_24_HR);
+/// Called by the RTC's IRQ each time it raises the update ended interrupt.
+fn rtc_updated() {
+      /// The number of updates since the last sync.
+      static UPDATES: AtomicU64 = AtomicU64::new(0);
+
+      if UPDATES
+            .fetch_add(1, Ordering::Relaxed)
+            .is_multiple_of(RESYNC_SECS)
+      {
+            sync_time_to_rtc();
       }
+}
 
-      // If 12 hour time (bit 1 clear and flag set)
-      if (reg_b != reg_b | 0b10) && (hour == hour & FLAG_24_HR) {
-            // Clear 24 / 12 hour flag and convert to 24 hour time
-            time.hour = ((hour & 0b1111111) + 12) % 24;
-      }
+/// Stores the current time in the RTC into [`EPOCH_TICKS`], and the first
+/// time into [`LAUNCH_TIME`].
+fn sync_time_to_rtc() {
+      let time = rtc::read_time();
 
-      time.year += CENTURY * 100;
+      // The update just ended, so the second started on this tick. Reads
+      // which happened mid-update are skipped until the next resync
+      if time.is_valid() {
+            set_epoch(time);
+      }
 
-      // Ignore possible error as wait_for_rtc_sync checks this later
+      // Ignore possible error as wait_for_rtc_sync checks this later, and
+      // since it's already set after the first sync
       _ = LAUNCH_TIME.init(time);
       RTC_SYNC_DONE.store(true, Ordering::Relaxed);
+}
 
-      fn bcd_to_bin(bcd: u8) -> u8 {
-            ((bcd / 16) * 10) + (bcd & 0xF)
-      }
+/// Makes [`now`] return `time` at the current tick.
+fn set_epoch(time: Time) {
+      let ticks = time.to_unix() as u64 * KERNEL_TICKS_HZ - get_time();
+      EPOCH_TICKS.store(ticks, Ordering::Relaxed);
 }
 
 #[cfg(test)]
 mod tests {
+      use super::clock::Duration;
       use super::*;
       use crate::speaker;
 
@@ -311,9 +591,22 @@ mod tests {
             // starting waiting
             wait(1);
 
-            let time = get_time();
+            let start = Instant::now();
             wait(15);
-            assert!(get_time() - 15 - time < 3) // less than 3 tick difference
+            let elapsed = start.elapsed();
+            // sleep waits for an extra tick, so less than a tick of difference
+            // from 16 ticks
+            let tick = clock::NANOS_PER_TICK;
+            assert!(elapsed >= Duration::from_nanos(15 * tick));
+            assert!(elapsed < Duration::from_nanos(16 * tick + tick / 2));
+      }
+
+      /// Tests that `sleep` waits for at least the correct amount of time.
+      #[test_case]
+      fn sleep_waits_for_correct_time() {
+            let time = get_time();
+            crate::task::block_on(sleep(5));
+            assert!(get_time() - time >= 5)
       }
 
       /// Tests that `wait` & `play_special` immediately return if the PIT
@@ -330,6 +623,83 @@ mod tests {
             unsafe { startup::PIT_INIT.store(init) }
       }
 
+      /// Tests that Unix timestamps convert to and from the right times,
+      /// including around leap days and before the epoch.
+      #[test_case]
+      fn unix_time_round_trips() {
+            let time = |year, month, day, hour, min, sec| Time {
+                  year,
+                  month,
+                  day,
+                  hour,
+                  min,
+                  sec,
+            };
+
+            let cases = [
+                  (0, time(1970, 1, 1, 0, 0, 0)),
+                  (951782400, time(2000, 2, 29, 0, 0, 0)),
+                  (1769936703, time(2026, 2, 1, 9, 5, 3)),
+                  (4107542399, time(2100, 2, 28, 23, 59, 59)),
+                  (-1, time(1969, 12, 31, 23, 59, 59)),
+            ];
+
+            for (secs, time) in cases {
+                  assert_eq!(time.to_unix(), secs);
+                  assert_eq!(Time::from_unix(secs), Some(time));
+            }
+            assert_eq!(Time::from_unix(i64::MAX), None);
+      }
+
+      /// Tests that leap years, month lengths and weekdays are calculated
+      /// correctly.
+      #[test_case]
+      fn calendar_is_correct() {
+            assert!(is_leap_year(2000) && is_leap_year(2024));
+            assert!(!is_leap_year(1900) && !is_leap_year(2026));
+
+            let feb = Time::from_unix(951782400).unwrap(); // 2000-02-29
+            assert_eq!(feb.days_in_month(), 29);
+            assert_eq!(feb.weekday(), Weekday::Tuesday);
+            assert!(feb.is_valid());
+            assert!(!Time { day: 30, ..feb }.is_valid());
+            assert_eq!(
+                  Time::from_unix(0).unwrap().weekday(),
+                  Weekday::Thursday
+            );
+            assert_eq!(
+                  Time::from_unix(-1).unwrap().weekday(),
+                  Weekday::Wednesday
+            );
+      }
+
+      /// Tests that times are zero-padded ISO 8601.
+      #[test_case]
+      fn time_displays_as_iso_8601() {
+            let time = Time::from_unix(1769936703).unwrap();
+            assert_eq!(alloc::format!("{time}"), "2026-02-01T09:05:03");
+      }
+
+      /// Tests that displayed times parse back, and malformed ones don't.
+      #[test_case]
+      fn time_parses_iso_8601() {
+            let time = Time::from_unix(1769936703).unwrap();
+            let iso = alloc::format!("{time}");
+            assert_eq!(Time::from_iso(&iso), Some(time));
+
+            assert_eq!(Time::from_iso("2026-02-01 09:05:03"), None);
+            assert_eq!(Time::from_iso("2026-02-30T09:05:03"), None);
+            assert_eq!(Time::from_iso("2026-02-01T09:05:3"), None);
+            assert_eq!(Time::from_iso("+026-02-01T09:05:03"), None);
+      }
+
+      /// Tests that `now` moves forward from the launch time.
+      #[test_case]
+      fn now_is_after_launch() {
+            let launch = LAUNCH_TIME.read().unwrap().to_unix();
+            assert!(now().unwrap().to_unix() >= launch);
+      }
+
       /// Tests that the RTC contains sane values through `LAUNCH_TIME`.
       #[test_case]
       fn rtc_contains_sane_values() {
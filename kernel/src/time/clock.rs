@@ -0,0 +1,355 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/time/clock.rs
+
+    A monotonic clock with nanosecond resolution, counted by the TSC or HPET.
+    Contained within the time module
+*/
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::fmt::Display;
+use core::ops::{Add, Sub};
+pub use core::time::Duration;
+
+use libutil::InitLater;
+use thiserror::Error;
+
+use super::{KERNEL_TICKS_HZ, PIT_BASE_FREQ, get_time, hpet};
+use crate::interrupts;
+use crate::ports::{self, Port};
+use crate::startup::ExitCode;
+
+/// The number of nanoseconds in a second.
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// The number of nanoseconds in a kernel tick.
+pub const NANOS_PER_TICK: u64 = NANOS_PER_SEC / KERNEL_TICKS_HZ;
+
+/// The number of femtoseconds in a nanosecond.
+const FEMTOS_PER_NANO: u64 = 1_000_000;
+
+/// The number of fractional bits in [`Counter::mult`].
+const MULT_SHIFT: u32 = 32;
+
+/// How TSC readings are turned into nanoseconds, only set if the TSC is
+/// usable.
+static TSC: InitLater<Counter> = InitLater::uninit();
+
+/// How HPET main counter readings are turned into nanoseconds, only set if
+/// the TSC isn't usable.
+static HPET: InitLater<Counter> = InitLater::uninit();
+
+/// A free running counter's calibration.
+#[derive(Debug)]
+struct Counter {
+      /// The counter's value when it was calibrated.
+      base:       u64,
+      /// The nanoseconds since launch when the counter was calibrated.
+      base_nanos: u64,
+      /// Nanoseconds per increment, with [`MULT_SHIFT`] fractional bits.
+      mult:       u64,
+}
+
+impl Counter {
+      /// Returns the calibration of a counter which currently reads `count`
+      /// and takes `mult` nanoseconds to increment.
+      fn new(count: u64, mult: u64) -> Self {
+            Counter {
+                  base: count,
+                  base_nanos: nanos(),
+                  mult,
+            }
+      }
+
+      /// Returns the nanoseconds since launch when the counter reads `count`.
+      fn nanos(&self, count: u64) -> u64 {
+            let elapsed = count.wrapping_sub(self.base) as u128;
+            self.base_nanos +
+                  ((elapsed * self.mult as u128) >> MULT_SHIFT) as u64
+      }
+}
+
+/// The counter which [`Instant`]s are measured with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClockSource {
+      Tsc,
+      Hpet,
+      /// Kernel ticks, only accurate to a tick.
+      Ticks,
+}
+
+/// A point in time measured by the monotonic clock.
+///
+/// Counted by the TSC with nanosecond resolution if it's invariant, otherwise
+/// by the HPET or kernel ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+      /// Returns the current instant.
+      pub fn now() -> Self {
+            Instant(nanos())
+      }
+
+      /// Returns how much time passed between `earlier` and `self`, or zero
+      /// if `earlier` is later.
+      pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.checked_duration_since(earlier).unwrap_or_default()
+      }
+
+      /// Returns how much time passed between `earlier` and `self`, or `None`
+      /// if `earlier` is later.
+      pub fn checked_duration_since(
+            &self, earlier: Instant,
+      ) -> Option<Duration> {
+            self.0.checked_sub(earlier.0).map(Duration::from_nanos)
+      }
+
+      /// Returns how much time has passed since `self`.
+      pub fn elapsed(&self) -> Duration {
+            Instant::now().duration_since(*self)
+      }
+
+      /// Returns `self` moved forward by `dur`, or `None` if it overflows.
+      pub fn checked_add(&self, dur: Duration) -> Option<Instant> {
+            let nanos = u64::try_from(dur.as_nanos()).ok()?;
+            self.0.checked_add(nanos).map(Instant)
+      }
+
+      /// Returns `self` moved back by `dur`, or `None` if it's before launch.
+      pub fn checked_sub(&self, dur: Duration) -> Option<Instant> {
+            let nanos = u64::try_from(dur.as_nanos()).ok()?;
+            self.0.checked_sub(nanos).map(Instant)
+      }
+}
+
+impl Add<Duration> for Instant {
+      type Output = Instant;
+
+      fn add(self, dur: Duration) -> Instant {
+            self.checked_add(dur).expect("overflow adding to instant")
+      }
+}
+
+impl Sub<Duration> for Instant {
+      type Output = Instant;
+
+      fn sub(self, dur: Duration) -> Instant {
+            self.checked_sub(dur)
+                  .expect("overflow subtracting from instant")
+      }
+}
+
+impl Sub<Instant> for Instant {
+      type Output = Duration;
+
+      fn sub(self, earlier: Instant) -> Duration {
+            self.duration_since(earlier)
+      }
+}
+
+/// Checks that the TSC is invariant, then calibrates it against PIT channel
+/// 2. [`Instant`]s are counted by the HPET or kernel ticks if this fails.
+pub fn init() -> ExitCode<TscError> {
+      /// Set in leaf 1's EDX if the TSC exists.
+      const TSC_BIT: u32 = 1 << 4;
+
+      /// The extended leaf describing power management features.
+      const POWER_LEAF: u32 = 0x8000_0007;
+
+      /// Set in the power management leaf's EDX if the TSC increments at the
+      /// same rate in every P, C and T state.
+      const INVARIANT_BIT: u32 = 1 << 8;
+
+      // Safety: Leaves 1 and 0x80000000 always exist on x86_64, and the power
+      // management leaf is checked
+      let invariant = unsafe {
+            if __cpuid(1).edx & TSC_BIT == 0 {
+                  return ExitCode::Error(TscError::NoTsc);
+            }
+            __cpuid(0x8000_0000).eax >= POWER_LEAF &&
+                  __cpuid(POWER_LEAF).edx & INVARIANT_BIT != 0
+      };
+
+      if !invariant {
+            return ExitCode::Error(TscError::NotInvariant);
+      }
+
+      let hz = exit_on_err!(calibrate().ok_or(TscError::Calibrate));
+      let mult = (((NANOS_PER_SEC as u128) << MULT_SHIFT) / hz as u128) as u64;
+
+      let res = interrupts::without_interrupts(|| {
+            TSC.init(Counter::new(rdtsc(), mult)).map(|_| ())
+      });
+
+      if let Err(e) = res {
+            return ExitCode::Error(TscError::NoStatic(e.state));
+      }
+
+      dbg_info!("tsc: running at {} kHz", hz / 1000);
+      ExitCode::Ok
+}
+
+/// Returns how many times the TSC increments each second, measured while PIT
+/// channel 2 counts down a few times, or `None` if either didn't count.
+///
+/// See https://wiki.osdev.org/Programmable_Interval_Timer
+fn calibrate() -> Option<u64> {
+      /// How many measurements to take, keeping the quickest.
+      const RUNS: usize = 3;
+
+      /// How many PIT cycles each measurement lasts, about 10 ms.
+      const LATCH: u16 = (PIT_BASE_FREQ / 100) as u16;
+
+      /// How many times channel 2 is polled before giving up.
+      const MAX_POLLS: u32 = 1_000_000;
+
+      /// Binary mode, interrupt on terminal count, low & high byte, channel 2
+      const COMMAND: u8 = 0b10_11_000_0;
+
+      /// The bit in the PC speaker port which lets channel 2 count.
+      const GATE: u8 = 1 << 0;
+
+      /// The bit in the PC speaker port which plays channel 2's output.
+      const SPEAKER: u8 = 1 << 1;
+
+      /// The bit in the PC speaker port which shows channel 2's output, set
+      /// once it's finished counting down.
+      const OUT: u8 = 1 << 5;
+
+      let measure = || {
+            // Safety: Channel 2's only used by the PC speaker, which is
+            // silenced and restored afterwards
+            unsafe {
+                  let prev = ports::readb(Port::PCSpeaker);
+                  ports::writeb(Port::PCSpeaker, (prev & !SPEAKER) | GATE);
+                  ports::writeb(Port::PITCmd, COMMAND);
+                  ports::writeb(Port::PITChannel2, LATCH as u8);
+                  ports::writeb(Port::PITChannel2, (LATCH >> 8) as u8);
+
+                  let start = rdtsc();
+                  let done = (0..MAX_POLLS).any(|_| {
+                        ports::readb_nodummy(Port::PCSpeaker) & OUT != 0
+                  });
+                  let cycles = rdtsc() - start;
+
+                  ports::writeb(Port::PCSpeaker, prev);
+                  done.then_some(cycles)
+            }
+      };
+
+      let cycles = (0..RUNS)
+            .filter_map(|_| interrupts::without_interrupts(measure))
+            .min()?;
+      (cycles != 0).then(|| cycles * PIT_BASE_FREQ / LATCH as u64)
+}
+
+/// Starts counting [`Instant`]s with the HPET's main counter, which
+/// increments every `period_fs` femtoseconds, unless the TSC's being used.
+pub(super) fn use_hpet(period_fs: u64) {
+      if TSC.read().is_ok() {
+            return;
+      }
+
+      let mult = ((period_fs as u128) << MULT_SHIFT) / FEMTOS_PER_NANO as u128;
+      interrupts::without_interrupts(|| {
+            if let Some(count) = hpet::counter() {
+                  _ = HPET.init(Counter::new(count, mult as u64));
+            }
+      })
+}
+
+/// Returns the counter which [`Instant`]s are measured with.
+pub fn source() -> ClockSource {
+      if TSC.read().is_ok() {
+            ClockSource::Tsc
+      } else if HPET.read().is_ok() {
+            ClockSource::Hpet
+      } else {
+            ClockSource::Ticks
+      }
+}
+
+/// Returns the number of nanoseconds since the kernel launched.
+pub(super) fn nanos() -> u64 {
+      if let Ok(tsc) = TSC.read() {
+            tsc.nanos(rdtsc())
+      } else if let Ok(hpet) = HPET.read() &&
+            let Some(count) = hpet::counter()
+      {
+            hpet.nanos(count)
+      } else {
+            get_time() * NANOS_PER_TICK
+      }
+}
+
+/// Returns the current value of the TSC.
+fn rdtsc() -> u64 {
+      // Safety: The TSC is checked to exist before being used
+      unsafe { _rdtsc() }
+}
+
+impl Display for ClockSource {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            let name = match self {
+                  ClockSource::Tsc => "TSC",
+                  ClockSource::Hpet => "HPET",
+                  ClockSource::Ticks => "kernel ticks",
+            };
+            write!(f, "{name}")
+      }
+}
+
+/// An error created when setting up the TSC.
+#[derive(Error, Debug)]
+pub enum TscError {
+      #[error("The CPU doesn't have a TSC!")]
+      NoTsc,
+
+      #[error("The TSC isn't invariant, so it won't be used")]
+      NotInvariant,
+
+      #[error("The TSC couldn't be calibrated against the PIT!")]
+      Calibrate,
+
+      #[error(
+            "Failed setting the TSC's calibration, static's init state is {0}"
+      )]
+      NoStatic(u8),
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that instants move forwards and can be offset by durations.
+      #[test_case]
+      fn instants_are_monotonic() {
+            let start = Instant::now();
+            crate::time::wait(2);
+            let end = Instant::now();
+
+            assert!(end > start);
+            assert!(end - start >= Duration::from_nanos(2 * NANOS_PER_TICK));
+            assert_eq!(start - end, Duration::ZERO);
+            assert_eq!(start + (end - start), end);
+            assert_eq!(start.checked_duration_since(end), None);
+      }
+}
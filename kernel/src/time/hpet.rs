@@ -0,0 +1,252 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/time/hpet.rs
+
+    High Precision Event Timer driver, providing a main counter for the clock
+    and a comparator which can raise kernel ticks instead of the PIT.
+    Contained within the time module
+
+    Register names follow the IA-PC HPET Specification, revision 1.0a
+*/
+
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+use thiserror::Error;
+
+use super::{KERNEL_TICKS_HZ, PREFERRED_TICKS, TickSource, clock};
+use crate::memory::paging::{self, MapError};
+use crate::startup::{self, ExitCode};
+use crate::{acpi, interrupts};
+
+/// The number of femtoseconds in a second.
+const FEMTOS_PER_SEC: u64 = 1_000_000_000_000_000;
+
+/// The longest main counter period allowed by the spec, 100 ns.
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// The size of the HPET's register block.
+const REGS_LEN: u64 = 0x400;
+
+/// Starts the main counter when set in the general config register.
+const ENABLE: u64 = 1 << 0;
+
+/// The mapped HPET registers, or null if there isn't one.
+static REGS: AtomicPtr<u64> = AtomicPtr::new(ptr::null_mut());
+
+/// How many main counter ticks pass between each kernel tick, set once
+/// comparator 0 starts raising them.
+static TICK_PERIOD: AtomicU64 = AtomicU64::new(0);
+
+/// A register in the HPET, by it's offset from the HPET's base.
+#[derive(Debug, Clone, Copy)]
+#[repr(usize)]
+enum HpetReg {
+      /// General capabilities and ID
+      Capabilities     = 0x0,
+      /// General configuration
+      Config           = 0x10,
+      MainCounter      = 0xF0,
+      /// Timer 0's configuration and capabilities
+      Timer0Config     = 0x100,
+      Timer0Comparator = 0x108,
+}
+
+/// Returns the value in HPET register `reg`.
+fn read(reg: HpetReg) -> u64 {
+      let regs = REGS.load(Ordering::Relaxed);
+      // Safety: REGS is only set once the registers have been mapped
+      unsafe { regs.byte_add(reg as usize).read_volatile() }
+}
+
+/// Writes `val` into HPET register `reg`.
+fn write(reg: HpetReg, val: u64) {
+      let regs = REGS.load(Ordering::Relaxed);
+      // Safety: REGS is only set once the registers have been mapped
+      unsafe { regs.byte_add(reg as usize).write_volatile(val) }
+}
+
+/// Starts the HPET's main counter, using it for the clock if it's 64 bits
+/// wide, then raises kernel ticks with it if it's the [`PREFERRED_TICKS`].
+///
+/// # Safety
+/// Only run this once, after the APIC has been initialised.
+pub unsafe fn init() -> ExitCode<HpetError> {
+      /// Set in the capabilities if the main counter is 64 bits wide.
+      const COUNT_64: u64 = 1 << 13;
+
+      let info =
+            exit_on_err!(acpi::HPET.read().map_err(|_| HpetError::NoTable));
+
+      // Safety: The HPET table gives the address of the HPET's registers
+      let regs = exit_on_err!(unsafe { paging::map_mmio(info.addr, REGS_LEN) });
+      REGS.store(regs.cast(), Ordering::Relaxed);
+
+      let caps = read(HpetReg::Capabilities);
+      let period_fs = caps >> 32;
+      if period_fs == 0 || period_fs > MAX_PERIOD_FS {
+            REGS.store(ptr::null_mut(), Ordering::Relaxed);
+            return ExitCode::Error(HpetError::BadPeriod(period_fs));
+      }
+
+      write(HpetReg::Config, read(HpetReg::Config) | ENABLE);
+      dbg_info!("hpet: counting every {period_fs} fs");
+
+      if caps & COUNT_64 != 0 {
+            clock::use_hpet(period_fs);
+      }
+
+      if PREFERRED_TICKS == TickSource::Hpet {
+            let ticks = FEMTOS_PER_SEC / period_fs / KERNEL_TICKS_HZ;
+            exit_on_err!(start_ticks(ticks.max(info.min_tick as u64)));
+      }
+
+      ExitCode::Ok
+}
+
+/// Returns the value of the main counter, or `None` if there's no HPET.
+pub fn counter() -> Option<u64> {
+      (!REGS.load(Ordering::Relaxed).is_null())
+            .then(|| read(HpetReg::MainCounter))
+}
+
+/// Makes comparator 0 raise kernel ticks every `ticks` main counter ticks,
+/// then stops the PIT.
+fn start_ticks(ticks: u64) -> Result<(), HpetError> {
+      if !startup::APIC_INIT.load() {
+            return Err(HpetError::NoApic);
+      }
+
+      TICK_PERIOD.store(ticks, Ordering::Relaxed);
+      interrupts::without_interrupts(|| {
+            restart_ticks()?;
+            super::stop_pit();
+            super::set_tick_source(TickSource::Hpet);
+            Ok::<_, HpetError>(())
+      })?;
+
+      dbg_info!("hpet: ticking every {ticks} counter ticks");
+      Ok(())
+}
+
+/// Makes comparator 0 raise a kernel tick every tick again, after it was made
+/// to raise one by [`one_shot`].
+pub fn restart_ticks() -> Result<(), HpetError> {
+      set_comparator(TICK_PERIOD.load(Ordering::Relaxed), true)
+}
+
+/// Makes comparator 0 raise a single kernel tick after `nanos` nanoseconds,
+/// instead of every tick.
+pub fn one_shot(nanos: u64) -> Result<(), HpetError> {
+      let period = TICK_PERIOD.load(Ordering::Relaxed);
+      set_comparator(nanos * period / clock::NANOS_PER_TICK, false)
+}
+
+/// Makes comparator 0 raise ISA IRQ 0 after `ticks` main counter ticks,
+/// repeating if `periodic` is set.
+///
+/// `ticks` must be at least the HPET's minimum tick, so that the main counter
+/// can't pass the comparator while it's being set.
+fn set_comparator(ticks: u64, periodic: bool) -> Result<(), HpetError> {
+      /// Raises an interrupt when the comparator matches.
+      const INT_ENABLE: u64 = 1 << 2;
+
+      /// Periodic mode.
+      const PERIODIC: u64 = 1 << 3;
+
+      /// Set in the config if the timer supports periodic mode.
+      const PERIODIC_CAP: u64 = 1 << 4;
+
+      /// Lets the next comparator write set the periodic interval.
+      const VAL_SET: u64 = 1 << 6;
+
+      /// Where the I/O APIC input to raise is stored.
+      const ROUTE_SHIFT: u64 = 9;
+
+      /// The mask of the config bits which can be written.
+      const CONFIG_MASK: u64 = 0xFFFF & !(0x1F << ROUTE_SHIFT | PERIODIC);
+
+      let config = read(HpetReg::Timer0Config);
+      if periodic && config & PERIODIC_CAP == 0 {
+            return Err(HpetError::NoPeriodic);
+      }
+
+      // Timer 0 has to raise whichever GSI the I/O APIC routes IRQ 0 from
+      let madt = acpi::MADT.read().map_err(|_| HpetError::NoApic)?;
+      let (gsi, _) = madt.irq_to_gsi(0);
+      if gsi >= 32 || (config >> 32) & (1 << gsi) == 0 {
+            return Err(HpetError::CantRoute(gsi));
+      }
+
+      let mut config =
+            config & CONFIG_MASK | INT_ENABLE | (gsi as u64) << ROUTE_SHIFT;
+      if periodic {
+            config |= PERIODIC | VAL_SET;
+      }
+
+      // The main counter isn't halted while setting the comparator, as it
+      // may be counting instants
+      write(HpetReg::Timer0Config, config);
+      write(
+            HpetReg::Timer0Comparator,
+            read(HpetReg::MainCounter) + ticks,
+      );
+      if periodic {
+            write(HpetReg::Timer0Comparator, ticks);
+      }
+
+      Ok(())
+}
+
+/// An error created when setting up the HPET.
+#[derive(Error, Debug)]
+pub enum HpetError {
+      #[error("The firmware has no HPET table")]
+      NoTable,
+
+      #[error(transparent)]
+      Map(#[from] MapError),
+
+      #[error("The HPET's counter period is invalid ({0} fs)")]
+      BadPeriod(u64),
+
+      #[error("The HPET can only raise ticks through the APIC")]
+      NoApic,
+
+      #[error("HPET timer 0 doesn't support periodic mode")]
+      NoPeriodic,
+
+      #[error("HPET timer 0 can't raise GSI {0}, used by IRQ 0")]
+      CantRoute(u32),
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that the main counter is running if there's an HPET.
+      #[test_case]
+      fn hpet_counter_runs() {
+            if let Some(start) = counter() {
+                  crate::time::wait(1);
+                  assert!(counter().unwrap() > start);
+            }
+      }
+}
@@ -0,0 +1,603 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/time/rtc.rs
+
+    CMOS real-time clock driver, which reads and sets the hardware clock and
+    raises it's alarm and periodic interrupts.
+    Contained within the time module
+*/
+
+use core::hint;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use libutil::ExclusiveMap;
+use thiserror::Error;
+
+use super::{CENTURY, Time};
+use crate::interrupts::{self, IrqError};
+use crate::ports::{self, Port};
+use crate::startup::{self, ExitCode};
+
+/// The IRQ line the RTC uses.
+const RTC_IRQ: u8 = 8;
+
+/// Set in register A while the RTC is updating it's time registers.
+const UPDATING: u8 = 1 << 7;
+
+/// The periodic interrupt's rate in register A.
+const RATE_MASK: u8 = 0xF;
+
+/// Stops the RTC updating when set in register B.
+const SET: u8 = 1 << 7;
+
+/// The periodic interrupt's enable bit in register B and flag in register C.
+const PERIODIC: u8 = 1 << 6;
+
+/// The alarm interrupt's enable bit in register B and flag in register C.
+const ALARM: u8 = 1 << 5;
+
+/// The update ended interrupt's enable bit in register B and flag in
+/// register C.
+const UPDATE_ENDED: u8 = 1 << 4;
+
+/// Set in register B if values are binary instead of BCD.
+const BINARY: u8 = 1 << 2;
+
+/// Set in register B if hours are 24 hour time instead of 12 hour time.
+const HOUR_24: u8 = 1 << 1;
+
+/// Set in 12 hour time hours after midday.
+const PM: u8 = 1 << 7;
+
+/// The callbacks ran by the RTC's IRQ, only accessed with interrupts
+/// disabled.
+static CALLBACKS: ExclusiveMap<Callbacks> = ExclusiveMap::new(Callbacks {
+      alarm:    None,
+      periodic: None,
+});
+
+/// A CMOS register used by the RTC.
+///
+/// NMIs are left enabled while selecting them, for the watchdog.
+#[derive(Debug, Clone, Copy)]
+#[repr(u8)]
+enum RtcReg {
+      Seconds      = 0x0,
+      SecondsAlarm = 0x1,
+      Minutes      = 0x2,
+      MinutesAlarm = 0x3,
+      Hours        = 0x4,
+      HoursAlarm   = 0x5,
+      /// The day of the week, starting with Sunday as 1
+      Weekday      = 0x6,
+      Day          = 0x7,
+      Month        = 0x8,
+      /// The year in the century
+      Year         = 0x9,
+      StatusA      = 0xA,
+      StatusB      = 0xB,
+      /// Which interrupts were raised, which must be read for the RTC to
+      /// raise another IRQ
+      StatusC      = 0xC,
+}
+
+/// The callbacks ran when the RTC raises an interrupt.
+struct Callbacks {
+      /// Ran and removed once the alarm goes off.
+      alarm:    Option<fn()>,
+      periodic: Option<fn()>,
+}
+
+/// How the RTC stores values, from register B.
+#[derive(Debug, Clone, Copy)]
+struct Format {
+      binary:  bool,
+      hour_24: bool,
+}
+
+impl Format {
+      /// Returns the format the RTC is currently using.
+      fn read() -> Self {
+            let reg_b = read(RtcReg::StatusB);
+            Format {
+                  binary:  reg_b & BINARY != 0,
+                  hour_24: reg_b & HOUR_24 != 0,
+            }
+      }
+
+      /// Converts `val`, read from the RTC, into binary.
+      fn decode(self, val: u8) -> u8 {
+            if self.binary {
+                  val
+            } else {
+                  (val >> 4) * 10 + (val & 0xF)
+            }
+      }
+
+      /// Converts `val` into the RTC's format.
+      fn encode(self, val: u8) -> u8 {
+            if self.binary {
+                  val
+            } else {
+                  ((val / 10) << 4) | (val % 10)
+            }
+      }
+
+      /// Converts `val`, read from an hours register, into 24 hour time.
+      fn decode_hour(self, val: u8) -> u8 {
+            if self.hour_24 {
+                  return self.decode(val);
+            }
+
+            // 12 AM is midnight, and 12 PM is midday
+            let hour = self.decode(val & !PM) % 12;
+            if val & PM != 0 { hour + 12 } else { hour }
+      }
+
+      /// Converts `hour`, in 24 hour time, into the RTC's format.
+      fn encode_hour(self, hour: u8) -> u8 {
+            if self.hour_24 {
+                  return self.encode(hour);
+            }
+
+            let pm = if hour >= 12 { PM } else { 0 };
+            match hour % 12 {
+                  0 => self.encode(12) | pm,
+                  hour => self.encode(hour) | pm,
+            }
+      }
+
+      /// Converts `raw`, holding values read from the RTC's registers, into
+      /// a time in [`CENTURY`].
+      fn decode_time(self, raw: Time) -> Time {
+            Time {
+                  year:  CENTURY * 100 + self.decode(raw.year as u8) as u16,
+                  month: self.decode(raw.month),
+                  day:   self.decode(raw.day),
+                  hour:  self.decode_hour(raw.hour),
+                  min:   self.decode(raw.min),
+                  sec:   self.decode(raw.sec),
+            }
+      }
+
+      /// Converts `time` into the values stored in the RTC's registers.
+      fn encode_time(self, time: Time) -> Time {
+            Time {
+                  year:  self.encode((time.year % 100) as u8) as u16,
+                  month: self.encode(time.month),
+                  day:   self.encode(time.day),
+                  hour:  self.encode_hour(time.hour),
+                  min:   self.encode(time.min),
+                  sec:   self.encode(time.sec),
+            }
+      }
+}
+
+/// Returns the current value of CMOS register `reg`.
+///
+/// Interrupts are disabled while reading, so that the RTC's handler can't
+/// select a different register in between.
+/// # Safety
+/// Reads and writes to I/O ports.
+pub unsafe fn read_cmos_reg(reg: u8) -> u8 {
+      interrupts::without_interrupts(|| unsafe {
+            ports::writeb(Port::CMOSIndex, reg);
+            ports::readb(Port::CMOSData)
+      })
+}
+
+/// Writes `val` into CMOS register `reg`.
+///
+/// Interrupts are disabled while writing, so that the RTC's handler can't
+/// select a different register in between.
+/// # Safety
+/// Writing to the wrong register can change the firmware's settings.
+pub unsafe fn write_cmos_reg(reg: u8, val: u8) {
+      interrupts::without_interrupts(|| unsafe {
+            ports::writeb(Port::CMOSIndex, reg);
+            ports::writeb(Port::CMOSData, val)
+      })
+}
+
+/// Returns the value in RTC register `reg`.
+fn read(reg: RtcReg) -> u8 {
+      // Safety: Just reading the RTC's registers
+      unsafe { read_cmos_reg(reg as u8) }
+}
+
+/// Writes `val` into RTC register `reg`.
+fn write(reg: RtcReg, val: u8) {
+      // Safety: The RTC's registers don't hold any firmware settings
+      unsafe { write_cmos_reg(reg as u8, val) }
+}
+
+/// Sets up the RTC's update ended interrupt in IRQ 8, which is raised once a
+/// second.
+pub fn init() -> ExitCode<RtcSetupError> {
+      if !startup::PIC_INIT.load() {
+            return ExitCode::Error(RtcSetupError::NoPic);
+      }
+
+      interrupts::cli();
+      set_enabled(UPDATE_ENDED, true);
+      interrupts::sti();
+      exit_on_err!(interrupts::register_irq(RTC_IRQ, rtc_irq));
+
+      // Safety: Just enabled it above!
+      unsafe { startup::RTC_IRQ_INIT.store(true) }
+
+      ExitCode::Ok
+}
+
+/// Returns the time in the RTC, waiting for any update in progress to
+/// finish.
+///
+/// The year is assumed to be in [`CENTURY`].
+pub fn read_time() -> Time {
+      let format = Format::read();
+
+      // Read until two reads match, in case an update started in between
+      let mut prev = read_raw();
+      loop {
+            let raw = read_raw();
+            if raw == prev {
+                  return format.decode_time(raw);
+            }
+            prev = raw;
+      }
+}
+
+/// Returns the values in the RTC's date & time registers, once it's not
+/// updating them.
+fn read_raw() -> Time {
+      while read(RtcReg::StatusA) & UPDATING != 0 {
+            hint::spin_loop()
+      }
+
+      Time {
+            year:  read(RtcReg::Year) as u16,
+            month: read(RtcReg::Month),
+            day:   read(RtcReg::Day),
+            hour:  read(RtcReg::Hours),
+            min:   read(RtcReg::Minutes),
+            sec:   read(RtcReg::Seconds),
+      }
+}
+
+/// Sets the RTC to `time`, which has to be in [`CENTURY`], and moves the
+/// wall-clock time to match.
+pub fn set_time(time: Time) -> Result<(), RtcError> {
+      if !time.is_valid() {
+            return Err(RtcError::BadTime(time));
+      }
+
+      if time.year / 100 != CENTURY {
+            return Err(RtcError::BadCentury(time.year));
+      }
+
+      // The RTC counts Sunday as 1
+      let weekday = (time.weekday() as u8 + 1) % 7 + 1;
+
+      interrupts::without_interrupts(|| {
+            let raw = Format::read().encode_time(time);
+            let reg_b = read(RtcReg::StatusB);
+
+            // Stop the RTC updating while it's being set
+            write(RtcReg::StatusB, reg_b | SET);
+            write(RtcReg::Seconds, raw.sec);
+            write(RtcReg::Minutes, raw.min);
+            write(RtcReg::Hours, raw.hour);
+            write(RtcReg::Weekday, weekday);
+            write(RtcReg::Day, raw.day);
+            write(RtcReg::Month, raw.month);
+            write(RtcReg::Year, raw.year as u8);
+            write(RtcReg::StatusB, reg_b & !SET);
+
+            super::set_epoch(time)
+      });
+
+      dbg_info!("rtc: set time to {time}");
+      Ok(())
+}
+
+/// Asks for the time to set the RTC to, then sets it. Ran after inputting
+/// syscmd 10.
+pub async fn prompt_time() {
+      /// The length of an ISO 8601 time, e.g. `2026-02-01T09:05:03`.
+      const ISO_LEN: usize = 19;
+
+      /// Is the time already being asked for?
+      static PROMPTING: AtomicBool = AtomicBool::new(false);
+
+      if PROMPTING.swap(true, Ordering::Relaxed) {
+            return;
+      }
+
+      interrupts::clear_typed();
+      println!(fg = LightBlue, "\nSet the hardware clock");
+      print!("Type the time in UTC as YYYY-MM-DDTHH:MM:SS: ");
+
+      // Stops at enter, or once a whole time has been typed in case enter
+      // is disabled
+      let mut buf = [0; ISO_LEN];
+      let mut len = 0;
+      while len < ISO_LEN {
+            match interrupts::next_typed() {
+                  Some(b'\n') => break,
+                  Some(typed) => {
+                        buf[len] = typed;
+                        len += 1;
+                  }
+                  None => super::sleep(1).await,
+            }
+      }
+      PROMPTING.store(false, Ordering::Relaxed);
+
+      let iso = core::str::from_utf8(&buf[..len]).unwrap_or_default();
+      let Some(time) = Time::from_iso(iso) else {
+            return println!(fg = LightRed, "\n{iso} isn't a valid time");
+      };
+
+      match set_time(time) {
+            Ok(()) => println!("\nSet the time to {time}"),
+            Err(e) => println!(fg = LightRed, "\nFailed setting the time: {e}"),
+      }
+}
+
+/// Runs `callback` inside of the RTC's IRQ the next time the RTC reaches
+/// `hour:min:sec`, replacing the alarm if it was already set.
+///
+/// The callback must be quick and can't access I/O ports or block.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn set_alarm(
+      hour: u8, min: u8, sec: u8, callback: fn(),
+) -> Result<(), RtcError> {
+      if hour >= 24 || min >= 60 || sec >= 60 {
+            return Err(RtcError::BadAlarm(hour, min, sec));
+      }
+
+      if !startup::RTC_IRQ_INIT.load() {
+            return Err(RtcError::NoIrq);
+      }
+
+      interrupts::without_interrupts(|| {
+            CALLBACKS
+                  .map(|c| c.alarm = Some(callback))
+                  .ok_or(RtcError::Contended)?;
+
+            let format = Format::read();
+            write(RtcReg::HoursAlarm, format.encode_hour(hour));
+            write(RtcReg::MinutesAlarm, format.encode(min));
+            write(RtcReg::SecondsAlarm, format.encode(sec));
+            set_enabled(ALARM, true);
+            Ok(())
+      })
+}
+
+/// Stops the alarm from going off, returning `false` if it wasn't set.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn cancel_alarm() -> bool {
+      interrupts::without_interrupts(|| {
+            set_enabled(ALARM, false);
+            CALLBACKS.map(|c| c.alarm.take()).flatten().is_some()
+      })
+}
+
+/// Runs `callback` inside of the RTC's IRQ `hz` times a second, replacing the
+/// periodic callback if it was already set.
+///
+/// `hz` must be a power of two from 2 to 8192, and the callback must be quick
+/// and can't access I/O ports or block.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn start_periodic(hz: u16, callback: fn()) -> Result<(), RtcError> {
+      let rate = rate_for(hz)?;
+      if !startup::RTC_IRQ_INIT.load() {
+            return Err(RtcError::NoIrq);
+      }
+
+      interrupts::without_interrupts(|| {
+            CALLBACKS
+                  .map(|c| c.periodic = Some(callback))
+                  .ok_or(RtcError::Contended)?;
+
+            let reg_a = read(RtcReg::StatusA);
+            write(RtcReg::StatusA, reg_a & !RATE_MASK | rate);
+            set_enabled(PERIODIC, true);
+            Ok(())
+      })
+}
+
+/// Returns the rate in register A which makes the periodic interrupt fire `hz`
+/// times a second.
+fn rate_for(hz: u16) -> Result<u8, RtcError> {
+      if !hz.is_power_of_two() || !(2..=8192).contains(&hz) {
+            return Err(RtcError::BadRate(hz));
+      }
+
+      // The RTC runs at 32768 >> (rate - 1) Hz
+      Ok(16 - hz.trailing_zeros() as u8)
+}
+
+/// Stops the periodic interrupt, returning `false` if it wasn't running.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn stop_periodic() -> bool {
+      interrupts::without_interrupts(|| {
+            set_enabled(PERIODIC, false);
+            CALLBACKS.map(|c| c.periodic.take()).flatten().is_some()
+      })
+}
+
+/// Enables or disables interrupt `int` in register B.
+///
+/// Must be called with external interrupts disabled.
+fn set_enabled(int: u8, enabled: bool) {
+      let reg_b = read(RtcReg::StatusB);
+      let reg_b = if enabled { reg_b | int } else { reg_b & !int };
+      write(RtcReg::StatusB, reg_b);
+}
+
+/// Ran by IRQ 8 for each interrupt the RTC raises.
+fn rtc_irq() {
+      let flags = read(RtcReg::StatusC);
+      if flags & UPDATE_ENDED != 0 {
+            super::rtc_updated()
+      }
+
+      let (alarm, periodic) = CALLBACKS
+            .map(|c| {
+                  let alarm = c.alarm.take_if(|_| flags & ALARM != 0);
+                  (alarm, c.periodic.filter(|_| flags & PERIODIC != 0))
+            })
+            .unwrap_or_default();
+
+      // The alarm only goes off once
+      if let Some(alarm) = alarm {
+            set_enabled(ALARM, false);
+            alarm()
+      }
+
+      if let Some(periodic) = periodic {
+            periodic()
+      }
+}
+
+#[derive(Error, Debug)]
+pub enum RtcSetupError {
+      #[error("The PIC isn't init!")]
+      NoPic,
+
+      #[error(transparent)]
+      Irq(#[from] IrqError),
+}
+
+/// An error created when using the RTC.
+#[derive(Error, Debug)]
+pub enum RtcError {
+      #[error("{0} isn't a valid time")]
+      BadTime(Time),
+
+      #[error("the RTC can only store years in the {CENTURY}00s, not {0}")]
+      BadCentury(u16),
+
+      #[error("{0:02}:{1:02}:{2:02} isn't a valid time of day")]
+      BadAlarm(u8, u8, u8),
+
+      #[error("the RTC can't interrupt at {0} Hz")]
+      BadRate(u16),
+
+      #[error("the RTC's IRQ isn't enabled")]
+      NoIrq,
+
+      #[error("the RTC's callbacks are being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use core::sync::atomic::{AtomicU32, Ordering};
+
+      use super::*;
+      use crate::time::{self, KERNEL_TICKS_HZ};
+
+      /// Tests that only powers of two from 2 to 8192 Hz have a rate.
+      #[test_case]
+      fn periodic_rates() {
+            assert_eq!(rate_for(2).unwrap(), 15);
+            assert_eq!(rate_for(1024).unwrap(), 6);
+            assert_eq!(rate_for(8192).unwrap(), 3);
+            for hz in [0, 1, 3, 1000, 16384] {
+                  assert!(matches!(rate_for(hz), Err(RtcError::BadRate(_))));
+            }
+      }
+
+      /// Tests that the periodic callback runs until it's stopped.
+      #[test_case]
+      fn periodic_callback_fires() {
+            static RAN: AtomicU32 = AtomicU32::new(0);
+            start_periodic(64, || {
+                  RAN.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+
+            time::wait(KERNEL_TICKS_HZ / 4);
+            assert!(stop_periodic());
+            let ran = RAN.load(Ordering::Relaxed);
+            assert!(ran > 0);
+
+            time::wait(KERNEL_TICKS_HZ / 4);
+            assert_eq!(RAN.load(Ordering::Relaxed), ran);
+            assert!(!stop_periodic());
+      }
+
+      /// Tests that the alarm goes off once, then disables itself.
+      #[test_case]
+      fn alarm_fires_once() {
+            static RAN: AtomicU32 = AtomicU32::new(0);
+            let now = read_time();
+            let secs =
+                  now.hour as u32 * 3600 + now.min as u32 * 60 + now.sec as u32;
+            let secs = (secs + 2) % (24 * 3600);
+            let (hour, min, sec) = (secs / 3600, secs / 60 % 60, secs % 60);
+            set_alarm(hour as u8, min as u8, sec as u8, || {
+                  RAN.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+
+            time::wait(3 * KERNEL_TICKS_HZ);
+            assert_eq!(RAN.load(Ordering::Relaxed), 1);
+            let reg_b =
+                  interrupts::without_interrupts(|| read(RtcReg::StatusB));
+            assert_eq!(reg_b & ALARM, 0);
+            assert!(!cancel_alarm());
+      }
+
+      /// Tests that times convert to and from every format the RTC can use,
+      /// including hours around midnight and midday.
+      #[test_case]
+      fn formats_round_trip() {
+            let time = |hour| Time {
+                  year: CENTURY * 100 + 26,
+                  month: 12,
+                  day: 31,
+                  hour,
+                  min: 59,
+                  sec: 9,
+            };
+
+            for binary in [false, true] {
+                  for hour_24 in [false, true] {
+                        let format = Format { binary, hour_24 };
+                        for hour in [0, 1, 11, 12, 13, 23] {
+                              let raw = format.encode_time(time(hour));
+                              assert_eq!(format.decode_time(raw), time(hour));
+                        }
+                  }
+            }
+
+            let bcd_12 = Format {
+                  binary:  false,
+                  hour_24: false,
+            };
+            assert_eq!(bcd_12.encode(59), 0x59);
+            assert_eq!(bcd_12.encode_hour(0), 0x12);
+            assert_eq!(bcd_12.encode_hour(12), PM | 0x12);
+            assert_eq!(bcd_12.decode_hour(PM | 0x11), 23);
+      }
+}
@@ -0,0 +1,246 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/time/tickless.rs
+
+    Stops kernel ticks while the CPU is idle, programming the tick source to
+    fire once on the next deadline and catching the time up once it wakes.
+    Contained within the time module
+*/
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use super::clock::{self, ClockSource, NANOS_PER_TICK};
+use super::{TIME, TickSource, get_time, hpet, timer};
+use crate::task::wake;
+use crate::{interrupts, startup, thread};
+
+/// Whether ticks are stopped while idle, set by `SFK_TICKLESS` when
+/// building.
+const ENABLED: bool = crate::env_as_int!("SFK_TICKLESS", u8) != 0;
+
+/// The fewest ticks worth stopping ticks for.
+const MIN_TICKS: u64 = 2;
+
+/// The most ticks which can be skipped at once, so that the tick sources'
+/// counts can't overflow.
+const MAX_TICKS: u64 = super::KERNEL_TICKS_HZ;
+
+/// Set while ticks are stopped.
+static STOPPED: AtomicBool = AtomicBool::new(false);
+
+/// When the last tick happened, in nanoseconds since launch.
+static LAST_TICK: AtomicU64 = AtomicU64::new(0);
+
+/// The earliest tick a task waiting on [`wake::TIMER`] needs to be woken on,
+/// cleared each time it fires.
+static NEXT_WAKE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// How many ticks have been skipped while idle.
+static SKIPPED: AtomicU64 = AtomicU64::new(0);
+
+/// Halts until the next interrupt, stopping ticks until the next deadline
+/// first if nothing needs them sooner.
+///
+/// Must be called with external interrupts disabled, which are enabled once
+/// this returns.
+pub fn idle() {
+      let stopped = stop_ticks();
+
+      // Safety: sti only takes effect after the next instruction, so the
+      // next interrupt is guaranteed to end the hlt
+      unsafe { asm!("sti", "hlt") }
+
+      // Woken by something other than the tick source
+      if stopped {
+            interrupts::without_interrupts(|| {
+                  if let Some(ticks) = resume() &&
+                        ticks != 0
+                  {
+                        advance(ticks);
+                        timer::run_timers();
+                        wake::wake_timer();
+                  }
+            })
+      }
+}
+
+/// Makes sure tasks waiting on [`wake::TIMER`] are woken by tick `tick`,
+/// even if ticks are stopped.
+pub fn wake_by(tick: u64) {
+      NEXT_WAKE.fetch_min(tick, Ordering::Relaxed);
+}
+
+/// Forgets when tasks waiting on [`wake::TIMER`] need to be woken, as it's
+/// about to wake them.
+pub fn clear_wakes() {
+      NEXT_WAKE.store(u64::MAX, Ordering::Relaxed);
+}
+
+/// Returns how many ticks have been skipped while idle.
+pub fn skipped() -> u64 {
+      SKIPPED.load(Ordering::Relaxed)
+}
+
+/// Makes the tick source fire once on the next deadline, returning whether
+/// it was.
+///
+/// Ticks are only stopped if there's a clock to count how many were skipped.
+fn stop_ticks() -> bool {
+      if !ENABLED ||
+            !startup::PIT_INIT.load() ||
+            clock::source() == ClockSource::Ticks
+      {
+            return false;
+      }
+
+      let deadline = [
+            Some(NEXT_WAKE.load(Ordering::Relaxed)),
+            timer::next_expiry(),
+            thread::sched::next_wakeup(),
+      ]
+      .into_iter()
+      .flatten()
+      .min()
+      .unwrap_or(u64::MAX);
+
+      // The deadline's tick is counted from the last tick, not from now
+      let ticks = deadline.saturating_sub(get_time()).min(MAX_TICKS);
+      let since =
+            clock::nanos().saturating_sub(LAST_TICK.load(Ordering::Relaxed));
+      let nanos = (ticks * NANOS_PER_TICK).saturating_sub(since);
+      if ticks < MIN_TICKS || nanos < NANOS_PER_TICK {
+            return false;
+      }
+
+      match super::tick_source() {
+            TickSource::Pit => super::one_shot_pit(nanos),
+            TickSource::Apic => interrupts::one_shot_lapic_timer(nanos),
+            TickSource::Hpet => {
+                  if hpet::one_shot(nanos).is_err() {
+                        return false;
+                  }
+            }
+      }
+
+      STOPPED.store(true, Ordering::Relaxed);
+      true
+}
+
+/// Makes the tick source fire every tick again if it was stopped, returning
+/// how many ticks passed while it was.
+///
+/// Must be called with external interrupts disabled.
+pub(super) fn resume() -> Option<u64> {
+      if !STOPPED.swap(false, Ordering::Relaxed) {
+            return None;
+      }
+
+      match super::tick_source() {
+            TickSource::Pit => super::start_pit(),
+            TickSource::Apic => interrupts::restart_lapic_timer(),
+            TickSource::Hpet => {
+                  if let Err(_e) = hpet::restart_ticks() {
+                        warn!("tickless: failed restarting hpet ticks: {_e}")
+                  }
+            }
+      }
+
+      let since =
+            clock::nanos().saturating_sub(LAST_TICK.load(Ordering::Relaxed));
+      let ticks = ticks_in(since);
+      SKIPPED.fetch_add(ticks.saturating_sub(1), Ordering::Relaxed);
+      Some(ticks)
+}
+
+/// Returns how many ticks fit in `nanos` nanoseconds, rounded so that time
+/// isn't lost each time ticks are stopped.
+fn ticks_in(nanos: u64) -> u64 {
+      (nanos + NANOS_PER_TICK / 2) / NANOS_PER_TICK
+}
+
+/// Moves the time forward by `ticks` ticks, running the timers of each tick
+/// skipped, but leaving the last tick's to the caller.
+///
+/// Called on every tick, so it also records when the last tick happened.
+/// Must be called with external interrupts disabled.
+pub(super) fn advance(ticks: u64) {
+      step(&TIME, ticks, || timer::run_timers());
+      LAST_TICK.store(clock::nanos(), Ordering::Relaxed);
+}
+
+/// Moves `time` forward by `ticks` ticks, calling `run_timers` after each
+/// skipped tick whose slot in the timer wheel may still hold timers.
+fn step(time: &AtomicU64, ticks: u64, mut run_timers: impl FnMut()) {
+      let skipped = ticks.saturating_sub(1);
+
+      // Every timer left is in a slot of the last rotation
+      let checked = skipped.min(timer::WHEEL_SLOTS as u64);
+      time.fetch_add(skipped - checked, Ordering::Relaxed);
+      for _ in 0..checked {
+            time.fetch_add(1, Ordering::Relaxed);
+            run_timers();
+      }
+
+      time.fetch_add(1, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+      use alloc::vec::Vec;
+
+      use super::*;
+
+      /// Tests that advancing runs the timers of every skipped tick, and of
+      /// every wheel slot if more ticks were skipped than the wheel has.
+      ///
+      /// Uses it's own time, so that the kernel's doesn't jump forward.
+      #[test_case]
+      fn advance_runs_skipped_timers() {
+            let slots = timer::WHEEL_SLOTS as u64;
+            for ticks in [1, 3, slots + 1, slots * 3 + 10] {
+                  let time = AtomicU64::new(1000);
+                  let mut ran = Vec::new();
+                  step(&time, ticks, || ran.push(time.load(Ordering::Relaxed)));
+
+                  let end = 1000 + ticks;
+                  let skipped = (ticks - 1).min(slots);
+                  assert_eq!(time.load(Ordering::Relaxed), end);
+                  assert!(ran.iter().copied().eq(end - skipped..end));
+            }
+      }
+
+      /// Tests that resuming does nothing if ticks weren't stopped.
+      #[test_case]
+      fn resume_without_stopping() {
+            assert_eq!(interrupts::without_interrupts(resume), None);
+      }
+
+      /// Tests that the time passed while stopped is rounded to the nearest
+      /// tick.
+      #[test_case]
+      fn stopped_time_is_rounded() {
+            assert_eq!(ticks_in(0), 0);
+            assert_eq!(ticks_in(NANOS_PER_TICK / 2 - 1), 0);
+            assert_eq!(ticks_in(NANOS_PER_TICK / 2), 1);
+            assert_eq!(ticks_in(3 * NANOS_PER_TICK - 1), 3);
+            assert_eq!(ticks_in(3 * NANOS_PER_TICK + NANOS_PER_TICK / 2), 4);
+      }
+}
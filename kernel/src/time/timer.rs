@@ -0,0 +1,388 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/time/timer.rs
+
+    A timer wheel which runs one shot and periodic callbacks, either inside of
+    the timer IRQ or deferred to a task.
+    Contained within the time module
+*/
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use libutil::ExclusiveMap;
+use thiserror::Error;
+
+use crate::interrupts;
+use crate::task::wake::IrqEvent;
+
+/// The number of slots in the wheel, timers further away than this wait in
+/// their slot for multiple rotations.
+pub(super) const WHEEL_SLOTS: usize = 64;
+
+/// The most timers which can be registered at once.
+pub const MAX_TIMERS: usize = 64;
+
+/// Every registered timer, only accessed with interrupts disabled.
+static WHEEL: ExclusiveMap<Wheel> = ExclusiveMap::new(Wheel::new());
+
+/// Fired when deferred callbacks are ready to be ran by [`timer_task`].
+static DEFERRED: IrqEvent = IrqEvent::new();
+
+/// How many ticks the wheel was contended on, which are caught up on the
+/// next tick.
+static MISSED: AtomicU64 = AtomicU64::new(0);
+
+/// Used to tell apart timers which reuse the same slot.
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Each timer needs a bit in the slot bitmaps
+const _: () = assert!(MAX_TIMERS <= u64::BITS as usize);
+
+/// Where a timer's callback runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+      /// Inside of the timer IRQ, so the callback must be quick and can't
+      /// access I/O ports or block.
+      Irq,
+
+      /// Inside of the timer task, shortly after the timer expires.
+      Deferred,
+}
+
+/// Identifies a registered timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerId {
+      idx:        usize,
+      generation: u64,
+}
+
+/// The timer wheel.
+struct Wheel {
+      timers:  [Option<Timer>; MAX_TIMERS],
+      /// A bitmap of the timers in each slot, by `expires % WHEEL_SLOTS`.
+      slots:   [u64; WHEEL_SLOTS],
+      /// A bitmap of the deferred timers which have expired.
+      pending: u64,
+}
+
+/// A registered timer.
+struct Timer {
+      generation: u64,
+      /// The tick the timer next expires on.
+      expires:    u64,
+      /// The ticks between each expiry, or zero for one shot timers.
+      period:     u64,
+      context:    Context,
+      callback:   fn(),
+}
+
+/// Runs `callback` in `context` once after `ticks` ticks.
+pub fn one_shot(
+      ticks: u64, context: Context, callback: fn(),
+) -> Result<TimerId, TimerError> {
+      add(ticks, 0, context, callback)
+}
+
+/// Runs `callback` in `context` every `ticks` ticks, until cancelled.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn periodic(
+      ticks: u64, context: Context, callback: fn(),
+) -> Result<TimerId, TimerError> {
+      if ticks == 0 {
+            return Err(TimerError::ZeroPeriod);
+      }
+      add(ticks, ticks, context, callback)
+}
+
+/// Stops timer `id`, returning `false` if it had already finished.
+///
+/// Once this returns the timer's callback won't start running again.
+pub fn cancel(id: TimerId) -> bool {
+      interrupts::without_interrupts(|| {
+            WHEEL.map(|wheel| {
+                  let timer = wheel.timers[id.idx]
+                        .take_if(|t| t.generation == id.generation);
+                  if let Some(ref timer) = timer {
+                        let bit = 1 << id.idx;
+                        wheel.slots[timer.expires as usize % WHEEL_SLOTS] &=
+                              !bit;
+                        wheel.pending &= !bit;
+                  }
+                  timer.is_some()
+            })
+            .unwrap_or(false)
+      })
+}
+
+/// Registers a timer expiring in `ticks` ticks.
+fn add(
+      ticks: u64, period: u64, context: Context, callback: fn(),
+) -> Result<TimerId, TimerError> {
+      let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+      interrupts::without_interrupts(|| {
+            WHEEL.map(|wheel| {
+                  let idx = wheel
+                        .timers
+                        .iter()
+                        .position(Option::is_none)
+                        .ok_or(TimerError::Full)?;
+
+                  // This tick's slot has already been checked
+                  let expires = super::get_time() + ticks.max(1);
+                  wheel.timers[idx] = Some(Timer {
+                        generation,
+                        expires,
+                        period,
+                        context,
+                        callback,
+                  });
+                  wheel.slots[expires as usize % WHEEL_SLOTS] |= 1 << idx;
+                  Ok(TimerId { idx, generation })
+            })
+            .ok_or(TimerError::Contended)?
+      })
+}
+
+/// Returns the tick the next timer expires on, or `None` if none are waiting
+/// to.
+///
+/// Returns the current tick if the wheel's being used somewhere else.
+pub(super) fn next_expiry() -> Option<u64> {
+      interrupts::without_interrupts(|| {
+            WHEEL.map(|wheel| {
+                  wheel.timers
+                        .iter()
+                        .enumerate()
+                        // Expired deferred timers are waiting on the timer task
+                        .filter(|(idx, _)| wheel.pending & 1 << idx == 0)
+                        .filter_map(|(_, timer)| timer.as_ref())
+                        .map(|timer| timer.expires)
+                        .min()
+            })
+      })
+      .unwrap_or(Some(super::get_time()))
+}
+
+/// Runs the IRQ callbacks of the timers expiring this tick, and wakes the
+/// timer task if any deferred ones expired.
+///
+/// Called by the timer handler after increasing the time, and for each tick
+/// skipped while idle. Ticks the wheel was contended on are caught up on the
+/// next one it isn't.
+#[unsafe(no_mangle)]
+pub(super) extern "sysv64" fn run_timers() {
+      let now = super::get_time();
+      let mut callbacks: [Option<fn()>; MAX_TIMERS] = [None; MAX_TIMERS];
+      let mut deferred = false;
+
+      let ran = WHEEL.map(|wheel| {
+            // Every slot has been checked once WHEEL_SLOTS ticks are caught up
+            let missed = MISSED.swap(0, Ordering::Relaxed);
+            let first = now.saturating_sub(missed.min(WHEEL_SLOTS as u64 - 1));
+            for tick in first..=now {
+                  let slot = tick as usize % WHEEL_SLOTS;
+                  deferred |= run_slot(wheel, slot, now, &mut callbacks);
+            }
+      });
+      if ran.is_none() {
+            MISSED.fetch_add(1, Ordering::Relaxed);
+      }
+
+      // Ran after releasing the wheel so callbacks can add timers
+      for callback in callbacks.into_iter().flatten() {
+            callback()
+      }
+
+      if deferred {
+            DEFERRED.fire()
+      }
+}
+
+/// Expires the timers in `slot` which are due by tick `now`, adding the
+/// callbacks of IRQ timers to `callbacks` and returning whether any deferred
+/// timers expired.
+fn run_slot(
+      wheel: &mut Wheel, slot: usize, now: u64,
+      callbacks: &mut [Option<fn()>; MAX_TIMERS],
+) -> bool {
+      let mut due = wheel.slots[slot];
+      let mut deferred = false;
+
+      while due != 0 {
+            let idx = due.trailing_zeros() as usize;
+            let bit = 1 << idx;
+            due &= !bit;
+
+            let Some(timer) = &mut wheel.timers[idx] else {
+                  wheel.slots[slot] &= !bit;
+                  continue;
+            };
+            if timer.expires > now {
+                  continue; // expires on a later rotation
+            }
+
+            wheel.slots[slot] &= !bit;
+            match timer.context {
+                  Context::Irq => callbacks[idx] = Some(timer.callback),
+                  Context::Deferred => {
+                        wheel.pending |= bit;
+                        deferred = true;
+                  }
+            }
+
+            if timer.period != 0 {
+                  timer.expires = now + timer.period;
+                  wheel.slots[timer.expires as usize % WHEEL_SLOTS] |= bit;
+            } else if timer.context == Context::Irq {
+                  wheel.timers[idx] = None;
+            } // deferred timers are removed once ran
+      }
+
+      deferred
+}
+
+/// Runs the callbacks of expired deferred timers.
+pub async fn timer_task() {
+      loop {
+            // Created first so expiries while running aren't missed
+            let wait = DEFERRED.wait();
+            run_deferred();
+            wait.await
+      }
+}
+
+/// Runs and removes every expired deferred timer.
+fn run_deferred() {
+      loop {
+            let callback = interrupts::without_interrupts(|| {
+                  WHEEL.map(|wheel| {
+                        if wheel.pending == 0 {
+                              return None;
+                        }
+
+                        let idx = wheel.pending.trailing_zeros() as usize;
+                        wheel.pending &= !(1 << idx);
+                        let timer = wheel.timers[idx].as_ref()?;
+                        let callback = timer.callback;
+                        if timer.period == 0 {
+                              wheel.timers[idx] = None;
+                        }
+                        Some(callback)
+                  })
+            });
+
+            match callback {
+                  Some(Some(callback)) => callback(),
+                  Some(None) => (), // cancelled after expiring
+                  None => return,   // nothing left or contended
+            }
+      }
+}
+
+impl Wheel {
+      /// Creates a wheel without any timers.
+      const fn new() -> Self {
+            Wheel {
+                  timers:  [const { None }; MAX_TIMERS],
+                  slots:   [0; WHEEL_SLOTS],
+                  pending: 0,
+            }
+      }
+}
+
+/// An error created when registering a timer.
+#[derive(Error, Debug, PartialEq)]
+pub enum TimerError {
+      #[error("already running the max of {MAX_TIMERS} timers")]
+      Full,
+
+      #[error("periodic timers can't have a period of zero")]
+      ZeroPeriod,
+
+      #[error("the timer wheel is being used somewhere else")]
+      Contended,
+}
+
+#[cfg(test)]
+mod tests {
+      use core::sync::atomic::AtomicU32;
+
+      use super::*;
+      use crate::time;
+
+      /// Tests that one shot timers run once, and cancelled ones never run.
+      #[test_case]
+      fn one_shot_timers_run_once() {
+            static RAN: AtomicU32 = AtomicU32::new(0);
+            fn inc() {
+                  RAN.fetch_add(1, Ordering::Relaxed);
+            }
+
+            let id = one_shot(2, Context::Irq, inc).unwrap();
+            let cancelled = one_shot(2, Context::Irq, inc).unwrap();
+            assert!(cancel(cancelled));
+
+            time::wait(5);
+            assert_eq!(RAN.load(Ordering::Relaxed), 1);
+            assert!(!cancel(id)); // already finished
+      }
+
+      /// Tests that periodic timers keep running until cancelled, even if
+      /// their period is longer than the wheel.
+      #[test_case]
+      fn periodic_timers_repeat() {
+            static SHORT: AtomicU32 = AtomicU32::new(0);
+            static LONG: AtomicU32 = AtomicU32::new(0);
+
+            let short = periodic(2, Context::Irq, || {
+                  SHORT.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+            let long = periodic(WHEEL_SLOTS as u64 + 1, Context::Irq, || {
+                  LONG.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+
+            time::wait(10);
+            assert!(cancel(short));
+            assert!(SHORT.load(Ordering::Relaxed) >= 3);
+            assert_eq!(LONG.load(Ordering::Relaxed), 0);
+            assert!(cancel(long));
+            assert_eq!(
+                  periodic(0, Context::Irq, || ()),
+                  Err(TimerError::ZeroPeriod)
+            );
+      }
+
+      /// Tests that deferred timers wait to be ran outside of the IRQ.
+      #[test_case]
+      fn deferred_timers_run_outside_irq() {
+            static RAN: AtomicU32 = AtomicU32::new(0);
+            one_shot(1, Context::Deferred, || {
+                  RAN.fetch_add(1, Ordering::Relaxed);
+            })
+            .unwrap();
+
+            time::wait(3);
+            assert_eq!(RAN.load(Ordering::Relaxed), 0);
+            run_deferred();
+            assert_eq!(RAN.load(Ordering::Relaxed), 1);
+      }
+}
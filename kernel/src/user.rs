@@ -0,0 +1,452 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/user.rs
+
+    The user module runs programs in ring 3, killing them instead of the
+    kernel if they raise an exception.
+    This file is responsible for entering and leaving user mode, as well as
+    checking memory passed by programs.
+
+    Contains 4 submodules:
+    * elf.rs - Loads ELF executables from floppyfs files
+    * error.rs - Converts kernel errors into syscall error codes
+    * files.rs - Tracks the floppyfs files opened by programs
+    * syscall.rs - Handles the `syscall` instruction and dispatches syscalls
+*/
+
+use core::arch::naked_asm;
+use core::ffi::{CStr, c_char};
+use core::fmt::Display;
+use core::ops::Range;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use libfs::PROGRAM_INODE;
+use libutil::{ExclusiveMap, SysError};
+use thiserror::Error;
+
+use crate::gdt::{self, USER_CODE_OFFSET, USER_DATA_OFFSET};
+use crate::interrupts::{self, IntStackFrame};
+use crate::memory::heap::{HEAP_MAX_SIZE, HEAP_START};
+use crate::memory::paging::{self, MMIO_START, PageFlags};
+use crate::memory::stacks::STACKS_START;
+use crate::memory::{PAGE_SIZE, extable};
+use crate::thread::{self, Priority, ThreadId, sched};
+
+pub mod elf;
+mod error;
+mod files;
+mod syscall;
+
+pub use syscall::init as init_syscalls;
+
+/// The flags user programs start with, just external interrupts enabled.
+const USER_RFLAGS: u64 = 0x202;
+
+/// The first address programs can't access.
+///
+/// One page below the end of the lower half, as `sysret` raises #GP in ring 0
+/// if it returns to a non-canonical address, which a syscall at the very end
+/// of the lower half would.
+const USER_END: u64 = 0x_7FFF_FFFF_F000;
+
+/// The space reserved for lower half ranges which grow without a set limit,
+/// the 512 GiB mapped by a single PML4 entry.
+const GROWING_SIZE: u64 = 1 << 39;
+
+/// The lower half ranges the kernel maps it's own memory into, which programs
+/// can't be loaded into.
+const RESERVED: [Range<u64>; 3] = [
+      STACKS_START..STACKS_START + GROWING_SIZE,
+      HEAP_START..HEAP_START + HEAP_MAX_SIZE,
+      MMIO_START..MMIO_START + GROWING_SIZE,
+];
+
+/// How a user program stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+      /// Exited with the exit code passed to the exit syscall.
+      Exited(u64),
+
+      /// Killed after raising the exception `cause` at `ip`.
+      Killed { cause: &'static CStr, ip: u64 },
+}
+
+/// Returned by `enter_asm` once the program stops running.
+#[repr(C)]
+struct RawExit {
+      cause: *const c_char,
+      value: u64,
+}
+
+/// Runs the user program at `entry` with it's stack pointer set to `stack` on
+/// the current thread, until it stops running.
+///
+/// The current thread must have been spawned with `thread::spawn`, as
+/// interrupts raised by the program use the thread's stack.
+///
+/// # Safety
+/// The program can access any user accessible memory, so the caller must
+/// ensure that nothing the kernel relies on is user accessible.
+pub unsafe fn enter_user(
+      entry: u64, stack: u64,
+) -> Result<ExitStatus, UserError> {
+      let saved = sched::user_rsp_ptr().ok_or(UserError::NoStack)?;
+      let rsp0 = gdt::rsp0_ptr().ok_or(UserError::NoTss)?;
+      let flags = PageFlags::PRESENT | PageFlags::USER;
+      if !user_mapped(entry, flags) ||
+            !user_mapped(stack.wrapping_sub(8), flags)
+      {
+            return Err(UserError::NotUserMapped);
+      }
+      interrupts::clear_typed();
+
+      // Safety: The caller ensures the program can't break the kernel, and
+      // saved & rsp0 stay valid while the thread is running
+      let exit = interrupts::without_interrupts(|| unsafe {
+            enter_asm(saved, entry, stack, rsp0)
+      });
+      files::close_all(thread::current());
+
+      if exit.cause.is_null() {
+            return Ok(ExitStatus::Exited(exit.value));
+      }
+
+      // Safety: kill only ever leaves with the cause of the exception
+      let cause = unsafe { CStr::from_ptr(exit.cause) };
+      Ok(ExitStatus::Killed {
+            cause,
+            ip: exit.value,
+      })
+}
+
+/// Runs the program seeder copied into [`PROGRAM_INODE`] on it's own thread,
+/// unless the last program started this way is still running.
+pub fn run_program() {
+      /// The thread running the last program started.
+      static THREAD: ExclusiveMap<Option<ThreadId>> = ExclusiveMap::new(None);
+
+      /// Is the last program started still running?
+      static RUNNING: AtomicBool = AtomicBool::new(false);
+
+      if RUNNING.swap(true, Ordering::Relaxed) {
+            return println!(fg = LightRed, "\nA program is already running");
+      }
+
+      let spawned = THREAD.map(|thread| {
+            // The thread has finished or is just about to
+            if let Some(id) = thread.take() {
+                  _ = thread::join(id);
+            }
+
+            let res = thread::spawn("user program", Priority::Normal, || {
+                  match elf::exec(PROGRAM_INODE, &["program"]) {
+                        Ok(status) => println!("\nProgram {status}"),
+                        Err(e) => {
+                              println!(
+                                    fg = LightRed,
+                                    "\nFailed running program: {e}"
+                              )
+                        }
+                  }
+                  RUNNING.store(false, Ordering::Relaxed);
+            });
+
+            match res {
+                  Ok(id) => *thread = Some(id),
+                  Err(ref e) => println!(
+                        fg = LightRed,
+                        "\nFailed spawning program: {e}"
+                  ),
+            }
+            res.is_ok()
+      });
+
+      if spawned != Some(true) {
+            RUNNING.store(false, Ordering::Relaxed);
+      }
+}
+
+/// Stops the user program which called the exit syscall, returning `code`
+/// from the program's [`enter_user`].
+fn exit(code: u64) -> ! {
+      // Safety: The pointer is valid as the thread is running
+      let saved = sched::user_rsp_ptr().map(|ptr| unsafe { ptr.replace(0) });
+      match saved {
+            // Safety: The thread saved it's kernel state there when entering
+            // user mode
+            Some(saved) if saved != 0 => unsafe {
+                  leave_asm(saved, ptr::null(), code)
+            },
+            _ => PANIC!(badbug "Exit syscall ran without a saved kernel state"),
+      }
+}
+
+/// Kills the user program which raised exception `cause`, printing a report
+/// followed by `info` then returning from the program's [`enter_user`].
+pub fn kill(
+      cause: &'static CStr, frame: &IntStackFrame, info: impl FnOnce(),
+) -> ! {
+      let cause_str = cause.to_str().unwrap_or("UNKNOWN");
+      println!(fg = LightRed, "\nUser program killed: {cause_str}");
+      println!("{frame}");
+      info();
+
+      // Safety: The pointer is valid as the thread is running
+      let saved = sched::user_rsp_ptr().map(|ptr| unsafe { ptr.replace(0) });
+      match saved {
+            // Safety: The thread saved it's kernel state there when entering
+            // user mode
+            Some(saved) if saved != 0 => unsafe {
+                  leave_asm(saved, cause.as_ptr(), frame.ip)
+            },
+            _ => PANIC!(badbug "User program raised {cause_str} without a \
+                        saved kernel state"),
+      }
+}
+
+/// Returns an error if any of `start..end` is in a [`RESERVED`] range.
+fn check_reserved(start: u64, end: u64) -> Result<(), UserError> {
+      match RESERVED.iter().find(|r| start < r.end && r.start < end) {
+            Some(range) => Err(UserError::Reserved(start.max(range.start))),
+            None => Ok(()),
+      }
+}
+
+/// Returns the buffer at `ptr` with length `len` passed by a program, if it's
+/// in the lower half and every page of it is mapped with `flags`.
+///
+/// # Safety
+/// The buffer must not be used after the program stops running.
+unsafe fn user_buf(
+      ptr: u64, len: u64, flags: PageFlags,
+) -> Result<*mut [u8], SysError> {
+      let end = ptr
+            .checked_add(len)
+            .filter(|end| *end <= USER_END)
+            .ok_or(SysError::BadAddress)?;
+
+      let flags = flags | PageFlags::PRESENT | PageFlags::USER;
+      let mut page = ptr & !(PAGE_SIZE - 1);
+      while page < end {
+            if !user_mapped(page, flags) {
+                  return Err(SysError::BadAddress);
+            }
+            page += PAGE_SIZE;
+      }
+
+      Ok(ptr::slice_from_raw_parts_mut(ptr as *mut u8, len as usize))
+}
+
+/// Returns the buffer at `ptr` with length `len` passed by a program if it
+/// can be read.
+fn user_slice(ptr: u64, len: u64) -> Result<&'static [u8], SysError> {
+      if len == 0 {
+            return Ok(&[]);
+      }
+
+      // Safety: The buffer is mapped and syscalls only use it while the
+      // program is running
+      unsafe { Ok(&*user_buf(ptr, len, PageFlags::empty())?) }
+}
+
+/// Returns the buffer at `ptr` with length `len` passed by a program if it
+/// can be written to.
+fn user_slice_mut(ptr: u64, len: u64) -> Result<&'static mut [u8], SysError> {
+      if len == 0 {
+            return Ok(&mut []);
+      }
+
+      // Safety: The buffer is mapped writable and syscalls only use it while
+      // the program is running
+      unsafe { Ok(&mut *user_buf(ptr, len, PageFlags::WRITABLE)?) }
+}
+
+/// Copies the buffer at `src` passed by a program into `dst`, failing instead
+/// of faulting if any of it can't be read.
+fn copy_from_user(dst: &mut [u8], src: u64) -> Result<(), SysError> {
+      // Safety: The buffer is only used for the copy below
+      let src = unsafe { user_buf(src, dst.len() as u64, PageFlags::empty())? };
+
+      // Safety: The buffer is user accessible, so faults are only ever from it
+      // being unmapped since being checked, which copy_checked recovers from
+      unsafe {
+            extable::copy_checked(dst.as_mut_ptr(), src as *const u8, dst.len())
+      }
+      .map_err(|_| SysError::BadAddress)
+}
+
+/// Returns whether `addr` is mapped with `flags` at every level.
+fn user_mapped(addr: u64, flags: PageFlags) -> bool {
+      paging::walk(addr).is_ok_and(|walk| {
+            walk.entries.iter().all(|entry| {
+                  entry.is_some_and(|e| {
+                        PageFlags::from_bits_truncate(e).contains(flags)
+                  })
+            })
+      })
+}
+
+/// Saves the callee-saved registers to the thread's stack, storing where to
+/// in both `saved` and `rsp0`, then jumps to `entry` in ring 3.
+///
+/// Returns once `leave_asm` is called.
+#[unsafe(naked)]
+#[rustfmt::skip]
+unsafe extern "sysv64" fn enter_asm(
+      saved: *mut u64, entry: u64, stack: u64, rsp0: *mut u64,
+) -> RawExit {
+      naked_asm!(
+            "push rbp",
+            "push rbx",
+            "push r12",
+            "push r13",
+            "push r14",
+            "push r15",
+            "mov [rdi], rsp", // where leave_asm returns from
+            "mov [rcx], rsp", // interrupts in ring 3 use the stack below it
+
+            // iretq frame
+            "push {ss}",
+            "push rdx",       // stack
+            "push {rflags}",
+            "push {cs}",
+            "push rsi",       // entry
+
+            // Don't leak any kernel values to the program
+            "xor eax, eax",
+            "xor ebx, ebx",
+            "xor ecx, ecx",
+            "xor edx, edx",
+            "xor esi, esi",
+            "xor edi, edi",
+            "xor ebp, ebp",
+            "xor r8d, r8d",
+            "xor r9d, r9d",
+            "xor r10d, r10d",
+            "xor r11d, r11d",
+            "xor r12d, r12d",
+            "xor r13d, r13d",
+            "xor r14d, r14d",
+            "xor r15d, r15d",
+            "iretq",
+            ss = const USER_DATA_OFFSET | 3,
+            cs = const USER_CODE_OFFSET | 3,
+            rflags = const USER_RFLAGS,
+      )
+}
+
+/// Switches back to the kernel state at `saved`, returning from `enter_asm`
+/// with `cause` and `value`.
+#[unsafe(naked)]
+unsafe extern "sysv64" fn leave_asm(
+      saved: u64, cause: *const c_char, value: u64,
+) -> ! {
+      naked_asm!(
+            "mov rsp, rdi",
+            "mov rax, rsi", // RawExit is returned in rax & rdx
+            "pop r15", "pop r14", "pop r13", "pop r12", "pop rbx", "pop rbp",
+            "ret",
+      )
+}
+
+impl Display for ExitStatus {
+      fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            match self {
+                  ExitStatus::Exited(code) => {
+                        write!(f, "exited with code {code}")
+                  }
+                  ExitStatus::Killed { cause, ip } => {
+                        let cause = cause.to_str().unwrap_or("UNKNOWN");
+                        write!(f, "killed by {cause} at 0x{ip:x}")
+                  }
+            }
+      }
+}
+
+/// An error created when entering user mode.
+#[derive(Error, Debug)]
+pub enum UserError {
+      #[error("the current thread doesn't have it's own stack")]
+      NoStack,
+
+      #[error("the TSS hasn't been loaded")]
+      NoTss,
+
+      #[error("the program's entry or stack isn't user accessible")]
+      NotUserMapped,
+
+      #[error("0x{0:x} is reserved for the kernel")]
+      Reserved(u64),
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+      use crate::memory::{PAGE_SIZE, frames, phys_to_virt};
+      use crate::thread::{self, Priority};
+
+      /// Where the test program is mapped.
+      const CODE: u64 = 0x_6666_0000_0000;
+
+      /// The top of the test program's stack.
+      const STACK: u64 = CODE + 3 * PAGE_SIZE;
+
+      /// Tests that programs raising exceptions are killed instead of the
+      /// kernel.
+      #[test_case]
+      fn user_exceptions_kill_program() {
+            let id = thread::spawn("user test", Priority::Normal, || unsafe {
+                  let code = frames::alloc_frame().unwrap();
+                  let flags = PageFlags::USER;
+                  paging::map(CODE, code, flags).unwrap();
+                  phys_to_virt(code.addr()).unwrap().write(0xF4); // hlt
+
+                  let stack = frames::alloc_frame().unwrap();
+                  let flags =
+                        flags | PageFlags::WRITABLE | PageFlags::NO_EXECUTE;
+                  paging::map(STACK - PAGE_SIZE, stack, flags).unwrap();
+
+                  let status = enter_user(CODE, STACK).unwrap();
+                  assert_eq!(
+                        status,
+                        ExitStatus::Killed {
+                              cause: c"GP FAULT",
+                              ip:    CODE,
+                        }
+                  );
+
+                  for page in [CODE, STACK - PAGE_SIZE] {
+                        frames::free_frame(paging::unmap(page).unwrap())
+                              .unwrap()
+                  }
+            })
+            .unwrap();
+
+            thread::join(id).unwrap();
+      }
+
+      /// Tests that only threads with their own stack can enter user mode.
+      #[test_case]
+      fn enter_user_requires_own_stack() {
+            let res = unsafe { enter_user(CODE, STACK) };
+            assert!(matches!(res, Err(UserError::NoStack)));
+      }
+}
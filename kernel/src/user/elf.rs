@@ -0,0 +1,463 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/user/elf.rs
+
+    Loads static ELF64 executables from floppyfs files and runs them.
+    Contained within the user module
+*/
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ptr;
+
+use libutil::InitError;
+use thiserror::Error;
+
+use super::{ExitStatus, USER_END, UserError};
+use crate::floppy::floppyfs::{self, FileError};
+use crate::memory::paging::{self, MapError, PageFlags};
+use crate::memory::{PAGE_SIZE, frames, phys_to_virt};
+use crate::thread;
+
+/// The top of every program's stack.
+const STACK_TOP: u64 = 0x_7FFF_FFFF_0000;
+
+/// The number of pages in every program's stack.
+const STACK_PAGES: u64 = 4;
+
+/// The magic number at the start of every ELF file.
+const ELF_MAGIC: [u8; 4] = *b"\x7FELF";
+
+/// The `e_type` of executables which aren't position independent.
+const ET_EXEC: u16 = 2;
+
+/// The `e_machine` of x86_64 executables.
+const EM_X86_64: u16 = 0x3E;
+
+/// The `p_type` of segments which are loaded into memory.
+const PT_LOAD: u32 = 1;
+
+/// Set in `p_flags` if the segment is executable.
+const PF_X: u32 = 1;
+
+/// Set in `p_flags` if the segment is writable.
+const PF_W: u32 = 2;
+
+/// The header at the start of an ELF64 file.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ElfHeader {
+      ident:     [u8; 16],
+      kind:      u16,
+      machine:   u16,
+      version:   u32,
+      entry:     u64,
+      phoff:     u64,
+      shoff:     u64,
+      flags:     u32,
+      ehsize:    u16,
+      phentsize: u16,
+      phnum:     u16,
+      shentsize: u16,
+      shnum:     u16,
+      shstrndx:  u16,
+}
+
+/// Describes a segment of an ELF64 file.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct ProgramHeader {
+      kind:   u32,
+      flags:  u32,
+      offset: u64,
+      vaddr:  u64,
+      paddr:  u64,
+      filesz: u64,
+      memsz:  u64,
+      align:  u64,
+}
+
+/// The pages mapped for a program, which are unmapped and freed when dropped.
+struct Mapped(Vec<u64>);
+
+/// Loads the executable in inode `inode` and runs it on the current thread
+/// with `args` as it's arguments, until it stops running.
+///
+/// The current thread must have been spawned with `thread::spawn`.
+pub fn exec(inode: usize, args: &[&str]) -> Result<ExitStatus, ElfError> {
+      let mut file = vec![0; floppyfs::file_inode(inode)?.size() as usize];
+      thread::block_on(floppyfs::read_file(inode, 0, &mut file))?;
+
+      let header = ElfHeader::parse(&file)?;
+      let (mut mapped, mut entry_ok) = (Mapped(Vec::new()), false);
+      for idx in 0..header.phnum as usize {
+            let ph = ProgramHeader::parse(&file, &header, idx)?;
+            if ph.kind != PT_LOAD {
+                  continue;
+            }
+
+            load_segment(&file, &ph, &mut mapped)?;
+            let segment = ph.vaddr..ph.vaddr + ph.memsz;
+            entry_ok |= ph.flags & PF_X != 0 && segment.contains(&header.entry);
+      }
+
+      if !entry_ok {
+            return Err(ElfError::BadEntry(header.entry));
+      }
+
+      let stack = setup_stack(args, &mut mapped)?;
+
+      // Safety: The only user accessible pages are the ones just mapped for
+      // the program, which are unmapped once it stops running
+      Ok(unsafe { super::enter_user(header.entry, stack)? })
+}
+
+/// Maps the pages of segment `ph` as user accessible, copying it's data from
+/// `file` and zeroing the rest.
+fn load_segment(
+      file: &[u8], ph: &ProgramHeader, mapped: &mut Mapped,
+) -> Result<(), ElfError> {
+      let bad = || ElfError::BadSegment(ph.vaddr);
+      let file_end = ph.offset.checked_add(ph.filesz).ok_or_else(bad)?;
+      let end = ph
+            .vaddr
+            .checked_add(ph.memsz)
+            .filter(|end| *end <= USER_END)
+            .ok_or_else(bad)?;
+      if ph.filesz > ph.memsz || file_end > file.len() as u64 {
+            return Err(bad());
+      } else if ph.vaddr < PAGE_SIZE {
+            return Err(bad()); // keep null pointers faulting
+      }
+      super::check_reserved(ph.vaddr, end)?;
+
+      let mut flags = PageFlags::PRESENT | PageFlags::USER;
+      if ph.flags & PF_W != 0 {
+            flags |= PageFlags::WRITABLE
+      }
+      if ph.flags & PF_X == 0 {
+            flags |= PageFlags::NO_EXECUTE
+      }
+
+      let data_end = ph.vaddr + ph.filesz;
+      let mut page = ph.vaddr & !(PAGE_SIZE - 1);
+      while page < end {
+            // Copy the part of the segment's data in this page
+            let (start, stop) =
+                  (page.max(ph.vaddr), data_end.min(page + PAGE_SIZE));
+            let data = match start < stop {
+                  true => {
+                        let offset = (ph.offset + start - ph.vaddr) as usize;
+                        &file[offset..offset + (stop - start) as usize]
+                  }
+                  false => &[],
+            };
+
+            map_page(page, flags, (start - page) as usize, data, mapped)?;
+            page += PAGE_SIZE;
+      }
+
+      Ok(())
+}
+
+/// Maps the program's stack, pushing `args` onto it followed by `argc`,
+/// `argv`, an empty `envp` and auxiliary vector, returning the stack pointer.
+fn setup_stack(args: &[&str], mapped: &mut Mapped) -> Result<u64, ElfError> {
+      let flags = PageFlags::PRESENT |
+            PageFlags::USER |
+            PageFlags::WRITABLE |
+            PageFlags::NO_EXECUTE;
+      let bottom = STACK_TOP - STACK_PAGES * PAGE_SIZE;
+      for page in (bottom..STACK_TOP).step_by(PAGE_SIZE as usize) {
+            map_page(page, flags, 0, &[], mapped)?;
+      }
+
+      // argc, argv, argv's null, envp's null & the auxv's AT_NULL pair
+      let mut words = Vec::with_capacity(args.len() + 5);
+      words.push(args.len() as u64);
+
+      let strs = args.iter().map(|a| a.len() as u64 + 1).sum::<u64>();
+      let mut str_ptr = STACK_TOP - strs;
+      let rsp = (str_ptr - (args.len() as u64 + 5) * 8) & !0xF;
+      if STACK_TOP - rsp > PAGE_SIZE {
+            return Err(ElfError::TooManyArgs);
+      }
+
+      for arg in args {
+            // Safety: The string fits within the stack, which was just mapped
+            // as writable
+            unsafe {
+                  let dst = str_ptr as *mut u8;
+                  ptr::copy_nonoverlapping(arg.as_ptr(), dst, arg.len());
+                  dst.add(arg.len()).write(0);
+            }
+            words.push(str_ptr);
+            str_ptr += arg.len() as u64 + 1;
+      }
+      words.extend([0; 4]);
+
+      // Safety: Same as above
+      unsafe {
+            ptr::copy_nonoverlapping(
+                  words.as_ptr(),
+                  rsp as *mut u64,
+                  words.len(),
+            )
+      };
+      Ok(rsp)
+}
+
+/// Maps `page` to a new zeroed frame with `flags`, copying `data` to `offset`
+/// bytes into it.
+fn map_page(
+      page: u64, flags: PageFlags, offset: usize, data: &[u8],
+      mapped: &mut Mapped,
+) -> Result<(), ElfError> {
+      let frame = frames::alloc_frame().ok_or(ElfError::NoFrames)?;
+
+      // Safety: The frame was just allocated, so nothing else uses it, and
+      // the data fits within it
+      let res = unsafe {
+            phys_to_virt(frame.addr()).map_err(ElfError::from).and_then(
+                  |virt| {
+                        virt.write_bytes(0, PAGE_SIZE as usize);
+                        ptr::copy_nonoverlapping(
+                              data.as_ptr(),
+                              virt.add(offset),
+                              data.len(),
+                        );
+                        Ok(paging::map(page, frame, flags)?)
+                  },
+            )
+      };
+
+      match res {
+            Ok(()) => {
+                  mapped.0.push(page);
+                  Ok(())
+            }
+            Err(e) => {
+                  // Safety: The frame was never mapped
+                  _ = unsafe { frames::free_frame(frame) };
+                  Err(e)
+            }
+      }
+}
+
+impl ElfHeader {
+      /// Reads and validates the header at the start of `file`.
+      fn parse(file: &[u8]) -> Result<ElfHeader, ElfError> {
+            if file.len() < size_of::<ElfHeader>() {
+                  return Err(ElfError::BadMagic);
+            }
+
+            // Safety: The file is large enough, and any bytes are valid
+            let header =
+                  unsafe { file.as_ptr().cast::<ElfHeader>().read_unaligned() };
+            let ident = header.ident;
+            if ident[..4] != ELF_MAGIC {
+                  Err(ElfError::BadMagic)
+            } else if ident[4] != 2 {
+                  Err(ElfError::NotElf64)
+            } else if ident[5] != 1 {
+                  Err(ElfError::NotLittleEndian)
+            } else if ident[6] != 1 || header.version != 1 {
+                  Err(ElfError::BadVersion)
+            } else if header.kind != ET_EXEC {
+                  Err(ElfError::NotExecutable)
+            } else if header.machine != EM_X86_64 {
+                  Err(ElfError::NotX86_64)
+            } else if header.phentsize as usize != size_of::<ProgramHeader>() {
+                  Err(ElfError::BadHeader)
+            } else {
+                  Ok(header)
+            }
+      }
+}
+
+impl ProgramHeader {
+      /// Reads the `idx`th program header in `file`.
+      fn parse(
+            file: &[u8], header: &ElfHeader, idx: usize,
+      ) -> Result<ProgramHeader, ElfError> {
+            let size = size_of::<ProgramHeader>();
+            let start = (header.phoff as usize)
+                  .checked_add(idx * size)
+                  .filter(|start| start + size <= file.len())
+                  .ok_or(ElfError::BadHeader)?;
+
+            // Safety: The header is within the file, and any bytes are valid
+            Ok(unsafe {
+                  file.as_ptr()
+                        .add(start)
+                        .cast::<ProgramHeader>()
+                        .read_unaligned()
+            })
+      }
+}
+
+impl Drop for Mapped {
+      fn drop(&mut self) {
+            for page in self.0.drain(..) {
+                  // Safety: The program has stopped running, so nothing uses
+                  // it's pages anymore
+                  unsafe {
+                        if let Ok(frame) = paging::unmap(page) {
+                              _ = frames::free_frame(frame);
+                        }
+                  }
+            }
+      }
+}
+
+/// An error created when loading an executable.
+#[derive(Error, Debug)]
+pub enum ElfError {
+      #[error("file error: {0}")]
+      File(#[from] FileError),
+
+      #[error("the file isn't an ELF file")]
+      BadMagic,
+
+      #[error("the file isn't a 64 bit ELF file")]
+      NotElf64,
+
+      #[error("the file isn't little endian")]
+      NotLittleEndian,
+
+      #[error("the file has an unknown ELF version")]
+      BadVersion,
+
+      #[error("the file isn't a static executable")]
+      NotExecutable,
+
+      #[error("the file isn't an x86_64 executable")]
+      NotX86_64,
+
+      #[error("the file's program headers are malformed")]
+      BadHeader,
+
+      #[error("the segment at 0x{0:x} can't be loaded")]
+      BadSegment(u64),
+
+      #[error("the entry point 0x{0:x} isn't in an executable segment")]
+      BadEntry(u64),
+
+      #[error("the arguments don't fit on the stack")]
+      TooManyArgs,
+
+      #[error("ran out of frames to load the program into")]
+      NoFrames,
+
+      #[error(transparent)]
+      Map(#[from] MapError),
+
+      #[error(transparent)]
+      NoOffset(#[from] InitError<u64>),
+
+      #[error(transparent)]
+      User(#[from] UserError),
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+      use crate::memory::heap::HEAP_START;
+
+      /// Returns the header of a valid executable.
+      fn good_header() -> [u8; size_of::<ElfHeader>()] {
+            let mut buf = [0; size_of::<ElfHeader>()];
+            buf[..7].copy_from_slice(b"\x7FELF\x02\x01\x01");
+            buf[16..18].copy_from_slice(&ET_EXEC.to_le_bytes());
+            buf[18..20].copy_from_slice(&EM_X86_64.to_le_bytes());
+            buf[20..24].copy_from_slice(&1u32.to_le_bytes());
+            buf[54..56].copy_from_slice(&56u16.to_le_bytes());
+            buf
+      }
+
+      /// Tests that only valid ELF headers are accepted.
+      #[test_case]
+      fn elf_headers_are_validated() {
+            assert!(ElfHeader::parse(&good_header()).is_ok());
+            assert!(matches!(
+                  ElfHeader::parse(&[0x7F]),
+                  Err(ElfError::BadMagic)
+            ));
+
+            let mut buf = good_header();
+            buf[4] = 1; // 32 bit
+            assert!(matches!(ElfHeader::parse(&buf), Err(ElfError::NotElf64)));
+
+            let mut buf = good_header();
+            buf[16] = 3; // position independent
+            let res = ElfHeader::parse(&buf);
+            assert!(matches!(res, Err(ElfError::NotExecutable)));
+
+            let mut buf = good_header();
+            buf[18] = 0x28; // ARM
+            assert!(matches!(ElfHeader::parse(&buf), Err(ElfError::NotX86_64)));
+      }
+
+      /// Tests that segments in the null page or kernel half aren't loaded.
+      #[test_case]
+      fn bad_segments_are_rejected() {
+            let mut ph = ProgramHeader {
+                  kind:   PT_LOAD,
+                  flags:  PF_X,
+                  offset: 0,
+                  vaddr:  0,
+                  paddr:  0,
+                  filesz: 0,
+                  memsz:  PAGE_SIZE,
+                  align:  PAGE_SIZE,
+            };
+            let mut mapped = Mapped(Vec::new());
+            let res = load_segment(&[], &ph, &mut mapped);
+            assert!(matches!(res, Err(ElfError::BadSegment(0))));
+
+            ph.vaddr = USER_END;
+            let res = load_segment(&[], &ph, &mut mapped);
+            assert!(matches!(res, Err(ElfError::BadSegment(USER_END))));
+            assert!(mapped.0.is_empty());
+      }
+
+      /// Tests that segments overlapping the kernel's lower half ranges
+      /// aren't loaded.
+      #[test_case]
+      fn reserved_segments_are_rejected() {
+            let ph = ProgramHeader {
+                  kind:   PT_LOAD,
+                  flags:  PF_X,
+                  offset: 0,
+                  vaddr:  HEAP_START - PAGE_SIZE,
+                  paddr:  0,
+                  filesz: 0,
+                  memsz:  2 * PAGE_SIZE,
+                  align:  PAGE_SIZE,
+            };
+            let mut mapped = Mapped(Vec::new());
+            let res = load_segment(&[], &ph, &mut mapped);
+            assert!(matches!(
+                  res,
+                  Err(ElfError::User(UserError::Reserved(HEAP_START)))
+            ));
+            assert!(mapped.0.is_empty());
+      }
+}
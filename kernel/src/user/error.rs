@@ -0,0 +1,112 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/user/error.rs
+
+    Converts the errors created while running syscalls into the stable error
+    codes returned to programs.
+    Contained within the user module
+*/
+
+use libfs::table::UpdateInodeError;
+use libutil::SysError;
+
+use crate::floppy::FloppyError;
+use crate::floppy::disk::DiskError;
+use crate::floppy::floppyfs::FileError;
+
+impl From<FileError> for SysError {
+      fn from(err: FileError) -> Self {
+            match err {
+                  FileError::NoFilesystem => SysError::NoFilesystem,
+                  FileError::NoFile(_) => SysError::NoFile,
+                  FileError::IsDirectory(_) => SysError::IsDirectory,
+                  FileError::TooBig => SysError::FileTooBig,
+                  FileError::NoSpace => SysError::NoSpace,
+                  FileError::Contended(_) | FileError::NoCache => {
+                        SysError::Busy
+                  }
+                  FileError::FloppyError(e) => e.into(),
+                  FileError::UpdateInode(UpdateInodeError::WriteError(e)) => {
+                        e.into()
+                  }
+                  FileError::UpdateInode(UpdateInodeError::TblExmapFailure) => {
+                        SysError::Busy
+                  }
+                  FileError::UpdateInode(UpdateInodeError::NullPtr) => {
+                        SysError::Io
+                  }
+            }
+      }
+}
+
+impl From<FloppyError> for SysError {
+      fn from(err: FloppyError) -> Self {
+            match err {
+                  FloppyError::ReadOrWrite(e) => e.into(),
+                  FloppyError::FifoTimeout(_) => SysError::FifoTimeout,
+                  FloppyError::InitStatic(_) => SysError::ControllerUninit,
+                  FloppyError::SendCommand(_) |
+                  FloppyError::SenseInterrupt(_) |
+                  FloppyError::Dma(_) |
+                  FloppyError::Irq(_) |
+                  FloppyError::Other(_) => SysError::Io,
+            }
+      }
+}
+
+impl From<DiskError> for SysError {
+      fn from(err: DiskError) -> Self {
+            match err {
+                  DiskError::BadBufLen(_) => SysError::BadBufLen,
+                  DiskError::ControllerUninit => SysError::ControllerUninit,
+                  DiskError::SendCommandTimeout => SysError::SendCommandTimeout,
+                  DiskError::IoTimeout => SysError::IoTimeout,
+                  DiskError::FifoTimeout => SysError::FifoTimeout,
+                  DiskError::BadSectOrHead(..) => SysError::BadSectOrHead,
+                  DiskError::NotWritable => SysError::NotWritable,
+                  DiskError::EndOfDrive => SysError::EndOfDrive,
+                  DiskError::BadSt0Bits => SysError::BadSt0Bits,
+                  DiskError::DriveNotReady => SysError::DriveNotReady,
+                  DiskError::NoDataFound => SysError::NoDataFound,
+                  DiskError::ControllerTimeout => SysError::ControllerTimeout,
+                  DiskError::EndOfCylinder => SysError::EndOfCylinder,
+                  DiskError::CRCError => SysError::CRCError,
+                  DiskError::BadCylinder => SysError::BadCylinder,
+                  DiskError::DifferingCylinder => SysError::DifferingCylinder,
+                  DiskError::NoAddressMark => SysError::NoAddressMark,
+            }
+      }
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that floppy errors keep their disk error's code.
+      #[test_case]
+      fn disk_errors_keep_code() {
+            let err = FloppyError::ReadOrWrite(DiskError::NoAddressMark);
+            assert_eq!(SysError::from(err).code(), 48);
+
+            let err = FileError::FloppyError(DiskError::EndOfDrive.into());
+            assert_eq!(SysError::from(err), SysError::EndOfDrive);
+            assert_eq!(SysError::from(FileError::NoFile(3)), SysError::NoFile);
+      }
+}
@@ -0,0 +1,180 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/user/files.rs
+
+    Tracks the floppyfs files opened by programs, where a file descriptor is
+    an index into the open file table.
+    Contained within the user module
+*/
+
+use alloc::vec;
+
+use libfs::INode;
+use libutil::{ExclusiveMap, SysError};
+
+use super::syscall::Args;
+use crate::floppy::floppyfs;
+use crate::thread::{self, ThreadId};
+
+/// The most files which can be open at once, by all programs.
+const MAX_FILES: usize = 16;
+
+/// Every file opened by a program.
+static FILES: ExclusiveMap<[Option<File>; MAX_FILES]> =
+      ExclusiveMap::new([const { None }; MAX_FILES]);
+
+/// A file opened by a program.
+struct File {
+      /// The thread running the program which opened the file.
+      owner: ThreadId,
+
+      /// The index of the file's inode in the inode table.
+      inode: usize,
+
+      /// The byte reads and writes start from.
+      pos: usize,
+}
+
+/// `open(inode)`
+pub fn open([inode, ..]: Args) -> Result<u64, SysError> {
+      let inode = inode as usize;
+      floppyfs::file_inode(inode)?;
+
+      let file = File {
+            owner: thread::current(),
+            inode,
+            pos: 0,
+      };
+
+      FILES.map(|files| {
+            let (fd, slot) = files
+                  .iter_mut()
+                  .enumerate()
+                  .find(|(_, f)| f.is_none())
+                  .ok_or(SysError::TooManyFiles)?;
+            *slot = Some(file);
+            Ok(fd as u64)
+      })
+      .ok_or(SysError::Busy)?
+}
+
+/// `read(fd, ptr, len)`
+pub fn read([fd, ptr, len, ..]: Args) -> Result<u64, SysError> {
+      let buf = super::user_slice_mut(ptr, len)?;
+      let (inode, pos) = with_file(fd, |f| (f.inode, f.pos))?;
+      let read = thread::block_on(floppyfs::read_file(inode, pos, buf))?;
+      with_file(fd, |f| f.pos = pos + read)?;
+      Ok(read as u64)
+}
+
+/// `write(fd, ptr, len)`
+pub fn write([fd, ptr, len, ..]: Args) -> Result<u64, SysError> {
+      if len > INode::MAX_SIZE as u64 {
+            return Err(SysError::FileTooBig);
+      }
+
+      // Copied out first, as the program's buffer might not stay mapped
+      let mut buf = vec![0; len as usize];
+      super::copy_from_user(&mut buf, ptr)?;
+      let (inode, pos) = with_file(fd, |f| (f.inode, f.pos))?;
+      thread::block_on(floppyfs::write_file(inode, pos, &buf))?;
+      with_file(fd, |f| f.pos = pos + buf.len())?;
+      Ok(len)
+}
+
+/// `close(fd)`
+pub fn close([fd, ..]: Args) -> Result<u64, SysError> {
+      let owner = thread::current();
+      FILES.map(|files| {
+            files.get_mut(fd as usize)
+                  .and_then(|f| f.take_if(|f| f.owner == owner))
+                  .map(|_| 0)
+                  .ok_or(SysError::BadFd)
+      })
+      .ok_or(SysError::Busy)?
+}
+
+/// Closes every file opened by a program ran by thread `owner`.
+pub fn close_all(owner: ThreadId) {
+      while FILES
+            .map(|files| {
+                  for file in files.iter_mut() {
+                        file.take_if(|f| f.owner == owner);
+                  }
+            })
+            .is_none()
+      {
+            thread::yield_now()
+      }
+}
+
+/// Runs `f` on file descriptor `fd`, if it was opened by the current thread.
+fn with_file<T>(
+      fd: u64, f: impl FnOnce(&mut File) -> T,
+) -> Result<T, SysError> {
+      let owner = thread::current();
+      FILES.map(|files| {
+            files.get_mut(fd as usize)
+                  .and_then(Option::as_mut)
+                  .filter(|file| file.owner == owner)
+                  .map(f)
+                  .ok_or(SysError::BadFd)
+      })
+      .ok_or(SysError::Busy)?
+}
+
+#[cfg(test)]
+mod tests {
+      use super::*;
+
+      /// Tests that file descriptors only work for the thread which opened
+      /// them.
+      #[test_case]
+      fn fds_are_per_thread() {
+            let fd = FILES
+                  .map(|files| {
+                        files[MAX_FILES - 1] = Some(File {
+                              owner: thread::current(),
+                              inode: 0,
+                              pos:   0,
+                        });
+                        MAX_FILES as u64 - 1
+                  })
+                  .unwrap();
+
+            assert_eq!(with_file(fd, |f| f.pos), Ok(0));
+            let id = thread::spawn(
+                  "fd test",
+                  thread::Priority::Normal,
+                  move || {
+                        assert_eq!(with_file(fd, |_| ()), Err(SysError::BadFd));
+                        assert_eq!(
+                              close([fd, 0, 0, 0, 0]),
+                              Err(SysError::BadFd)
+                        );
+                  },
+            )
+            .unwrap();
+            thread::join(id).unwrap();
+
+            close_all(thread::current());
+            assert_eq!(with_file(fd, |_| ()), Err(SysError::BadFd));
+      }
+}
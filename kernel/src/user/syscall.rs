@@ -0,0 +1,264 @@
+/* ---------------------------------------------------------------------------
+    Sunflower kernel - sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    kernel/src/user/syscall.rs
+
+    Handles the `syscall` instruction, dispatching syscalls through a table
+    ordered by their number in [`libutil::Syscall`].
+    Contained within the user module
+*/
+
+use core::arch::naked_asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use libutil::SysError;
+
+use super::{UserError, files};
+use crate::gdt::{self, CODE_SEGMENT_OFFSET, USER_DATA_OFFSET};
+use crate::msr::{self, Msr};
+use crate::startup::ExitCode;
+use crate::{interrupts, thread, time};
+
+/// The rflags bits cleared on syscall entry, the trap, interrupt & direction
+/// flags.
+const SFMASK: u64 = 0x700;
+
+/// The system call enable bit in the EFER.
+const EFER_SCE: u64 = 1;
+
+/// The most ticks a program can sleep for in one syscall, a day.
+const MAX_SLEEP: u64 = 24 * 60 * 60 * time::KERNEL_TICKS_HZ;
+
+/// The arguments passed to a syscall, in `rdi`, `rsi`, `rdx`, `r10` & `r8`.
+pub type Args = [u64; 5];
+
+/// A function which handles a syscall.
+type Handler = fn(Args) -> Result<u64, SysError>;
+
+/// Every syscall handler, indexed by the syscall's number.
+static SYSCALLS: [Handler; 10] = [
+      exit,         // Syscall::Exit
+      print,        // Syscall::Print
+      read_key,     // Syscall::ReadKey
+      sleep,        // Syscall::Sleep
+      uptime,       // Syscall::Uptime
+      files::open,  // Syscall::Open
+      files::read,  // Syscall::Read
+      files::write, // Syscall::Write
+      files::close, // Syscall::Close
+      time,         // Syscall::Time
+];
+
+/// Where the TSS's rsp0 is, which syscalls switch to the stack under.
+#[unsafe(no_mangle)]
+static SYSCALL_RSP0: AtomicU64 = AtomicU64::new(0);
+
+/// The program's stack pointer, saved while the kernel stack is found.
+#[unsafe(no_mangle)]
+static SYSCALL_USER_RSP: AtomicU64 = AtomicU64::new(0);
+
+/// The registers pushed by [`syscall_entry`], in order of lowest address.
+#[repr(C)]
+struct SyscallFrame {
+      num:    u64,
+      r9:     u64,
+      r8:     u64,
+      r10:    u64,
+      rdx:    u64,
+      rsi:    u64,
+      rdi:    u64,
+      rflags: u64,
+      ip:     u64,
+      sp:     u64,
+}
+
+/// Saves the program's registers, after switching to the kernel stack.
+macro_rules! save_user {
+      () => {
+            // rcx & r11 hold the program's rip & rflags
+            "push qword ptr [SYSCALL_USER_RSP]
+            push rcx
+            push r11
+            push rdi
+            push rsi
+            push rdx
+            push r10
+            push r8
+            push r9
+            push rax"
+      };
+}
+
+/// Restores the program's registers after an invocation of [`save_user!`],
+/// except `rax` which holds the syscall's result.
+macro_rules! restore_user {
+      () => {
+            "add rsp, 8
+            pop r9
+            pop r8
+            pop r10
+            pop rdx
+            pop rsi
+            pop rdi
+            pop r11
+            pop rcx
+            pop rsp"
+      };
+}
+
+/// Enables the `syscall` instruction, jumping to [`syscall_entry`].
+pub fn init() -> ExitCode<UserError> {
+      let Some(rsp0) = gdt::rsp0_ptr() else {
+            return ExitCode::Error(UserError::NoTss);
+      };
+      SYSCALL_RSP0.store(rsp0 as u64, Ordering::Relaxed);
+
+      // syscall loads the kernel segments, and sysret loads the user segments
+      // which are 8 & 16 bytes past the kernel data segment
+      let (code, user_data) =
+            (CODE_SEGMENT_OFFSET as u64, USER_DATA_OFFSET as u64);
+      let star = (code << 32) | ((user_data - 8) << 48);
+
+      // Safety: Every x86_64 CPU has these MSRs, and syscall_entry is able to
+      // handle syscalls
+      unsafe {
+            msr::write(Msr::Star, star);
+            msr::write(Msr::Lstar, syscall_entry as *const () as u64);
+            msr::write(Msr::Sfmask, SFMASK);
+            msr::write(Msr::Efer, msr::read(Msr::Efer) | EFER_SCE);
+      }
+
+      ExitCode::Ok
+}
+
+/// Ran when a program executes the `syscall` instruction.
+#[unsafe(naked)]
+extern "C" fn syscall_entry() -> ! {
+      naked_asm!(
+            // Interrupts are disabled by SFMASK until we're on the kernel
+            // stack
+            "mov [SYSCALL_USER_RSP], rsp",
+            "mov rsp, [SYSCALL_RSP0]",
+            "mov rsp, [rsp]", // under the thread's saved kernel state
+            "and rsp, -16",
+            save_user!(),
+            "mov rdi, rsp", // saved registers as first arg
+            "sti",          // syscalls can sleep
+            "call syscall_dispatch",
+            "cli", // don't handle interrupts on the program's stack
+            restore_user!(),
+            "sysretq",
+      )
+}
+
+/// Runs the syscall requested in `frame`, returning it's result or the
+/// negative error code if it failed.
+#[unsafe(no_mangle)]
+extern "sysv64" fn syscall_dispatch(frame: &SyscallFrame) -> i64 {
+      let args = [frame.rdi, frame.rsi, frame.rdx, frame.r10, frame.r8];
+      let handler = SYSCALLS.get(frame.num as usize);
+
+      match handler.map_or(Err(SysError::BadSyscall), |h| h(args)) {
+            Ok(res) => res as i64,
+            Err(e) => -e.code(),
+      }
+}
+
+/// `exit(code)`
+fn exit([code, ..]: Args) -> Result<u64, SysError> {
+      super::exit(code)
+}
+
+/// `print(ptr, len)`
+fn print([ptr, len, ..]: Args) -> Result<u64, SysError> {
+      for chunk in super::user_slice(ptr, len)?.utf8_chunks() {
+            print!("{}", chunk.valid());
+            if !chunk.invalid().is_empty() {
+                  print!("?")
+            }
+      }
+      Ok(len)
+}
+
+/// `read_key()`
+fn read_key(_: Args) -> Result<u64, SysError> {
+      loop {
+            if let Some(key) = interrupts::next_typed() {
+                  return Ok(key as u64);
+            }
+            thread::sleep(1)
+      }
+}
+
+/// `sleep(ticks)`
+fn sleep([ticks, ..]: Args) -> Result<u64, SysError> {
+      thread::sleep(ticks.min(MAX_SLEEP));
+      Ok(0)
+}
+
+/// `uptime()`
+fn uptime(_: Args) -> Result<u64, SysError> {
+      Ok(time::get_time())
+}
+
+/// `time()`
+fn time(_: Args) -> Result<u64, SysError> {
+      let now = time::now().ok_or(SysError::NoClock)?;
+      // Times before the epoch are clamped instead of wrapping around
+      Ok(now.to_unix().max(0) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+      use libutil::Syscall;
+
+      use super::*;
+
+      /// Tests that every syscall number has a handler in the table.
+      #[test_case]
+      fn syscall_table_matches_abi() {
+            let len = SYSCALLS.len() as u64;
+            assert_eq!(Syscall::from_num(len - 1), Some(Syscall::Time));
+            assert_eq!(Syscall::from_num(len), None);
+      }
+
+      /// Tests that unknown syscalls and bad pointers return error codes.
+      #[test_case]
+      fn syscalls_return_error_codes() {
+            let mut frame = SyscallFrame {
+                  num:    u64::MAX,
+                  r9:     0,
+                  r8:     0,
+                  r10:    0,
+                  rdx:    0,
+                  rsi:    1,
+                  rdi:    0x_8000_0000_0000,
+                  rflags: 0,
+                  ip:     0,
+                  sp:     0,
+            };
+            assert_eq!(syscall_dispatch(&frame), -SysError::BadSyscall.code());
+
+            frame.num = Syscall::Print as u64;
+            assert_eq!(syscall_dispatch(&frame), -SysError::BadAddress.code());
+
+            frame.num = Syscall::Uptime as u64;
+            assert!(syscall_dispatch(&frame) >= 0);
+      }
+}
@@ -42,6 +42,7 @@ use print::Corner;
 use crate::startup::ExitCode;
 use crate::sysinfo::SystemInfo;
 #[cfg(test)] use crate::tests::write_serial;
+use crate::time::{self, KERNEL_TICKS_HZ};
 
 pub mod buffers;
 pub mod cursor;
@@ -71,7 +72,19 @@ pub unsafe fn init() -> ExitCode<Infallible> {
       ExitCode::Infallible
 }
 
-/// Draws the topbar, ran every second by [`crate::interrupts::kbd_poll_loop`].
+/// Moves the cursor to where it should be every 100 ms, and redraws the topbar
+/// every second.
+pub async fn refresh_task() {
+      loop {
+            for _ in 0..10 {
+                  time::sleep(KERNEL_TICKS_HZ / 10).await;
+                  cursor::update_visual_pos();
+            }
+            draw_topbar();
+      }
+}
+
+/// Draws the topbar, ran every second by [`refresh_task`].
 pub fn draw_topbar() {
       // Print at the top left corner
       let (prev_row, prev_col) = CursorPos::row_col();
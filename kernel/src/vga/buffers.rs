@@ -23,7 +23,9 @@
     Contained within the vga module
 */
 
+use alloc::alloc::Layout;
 use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
 
 use libutil::UnsafeFlag;
 
@@ -108,24 +110,33 @@ pub fn clear() {
 }
 
 /// Swaps between the two buffers if the current one isn't currently being used.
-#[allow(clippy::redundant_pattern_matching)]
-#[rustfmt::skip]
+///
+/// The alt buffer is allocated on the heap the first time this is called.
 pub fn swap() {
       /// Where the unused buffer is stored.
-      static mut ALT: RawBuffer = YoinkedBuffer::empty_buffer();
-
-      /// Have to use a static since we don't want to store a 4kb buffer on the
-      /// stack. This is also why we can't just use ptr::swap
-      static mut TMP: RawBuffer = YoinkedBuffer::empty_buffer();
-
-      if let Some(_) = YoinkedBuffer::try_yoink() {
-            // Safety: We can safely write to BUFFER as it'll stay yoinked
-            // until dropped, all of the statics are well aligned & valid
-            // and since they're statics, they shouldn't overlap in any way
-            unsafe {
-                  ptr::copy_nonoverlapping(&raw const ALT, &raw mut TMP, 1);
-                  ptr::copy_nonoverlapping(BUFFER, &raw mut ALT, 1);
-                  ptr::copy_nonoverlapping(&raw const TMP, BUFFER, 1);
+      static ALT: AtomicPtr<RawBuffer> = AtomicPtr::new(ptr::null_mut());
+
+      let Some(_buf) = YoinkedBuffer::try_yoink() else {
+            return;
+      };
+
+      let mut alt = ALT.load(Ordering::Relaxed);
+      if alt.is_null() {
+            // Allocate the buffer manually, so that failing to doesn't panic
+            // Safety: RawBuffer isn't zero sized
+            alt = unsafe { alloc::alloc::alloc(Layout::new::<RawBuffer>()) }
+                  .cast();
+            if alt.is_null() {
+                  return;
             }
+
+            // Safety: The buffer was just allocated with room for every char
+            unsafe { (*alt).fill([VGAChar::SPACE; BUFFER_WIDTH as usize]) };
+            ALT.store(alt, Ordering::Relaxed);
       }
+
+      // Safety: We can safely write to BUFFER as it'll stay yoinked until
+      // dropped, and ALT is on the heap so it can't overlap with it. Swapping
+      // row by row avoids storing a 4kb buffer on the stack
+      unsafe { (*BUFFER).swap_with_slice(&mut *alt) }
 }
@@ -77,6 +77,9 @@ pub const INODE_START: u64 = 1;
 // Actually the last inode block, yet is unreachable due to block ptrs always being > 0
 pub const BLOCK_START: u64 = INODE_START + (INODES / 4) as u64;
 
+/// The index of the inode seeder copies user programs into.
+pub const PROGRAM_INODE: usize = 0;
+
 /// The numbers of bytes in a block;
 pub const BLOCK_SIZE: usize = 512;
 
@@ -150,6 +153,12 @@ impl INode {
     /// The size of the `_reserved` field.
     const RESERVED_BYTES: usize = 49;
 
+    /// The number of block pointers stored in an inode.
+    pub const MAX_BLOCKS: usize = DualBlockPtr::INODE_PTRS * 2;
+
+    /// The largest file size in bytes an inode can store.
+    pub const MAX_SIZE: usize = Self::MAX_BLOCKS * BLOCK_SIZE;
+
     // Returns an empty inode.
     pub const fn zeroed() -> Self {
         INode {
@@ -188,6 +197,35 @@ impl INode {
     pub fn parent(&self) -> InodePtr {
         self.parent
     }
+
+    /// Returns the size of the file in bytes.
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Sets the size of the file to `size` bytes.
+    pub fn set_size(&mut self, size: u16) {
+        self.size = size
+    }
+
+    /// Returns the `idx`th block pointer used by the file.
+    ///
+    /// Panics if `idx` isn't less than [`INode::MAX_BLOCKS`].
+    pub fn block(&self, idx: usize) -> BlockPtr {
+        let [fst, snd] = self.blocks[idx / 2].decode();
+        if idx.is_multiple_of(2) { fst } else { snd }
+    }
+
+    /// Sets the `idx`th block pointer used by the file to `ptr`.
+    ///
+    /// Panics if `idx` isn't less than [`INode::MAX_BLOCKS`].
+    pub fn set_block(&mut self, idx: usize, ptr: &BlockPtr) {
+        let [fst, snd] = self.blocks[idx / 2].decode();
+        self.blocks[idx / 2] = match idx.is_multiple_of(2) {
+            true => DualBlockPtr::encode([ptr, &snd]),
+            false => DualBlockPtr::encode([&fst, ptr]),
+        }
+    }
 }
 
 // Safety: Inode is packed, never containing any uninit bytes nor interior mutability.
@@ -317,6 +355,14 @@ impl InodePtr {
 impl InodeLookup {
     /// The number of bytes available in a name.
     const NAME_LEN: usize = 46;
+
+    /// Returns an empty lookup, which doesn't point to any inode.
+    pub const fn zeroed() -> Self {
+        InodeLookup {
+            name: [0; Self::NAME_LEN],
+            inode: InodePtr::null(),
+        }
+    }
 }
 
 impl BlockPtr {
@@ -425,4 +471,20 @@ mod tests {
             }
         }
     }
+
+    /// Tests that setting an inode's block pointers doesn't affect it's neighbours.
+    #[test]
+    fn inode_block_ptrs() {
+        let mut nod = INode::zeroed();
+        for idx in 0..INode::MAX_BLOCKS {
+            nod.set_block(idx, &BlockPtr::new(idx as u16 + 100));
+        }
+        for idx in 0..INode::MAX_BLOCKS {
+            assert_eq!(nod.block(idx), BlockPtr::new(idx as u16 + 100));
+        }
+        nod.set_block(3, &BlockPtr::null());
+        assert!(nod.block(3).is_null());
+        assert_eq!(nod.block(2), BlockPtr::new(102));
+        assert_eq!(nod.block(4), BlockPtr::new(104));
+    }
 }
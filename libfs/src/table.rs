@@ -67,7 +67,16 @@ pub fn alloc_bmp(block: &BlockPtr, bmp: &BlockBitmap) -> Result<(), AllocBmpErro
     }
 }
 
-/// The error returned from [`alloc_bmp`].
+/// Marks the block `block` in the [`BlockBitmap`] as available again.
+pub fn free_bmp(block: &BlockPtr, bmp: &BlockBitmap) -> Result<(), AllocBmpError> {
+    let ptr = block.get().ok_or(AllocBmpError::NullPtr)? as usize;
+    let bit = 1u128 << (ptr % U128_BITS);
+    bmp[ptr / U128_BITS]
+        .map(|i| *i &= !bit)
+        .ok_or(AllocBmpError::ExmapInUse(block.clone()))
+}
+
+/// The error returned from [`alloc_bmp`] and [`free_bmp`].
 #[derive(Error, Debug, PartialEq)]
 pub enum AllocBmpError {
     #[error("attempted allocating a null block ptr")]
@@ -110,7 +119,7 @@ pub fn alloc_inode<E>(
         {
             // we found a nod!
             let ptr = InodePtr::new(idx as u16 + 1); // add one so inode 0 isn't seen as a null pointer
-            write_inode_block(&ptr, tbl, write)?;
+            update_inode(&ptr, tbl, write)?;
             return Ok(InodePtr::new(idx as u16));
         }
     }
@@ -128,24 +137,35 @@ pub enum AllocInodeError<E> {
 }
 
 /// Writes the non-null inode pointer `ptr`, as well as the other inodes in it's block to the drive.
-fn write_inode_block<E>(
+pub fn update_inode<E>(
     ptr: &InodePtr,
     tbl: &InodeTable,
     write: Write<E>,
 ) -> Result<(), UpdateInodeError<E>> {
-    let ptr = ptr.get_table_idx().ok_or(UpdateInodeError::NullPtr)? as u64;
-    let block = (ptr & !0b11) + INODE_START; // round down to start of block
+    let (block, buf) = inode_block(ptr, tbl)?;
+    write(block, buf.as_bytes())?;
+    Ok(())
+}
+
+/// Returns the block the non-null inode pointer `ptr` is stored in, along with copies of every inode in it,
+/// for callers which can't pass a [`Write`] to [`update_inode`].
+pub fn inode_block<E>(
+    ptr: &InodePtr,
+    tbl: &InodeTable,
+) -> Result<(u64, [INode; 4]), UpdateInodeError<E>> {
+    let ptr = ptr.get_table_idx().ok_or(UpdateInodeError::NullPtr)? as usize;
+    let start = ptr & !0b11; // round down to start of block
+    let block = (start / 4) as u64 + INODE_START;
 
     // Get nods in the block
-    let (mut buf, ptr) = ([const { INode::zeroed() }; 4], ptr as usize);
-    for (idx, exmap) in tbl[ptr..ptr + 4].iter().enumerate() {
+    let mut buf = [const { INode::zeroed() }; 4];
+    for (idx, exmap) in tbl[start..start + 4].iter().enumerate() {
         if exmap.map(|n| buf[idx] = n.clone()).is_none() {
             return Err(UpdateInodeError::TblExmapFailure);
         }
     }
 
-    write(block, buf.as_bytes())?;
-    Ok(())
+    Ok((block, buf))
 }
 
 #[derive(Error, Debug)]
@@ -191,6 +211,18 @@ mod tests {
         assert_eq!(alloc_bmp(&BlockPtr::new(2), &BLOCK_BMP), Ok(()));
     }
 
+    /// Tests that blocks freed with [`free_bmp`] can be allocated again.
+    #[test]
+    #[allow(unused)]
+    #[rustfmt::skip]
+    fn bmp_free_reallocates() {
+        table_statics!();
+        let blk = alloc_next_bmp(&BLOCK_BMP);
+        assert_eq!(free_bmp(&blk, &BLOCK_BMP), Ok(()));
+        assert_eq!(alloc_next_bmp(&BLOCK_BMP), blk);
+        assert_eq!(free_bmp(&BlockPtr(None), &BLOCK_BMP), Err(AllocBmpError::NullPtr));
+    }
+
     /// Tests that [`alloc_inode`] works ok.
     #[test]
     #[allow(unused)]
@@ -208,6 +240,22 @@ mod tests {
         INODE_TBL[0].map(|n| assert_eq!(*n, NOD));
     }
 
+    /// Tests that [`update_inode`] writes the block containing the inode.
+    #[test]
+    #[allow(unused)]
+    fn update_inode_writes_right_block() {
+        table_statics!();
+        static NOD: INode = INode::new(FileMode::empty(), 42, InodePtr::null());
+        fn write(ptr: u64, buf: &[u8]) -> Result<(), ()> {
+            assert_eq!(ptr, INODE_START + 2); // inode 9 is in the third block
+            assert_eq!(&buf[size_of::<INode>()..size_of::<INode>() * 2], NOD.as_bytes());
+            Ok(())
+        }
+
+        INODE_TBL[9].map(|n| *n = NOD.clone()).unwrap();
+        update_inode(&InodePtr::new(10), &INODE_TBL, write).unwrap();
+    }
+
     /// Tests that [`alloc_next_bmp`] works correctly.
     #[test]
     #[allow(unused)]
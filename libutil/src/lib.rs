@@ -26,7 +26,13 @@
 #![feature(sync_unsafe_cell)]
 
 pub use send::{AsBytes, LoadRegisterError, TableDescriptor};
-pub use sync::{ExclusiveMap, InitError, InitLater, UnsafeFlag};
+pub use slab::{CacheStats, PageSource, SlabCache};
+pub use sync::{
+    ExclusiveMap, InitError, InitLater, IrqSafeMutex, RwLock, SpinMutex, UnsafeFlag,
+};
+pub use syscall::{SysError, Syscall};
 
 pub mod sync;
 pub mod send;
+pub mod slab;
+pub mod syscall;
@@ -0,0 +1,355 @@
+/* ---------------------------------------------------------------------------
+    libutil - Sunflower kernel utility library, sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    libutil/src/slab.rs
+
+    A slab allocator for caches of fixed size objects
+*/
+
+use core::fmt::Display;
+use core::ptr::{self, NonNull};
+
+/// The size and alignment of each slab, in bytes.
+pub const SLAB_SIZE: usize = 4096;
+
+/// Hands out the pages which slabs are carved from.
+pub trait PageSource {
+    /// Returns a new [`SLAB_SIZE`] aligned page, or `None` if there aren't
+    /// any left.
+    fn alloc_page(&mut self) -> Option<NonNull<u8>>;
+
+    /// Takes back `page`.
+    ///
+    /// # Safety
+    /// `page` must have been returned by [`PageSource::alloc_page`] and not
+    /// be used after this.
+    unsafe fn free_page(&mut self, page: NonNull<u8>);
+}
+
+/// Stored at the start of every slab.
+#[repr(C)]
+struct SlabHeader {
+    /// The next slab in the cache, or null if this is the last.
+    next: *mut SlabHeader,
+    /// The first free object in the slab, or null if the slab is full.
+    free: *mut FreeObject,
+    /// The number of objects in the slab which are in use.
+    in_use: usize,
+}
+
+/// Stored inside of every free object.
+struct FreeObject {
+    /// The next free object in the slab, or null if this is the last.
+    next: *mut FreeObject,
+}
+
+/// A cache of objects of type `T`, split into [`SLAB_SIZE`] slabs handed out by
+/// `P`.
+pub struct SlabCache<T, P: PageSource> {
+    ctor: fn() -> T,
+    source: P,
+    /// The first slab in the cache, or null if it doesn't have any.
+    slabs: *mut SlabHeader,
+    stats: CacheStats,
+}
+
+/// Statistics about a [`SlabCache`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStats {
+    /// The name of the cache.
+    pub name: &'static str,
+    /// The size of each object, including padding.
+    pub size: usize,
+    /// The number of objects which are currently handed out.
+    pub in_use: usize,
+    /// The number of objects which are ready to be handed out.
+    pub free: usize,
+    /// The number of slabs owned by the cache.
+    pub slabs: usize,
+}
+
+// Safety: The cache owns all of the memory it points to,
+// and can only be accessed through an `&mut` reference
+unsafe impl<T: Send, P: PageSource + Send> Send for SlabCache<T, P> {}
+
+// Safety: See above, the cache has no methods which can be called through a `&`
+// reference
+unsafe impl<T: Send, P: PageSource + Send> Sync for SlabCache<T, P> {}
+
+impl<T, P: PageSource> SlabCache<T, P> {
+    /// The alignment of each object.
+    const ALIGN: usize = max(align_of::<T>(), align_of::<FreeObject>());
+
+    /// The distance between each object in a slab.
+    const STRIDE: usize =
+        max(size_of::<T>(), size_of::<FreeObject>()).next_multiple_of(Self::ALIGN);
+
+    /// Where the first object is stored in each slab.
+    const FIRST_OBJECT: usize = size_of::<SlabHeader>().next_multiple_of(Self::ALIGN);
+
+    /// The number of objects which fit in a slab.
+    pub const OBJECTS_PER_SLAB: usize = SLAB_SIZE.saturating_sub(Self::FIRST_OBJECT) / Self::STRIDE;
+
+    /// Creates a new cache named `name`, using `ctor` to create each
+    /// allocated object.
+    pub const fn new(name: &'static str, ctor: fn() -> T, source: P) -> Self {
+        SlabCache {
+            ctor,
+            source,
+            slabs: ptr::null_mut(),
+            stats: CacheStats {
+                name,
+                size: Self::STRIDE,
+                in_use: 0,
+                free: 0,
+                slabs: 0,
+            },
+        }
+    }
+
+    /// Returns a new object created by the cache's constructor.
+    ///
+    /// Fails if a new slab is needed and the page source has run out of
+    /// pages.
+    pub fn alloc(&mut self) -> Option<NonNull<T>> {
+        let mut slab = self.slabs;
+
+        // Safety: Every slab in the list is a valid slab owned by the cache
+        unsafe {
+            while !slab.is_null() && (*slab).free.is_null() {
+                slab = (*slab).next;
+            }
+
+            if slab.is_null() {
+                slab = self.grow()?;
+            }
+
+            let obj = (*slab).free;
+            (*slab).free = (*obj).next;
+            (*slab).in_use += 1;
+
+            let obj = obj.cast::<T>();
+            obj.write((self.ctor)());
+            self.stats.in_use += 1;
+            self.stats.free -= 1;
+            NonNull::new(obj)
+        }
+    }
+
+    /// Drops `obj` and returns it to the cache.
+    ///
+    /// # Safety
+    /// `obj` must have been allocated by this cache and not be used after
+    /// this.
+    pub unsafe fn free(&mut self, obj: NonNull<T>) {
+        let obj = obj.as_ptr();
+        let slab = (obj as usize & !(SLAB_SIZE - 1)) as *mut SlabHeader;
+
+        // Safety: The caller ensures that obj is an object inside of one of
+        // our slabs
+        unsafe {
+            obj.drop_in_place();
+            let obj = obj.cast::<FreeObject>();
+            (*obj).next = (*slab).free;
+            (*slab).free = obj;
+            (*slab).in_use -= 1;
+        }
+
+        self.stats.in_use -= 1;
+        self.stats.free += 1;
+    }
+
+    /// Returns every slab without any objects in use to the page source,
+    /// returning how many were returned.
+    pub fn shrink(&mut self) -> usize {
+        let mut freed = 0;
+        let mut link = &raw mut self.slabs;
+
+        // Safety: Every slab in the list is a valid slab owned by the cache
+        unsafe {
+            while !(*link).is_null() {
+                let slab = *link;
+                if (*slab).in_use != 0 {
+                    link = &raw mut (*slab).next;
+                    continue;
+                }
+
+                *link = (*slab).next;
+                self.source.free_page(NonNull::new_unchecked(slab.cast()));
+                self.stats.free -= Self::OBJECTS_PER_SLAB;
+                self.stats.slabs -= 1;
+                freed += 1;
+            }
+        }
+
+        freed
+    }
+
+    /// Returns statistics about the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    /// Adds a new slab to the front of the cache, returning it.
+    fn grow(&mut self) -> Option<*mut SlabHeader> {
+        if Self::OBJECTS_PER_SLAB == 0 {
+            return None;
+        }
+
+        let page = self.source.alloc_page()?.as_ptr();
+        let slab = page.cast::<SlabHeader>();
+
+        // Safety: The page source ensures that the page is SLAB_SIZE bytes,
+        // and every object fits in it as OBJECTS_PER_SLAB is
+        // calculated from the space left over
+        unsafe {
+            let mut free = ptr::null_mut();
+            for idx in (0..Self::OBJECTS_PER_SLAB).rev() {
+                let obj = page
+                    .add(Self::FIRST_OBJECT + idx * Self::STRIDE)
+                    .cast::<FreeObject>();
+                obj.write(FreeObject { next: free });
+                free = obj;
+            }
+
+            slab.write(SlabHeader {
+                next: self.slabs,
+                free,
+                in_use: 0,
+            });
+        }
+
+        self.slabs = slab;
+        self.stats.free += Self::OBJECTS_PER_SLAB;
+        self.stats.slabs += 1;
+        Some(slab)
+    }
+}
+
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{} {}/{}",
+            self.name,
+            self.in_use,
+            self.in_use + self.free
+        )
+    }
+}
+
+/// Const available `usize::max`.
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::alloc::{self, Layout};
+    use std::cell::Cell;
+
+    use super::*;
+
+    /// Hands out pages from the host's allocator.
+    #[derive(Default)]
+    struct HostPages {
+        allocated: usize,
+        limit: Option<usize>,
+    }
+
+    const LAYOUT: Layout = match Layout::from_size_align(SLAB_SIZE, SLAB_SIZE) {
+        Ok(layout) => layout,
+        Err(_) => panic!("bad slab layout"),
+    };
+
+    impl PageSource for HostPages {
+        fn alloc_page(&mut self) -> Option<NonNull<u8>> {
+            if self.limit.is_some_and(|l| self.allocated == l) {
+                return None;
+            }
+            self.allocated += 1;
+            NonNull::new(unsafe { alloc::alloc(LAYOUT) })
+        }
+
+        unsafe fn free_page(&mut self, page: NonNull<u8>) {
+            self.allocated -= 1;
+            unsafe { alloc::dealloc(page.as_ptr(), LAYOUT) }
+        }
+    }
+
+    /// Tests that objects are constructed, unique and reused after being
+    /// freed.
+    #[test]
+    fn slab_allocs_and_reuses() {
+        let mut cache = SlabCache::new("test", || 0x42_u64, HostPages::default());
+        let fst = cache.alloc().unwrap();
+        let snd = cache.alloc().unwrap();
+
+        assert_ne!(fst, snd);
+        assert_eq!(unsafe { *fst.as_ptr() }, 0x42);
+        assert_eq!(cache.stats().in_use, 2);
+
+        unsafe { cache.free(fst) };
+        assert_eq!(cache.alloc().unwrap(), fst);
+    }
+
+    /// Tests that caches grow new slabs when full, and shrink empty ones.
+    #[test]
+    fn slab_grows_and_shrinks() {
+        type Cache = SlabCache<[u8; 128], HostPages>;
+        let per_slab = Cache::OBJECTS_PER_SLAB;
+        let mut cache = Cache::new("big", || [0; 128], HostPages::default());
+
+        let objs: Vec<_> = (0..per_slab + 1).map(|_| cache.alloc().unwrap()).collect();
+        let stats = cache.stats();
+        assert_eq!(stats.slabs, 2);
+        assert_eq!(stats.in_use + stats.free, per_slab * 2);
+        assert_eq!(stats.size, 128);
+
+        objs.into_iter().for_each(|obj| unsafe { cache.free(obj) });
+        assert_eq!(cache.shrink(), 2);
+        assert_eq!(cache.stats().free, 0);
+        assert_eq!(cache.source.allocated, 0);
+    }
+
+    /// Tests that freed objects are dropped, and failing page sources are
+    /// handled.
+    #[test]
+    fn slab_drops_and_fails() {
+        thread_local!(static DROPS: Cell<u32> = const { Cell::new(0) });
+        struct Counter;
+        impl Drop for Counter {
+            fn drop(&mut self) {
+                DROPS.with(|d| d.set(d.get() + 1))
+            }
+        }
+
+        let source = HostPages {
+            allocated: 0,
+            limit: Some(0),
+        };
+        let mut cache = SlabCache::new("drops", || Counter, source);
+        assert!(cache.alloc().is_none());
+
+        cache.source.limit = None;
+        let obj = cache.alloc().unwrap();
+        unsafe { cache.free(obj) };
+        assert_eq!(DROPS.with(|d| d.get()), 1);
+    }
+}
@@ -24,12 +24,14 @@
 
 use core::{
     any::type_name,
-    cell::SyncUnsafeCell,
+    cell::{SyncUnsafeCell, UnsafeCell},
     error::Error,
     fmt::{Debug, Display},
+    hint,
     marker::PhantomData,
-    mem::MaybeUninit,
-    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    mem::{ManuallyDrop, MaybeUninit},
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering},
 };
 
 /// A wrapper type to construct uninitialised instances of `T`, which can be safely given an initialised value later.
@@ -196,6 +198,286 @@ impl UnsafeFlag {
     }
 }
 
+/// A mutually exclusive piece of data which spins until it can be locked.
+///
+/// Unlike `ExclusiveMap`, locking never fails, so it shouldn't be used for data also accessed
+/// inside of interrupt handlers, as that could deadlock. Use `IrqSafeMutex` for that instead.
+pub struct SpinMutex<T> {
+    cell: UnsafeCell<T>,
+    locked: AtomicBool,
+}
+
+// Safety: Access to the contained value is serialised by `locked`
+unsafe impl<T: Send> Sync for SpinMutex<T> {}
+unsafe impl<T: Send> Send for SpinMutex<T> {}
+
+impl<T> SpinMutex<T> {
+    /// Creates a new unlocked mutex containing `val`.
+    pub const fn new(val: T) -> Self {
+        SpinMutex {
+            cell: UnsafeCell::new(val),
+            locked: AtomicBool::new(false),
+        }
+    }
+
+    /// Spins until the mutex is locked, then returns a guard which unlocks it when dropped.
+    pub fn lock(&self) -> SpinMutexGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+
+            // Wait without hammering the cache line with cmpxchgs
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop()
+            }
+        }
+    }
+
+    /// Locks the mutex if it isn't already locked.
+    pub fn try_lock(&self) -> Option<SpinMutexGuard<'_, T>> {
+        self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SpinMutexGuard { mutex: self })
+    }
+
+    /// Returns whether the mutex is currently locked.
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// Gives access to the value in a `SpinMutex`, unlocking it when dropped.
+pub struct SpinMutexGuard<'a, T> {
+    mutex: &'a SpinMutex<T>,
+}
+
+impl<T> Deref for SpinMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: The guard existing means we hold the lock
+        unsafe { &*self.mutex.cell.get() }
+    }
+}
+
+impl<T> DerefMut for SpinMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The guard existing means we hold the lock
+        unsafe { &mut *self.mutex.cell.get() }
+    }
+}
+
+impl<T> Drop for SpinMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A piece of data which can either be read by any amount of readers, or written to by one
+/// writer, spinning until it's available.
+pub struct RwLock<T> {
+    cell: UnsafeCell<T>,
+    /// The amount of active readers, or `WRITER` if it's being written to.
+    state: AtomicUsize,
+}
+
+/// The `RwLock` state used while it's being written to.
+const WRITER: usize = usize::MAX;
+
+// Safety: Readers only get shared references and writers are exclusive
+unsafe impl<T: Send + Sync> Sync for RwLock<T> {}
+unsafe impl<T: Send> Send for RwLock<T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new unlocked lock containing `val`.
+    pub const fn new(val: T) -> Self {
+        RwLock {
+            cell: UnsafeCell::new(val),
+            state: AtomicUsize::new(0),
+        }
+    }
+
+    /// Spins until there's no writer, then returns a guard allowing shared access.
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            hint::spin_loop()
+        }
+    }
+
+    /// Gives shared access if there's no writer.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        let readers = self.state.load(Ordering::Relaxed);
+        // WRITER - 1 readers is absurd, but would turn into a writer if we allowed another
+        if readers >= WRITER - 1 {
+            return None;
+        }
+
+        self.state
+            .compare_exchange_weak(readers, readers + 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockReadGuard { lock: self })
+    }
+
+    /// Spins until there's no readers or writer, then returns a guard allowing exclusive access.
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        loop {
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+            hint::spin_loop()
+        }
+    }
+
+    /// Gives exclusive access if there's no readers or writer.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| RwLockWriteGuard { lock: self })
+    }
+}
+
+/// Gives shared access to the value in a `RwLock`, releasing it when dropped.
+pub struct RwLockReadGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: No writers can exist while we're reading
+        unsafe { &*self.lock.cell.get() }
+    }
+}
+
+impl<T> Drop for RwLockReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Gives exclusive access to the value in a `RwLock`, releasing it when dropped.
+pub struct RwLockWriteGuard<'a, T> {
+    lock: &'a RwLock<T>,
+}
+
+impl<T> Deref for RwLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // Safety: The guard existing means we're the only writer
+        unsafe { &*self.lock.cell.get() }
+    }
+}
+
+impl<T> DerefMut for RwLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: The guard existing means we're the only writer
+        unsafe { &mut *self.lock.cell.get() }
+    }
+}
+
+impl<T> Drop for RwLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.state.store(0, Ordering::Release);
+    }
+}
+
+/// A `SpinMutex` which clears external interrupts while it's locked.
+///
+/// Allows data to be shared between interrupt handlers and normal code, as the handlers can't
+/// run while normal code holds the lock.
+pub struct IrqSafeMutex<T> {
+    mutex: SpinMutex<T>,
+}
+
+impl<T> IrqSafeMutex<T> {
+    /// Creates a new unlocked mutex containing `val`.
+    pub const fn new(val: T) -> Self {
+        IrqSafeMutex {
+            mutex: SpinMutex::new(val),
+        }
+    }
+
+    /// Clears interrupts then spins until the mutex is locked, setting them again when the
+    /// returned guard is dropped if they were set before.
+    pub fn lock(&self) -> IrqSafeMutexGuard<'_, T> {
+        let irqs_were_set = irq::save_and_clear();
+        IrqSafeMutexGuard {
+            guard: ManuallyDrop::new(self.mutex.lock()),
+            irqs_were_set,
+        }
+    }
+}
+
+/// Gives access to the value in an `IrqSafeMutex`, unlocking it then restoring interrupts when
+/// dropped.
+pub struct IrqSafeMutexGuard<'a, T> {
+    guard: ManuallyDrop<SpinMutexGuard<'a, T>>,
+    irqs_were_set: bool,
+}
+
+impl<T> Deref for IrqSafeMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for IrqSafeMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for IrqSafeMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Unlock before setting interrupts, so a handler can't spin on the lock forever.
+        // Safety: The guard is never used again
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+        if self.irqs_were_set {
+            irq::set();
+        }
+    }
+}
+
+/// Saving and restoring the interrupt flag.
+///
+/// Hosted targets (tests & the seeder) can't touch it, so do nothing.
+mod irq {
+    /// Returns whether interrupts were set, then clears them.
+    #[cfg(target_os = "none")]
+    pub fn save_and_clear() -> bool {
+        const IF: u64 = 1 << 9;
+        let rflags: u64;
+        // Safety: Just reading RFLAGS and clearing interrupts
+        unsafe { core::arch::asm!("pushf", "pop {}", "cli", out(reg) rflags) };
+        rflags & IF != 0
+    }
+
+    /// Sets interrupts.
+    #[cfg(target_os = "none")]
+    pub fn set() {
+        // Safety: Only called to restore the previous state
+        unsafe { core::arch::asm!("sti") }
+    }
+
+    #[cfg(not(target_os = "none"))]
+    pub fn save_and_clear() -> bool {
+        false
+    }
+
+    #[cfg(not(target_os = "none"))]
+    pub fn set() {}
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,4 +507,45 @@ mod tests {
         exmap.map(|i| assert_eq!(*i, 50)).unwrap();
         exmap.map(|_| assert!(exmap.map(|_| {}).is_none())).unwrap()
     }
+
+    /// Tests that `SpinMutex` can only be locked once at a time, and unlocks when the guard is
+    /// dropped.
+    #[test]
+    fn spinmutex_locks_and_unlocks() {
+        let mutex = SpinMutex::new(42);
+        let mut guard = mutex.lock();
+        *guard += 8;
+        assert!(mutex.try_lock().is_none());
+        drop(guard);
+        assert_eq!(*mutex.try_lock().unwrap(), 50);
+        assert!(!mutex.is_locked())
+    }
+
+    /// Tests that `RwLock` allows many readers or one writer, but not both.
+    #[test]
+    fn rwlock_readers_xor_writer() {
+        let lock = RwLock::new(42);
+        let (a, b) = (lock.read(), lock.read());
+        assert_eq!(*a + *b, 84);
+        assert!(lock.try_write().is_none());
+        drop((a, b));
+
+        let mut writer = lock.write();
+        *writer = 43;
+        assert!(lock.try_read().is_none());
+        drop(writer);
+        assert_eq!(*lock.read(), 43)
+    }
+
+    /// Tests that `SpinMutex` serialises access between threads.
+    #[test]
+    fn spinmutex_across_threads() {
+        let mutex = SpinMutex::new(0_u32);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| (0..1000).for_each(|_| *mutex.lock() += 1));
+            }
+        });
+        assert_eq!(*mutex.lock(), 4000)
+    }
 }
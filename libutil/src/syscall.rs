@@ -0,0 +1,218 @@
+/* ---------------------------------------------------------------------------
+    libutil - Sunflower kernel utility library, sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    libutil/src/syscall.rs
+
+    The stable system call ABI shared by the kernel and user programs.
+
+    The syscall number is passed in `rax` and up to five arguments in `rdi`,
+    `rsi`, `rdx`, `r10` and `r8`. The result is returned in `rax`, which is
+    negative error code if the syscall failed. `rcx` and `r11` are clobbered.
+*/
+
+use core::fmt::Display;
+
+/// Every system call, by its number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u64)]
+pub enum Syscall {
+    /// `exit(code)`, stops the program with exit code `code`.
+    Exit = 0,
+    /// `print(ptr, len)`, writes `len` bytes of UTF-8 at `ptr` to the console,
+    /// returning `len`.
+    Print = 1,
+    /// `read_key()`, waits for a key to be typed, returning its ASCII value.
+    ReadKey = 2,
+    /// `sleep(ticks)`, sleeps for `ticks` ticks, up to a day's worth.
+    Sleep = 3,
+    /// `uptime()`, returns the number of ticks the kernel has been running
+    /// for.
+    Uptime = 4,
+    /// `open(inode)`, opens the file stored in inode `inode`, returning a file
+    /// descriptor.
+    Open = 5,
+    /// `read(fd, ptr, len)`, reads up to `len` bytes from `fd` into `ptr`,
+    /// returning how many were read.
+    Read = 6,
+    /// `write(fd, ptr, len)`, writes `len` bytes from `ptr` to `fd`,
+    /// returning how many were written.
+    Write = 7,
+    /// `close(fd)`, closes `fd`.
+    Close = 8,
+    /// `time()`, returns the wall-clock time in seconds since the Unix epoch,
+    /// or 0 if it's before the epoch.
+    Time = 9,
+}
+
+/// An error returned by a system call, as the negative of its code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum SysError {
+    /// There isn't a syscall with that number.
+    BadSyscall = 1,
+    /// A pointer passed to the syscall isn't accessible by the program.
+    BadAddress = 2,
+    /// The file descriptor isn't open.
+    BadFd = 3,
+    /// There isn't a file in that inode.
+    NoFile = 4,
+    /// The inode is a directory.
+    IsDirectory = 5,
+    /// The program already has the max number of files open.
+    TooManyFiles = 6,
+    /// The write would make the file larger than an inode can store.
+    FileTooBig = 7,
+    /// There aren't any free blocks left on the drive.
+    NoSpace = 8,
+    /// Something the syscall needs is being used somewhere else, try again.
+    Busy = 9,
+    /// The floppy filesystem hasn't been initialised.
+    NoFilesystem = 10,
+    /// The floppy controller failed in some other way.
+    Io = 11,
+    /// The wall-clock time hasn't been read from the RTC.
+    NoClock = 12,
+
+    // Disk errors, from the floppy driver
+    BadBufLen = 32,
+    ControllerUninit = 33,
+    SendCommandTimeout = 34,
+    IoTimeout = 35,
+    FifoTimeout = 36,
+    BadSectOrHead = 37,
+    NotWritable = 38,
+    EndOfDrive = 39,
+    BadSt0Bits = 40,
+    DriveNotReady = 41,
+    NoDataFound = 42,
+    ControllerTimeout = 43,
+    EndOfCylinder = 44,
+    CRCError = 45,
+    BadCylinder = 46,
+    DifferingCylinder = 47,
+    NoAddressMark = 48,
+}
+
+impl Syscall {
+    /// Returns the syscall with number `num`, if there is one.
+    pub const fn from_num(num: u64) -> Option<Self> {
+        Some(match num {
+            0 => Syscall::Exit,
+            1 => Syscall::Print,
+            2 => Syscall::ReadKey,
+            3 => Syscall::Sleep,
+            4 => Syscall::Uptime,
+            5 => Syscall::Open,
+            6 => Syscall::Read,
+            7 => Syscall::Write,
+            8 => Syscall::Close,
+            9 => Syscall::Time,
+            _ => return None,
+        })
+    }
+}
+
+impl SysError {
+    /// Every error, in order of their codes.
+    pub const ALL: [SysError; 29] = [
+        SysError::BadSyscall,
+        SysError::BadAddress,
+        SysError::BadFd,
+        SysError::NoFile,
+        SysError::IsDirectory,
+        SysError::TooManyFiles,
+        SysError::FileTooBig,
+        SysError::NoSpace,
+        SysError::Busy,
+        SysError::NoFilesystem,
+        SysError::Io,
+        SysError::NoClock,
+        SysError::BadBufLen,
+        SysError::ControllerUninit,
+        SysError::SendCommandTimeout,
+        SysError::IoTimeout,
+        SysError::FifoTimeout,
+        SysError::BadSectOrHead,
+        SysError::NotWritable,
+        SysError::EndOfDrive,
+        SysError::BadSt0Bits,
+        SysError::DriveNotReady,
+        SysError::NoDataFound,
+        SysError::ControllerTimeout,
+        SysError::EndOfCylinder,
+        SysError::CRCError,
+        SysError::BadCylinder,
+        SysError::DifferingCylinder,
+        SysError::NoAddressMark,
+    ];
+
+    /// Returns the error's code.
+    pub const fn code(self) -> i64 {
+        self as i64
+    }
+
+    /// Returns the error with code `code`, if there is one.
+    pub fn from_code(code: i64) -> Option<Self> {
+        SysError::ALL.into_iter().find(|e| e.code() == code)
+    }
+
+    /// Converts the value returned in `rax` by a syscall into a result.
+    pub fn check(ret: i64) -> Result<u64, SysError> {
+        if ret >= 0 {
+            Ok(ret as u64)
+        } else {
+            Err(SysError::from_code(-ret).unwrap_or(SysError::Io))
+        }
+    }
+}
+
+impl Display for SysError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{self:?} (error {})", self.code())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Tests that every error's code maps back to itself, and that codes never
+    /// change.
+    #[test]
+    fn error_codes_are_stable() {
+        for err in SysError::ALL {
+            assert_eq!(SysError::from_code(err.code()), Some(err));
+            assert_eq!(SysError::check(-err.code()), Err(err));
+        }
+
+        assert_eq!(SysError::NoFile.code(), 4);
+        assert_eq!(SysError::NoAddressMark.code(), 48);
+        assert_eq!(SysError::check(42), Ok(42));
+        assert_eq!(SysError::from_code(0), None);
+    }
+
+    /// Tests that syscall numbers map back to themselves.
+    #[test]
+    fn syscall_numbers_are_stable() {
+        for num in 0..=9 {
+            assert_eq!(Syscall::from_num(num).unwrap() as u64, num);
+        }
+        assert_eq!(Syscall::from_num(10), None);
+    }
+}
@@ -0,0 +1,108 @@
+/* ---------------------------------------------------------------------------
+    hello - Sunflower's demo user program, sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    programs/hello/src/main.rs
+
+    A demo program which prints it's arguments, then waits for a key to be
+    pressed before exiting
+*/
+
+#![no_std]
+#![no_main]
+
+use core::{
+    arch::{asm, naked_asm},
+    ffi::{CStr, c_char},
+    panic::PanicInfo,
+};
+use libutil::{SysError, Syscall};
+
+/// The program's entry point, with `rsp` pointing to `argc`.
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+extern "C" fn _start() -> ! {
+    naked_asm!("mov rdi, rsp", "call main", "ud2")
+}
+
+/// Ran by `_start` with the stack it was entered with.
+#[unsafe(no_mangle)]
+extern "sysv64" fn main(stack: *const u64) -> ! {
+    // Safety: The kernel pushes argc followed by argc pointers to strings
+    let args = unsafe {
+        let argc = stack.read() as usize;
+        core::slice::from_raw_parts(stack.add(1).cast::<*const c_char>(), argc)
+    };
+
+    print("Hello from ring 3! Ran with arguments:\n");
+    for arg in args {
+        // Safety: Every argument is null terminated
+        let arg = unsafe { CStr::from_ptr(*arg) };
+        print("  ");
+        print(arg.to_str().unwrap_or("(not UTF-8)"));
+        print("\n");
+    }
+
+    print("Press any key to exit...");
+    let code = match read_key() {
+        Ok(_) => 0,
+        Err(e) => e.code() as u64,
+    };
+    exit(code)
+}
+
+/// Runs syscall `num` with arguments `args`.
+fn syscall(num: Syscall, args: [u64; 3]) -> Result<u64, SysError> {
+    let ret: i64;
+    // Safety: Syscalls only clobber rcx & r11
+    unsafe {
+        asm!(
+            "syscall",
+            inlateout("rax") num as u64 => ret,
+            in("rdi") args[0],
+            in("rsi") args[1],
+            in("rdx") args[2],
+            out("rcx") _,
+            out("r11") _,
+            options(nostack)
+        )
+    }
+    SysError::check(ret)
+}
+
+/// Prints `str` to the console.
+fn print(str: &str) {
+    _ = syscall(Syscall::Print, [str.as_ptr() as u64, str.len() as u64, 0]);
+}
+
+/// Waits for a key to be typed, returning it.
+fn read_key() -> Result<u8, SysError> {
+    syscall(Syscall::ReadKey, [0; 3]).map(|key| key as u8)
+}
+
+/// Stops the program with exit code `code`.
+fn exit(code: u64) -> ! {
+    _ = syscall(Syscall::Exit, [code, 0, 0]);
+    unreachable!("exit syscall returned")
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    print("hello panicked!\n");
+    exit(101)
+}
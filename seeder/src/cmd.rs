@@ -33,6 +33,9 @@ use thiserror::Error;
 /// The path of the built kernel image.
 pub const BUILT_KERNEL_IMG: &str = "kernel/target/x86_64-sunflower/release/bootimage-sunflower.bin";
 
+/// The path of the floppy drive image.
+pub const FLOPPY_IMG: &str = "floppy.img";
+
 /// The path of the copied kernel image.
 const COPIED_KERNEL_IMG: &str = "sunflower.bin";
 
@@ -84,15 +87,7 @@ pub fn run_command(cmd: &RunCommand, dir: &str, args: &ArgMatches) {
         }
     }
 
-    // Create floppy drive if it didn't already exist
-    if OpenOptions::new().read(true).open("floppy.img").is_err() {
-        // no floppy drive!
-        println!("Creating floppy drive...");
-        if let Err(e) = fs::write("floppy.img", [0u8; 1440 * 1024]) {
-            println!("error: failed created floppy.img, {e}");
-            process::exit(3)
-        }
-    }
+    create_floppy();
 
     // just need to copy over the bin and we're done!
     if *cmd == RunCommand::Build {
@@ -112,6 +107,18 @@ pub fn run_command(cmd: &RunCommand, dir: &str, args: &ArgMatches) {
     }
 }
 
+/// Creates the floppy drive if it doesn't already exist.
+pub fn create_floppy() {
+    if OpenOptions::new().read(true).open(FLOPPY_IMG).is_err() {
+        // no floppy drive!
+        println!("Creating floppy drive...");
+        if let Err(e) = fs::write(FLOPPY_IMG, [0u8; 1440 * 1024]) {
+            println!("error: failed created {FLOPPY_IMG}, {e}");
+            process::exit(3)
+        }
+    }
+}
+
 /// Attempts to run command `cmd`, returning false if any errors occurred.
 fn try_run(cmd: &str, dir: &str, args: &ArgMatches) -> Result<(), RunCargoError> {
     // Check for any features
@@ -42,6 +42,7 @@ const CORNFLOWER_BLUE: Color = Color::Rgb(RgbColor(120, 172, 255));
 const PURPLE_BLUE: Color = Color::Rgb(RgbColor(163, 158, 255));
 
 mod cmd;
+mod program;
 
 fn main() {
     let mut command = command!()
@@ -81,6 +82,13 @@ fn main() {
                 .about("Checks sunflower using clippy")
                 .args(args()),
         )
+        .subcommand(
+            Command::new("program, p")
+                .alias("program")
+                .alias("p")
+                .about("Builds a user program then copies it onto the floppy drive")
+                .arg(arg!(<dir> "The program's directory, such as programs/hello")),
+        )
         .subcommand(
             Command::new("dbg, d")
                 .alias("dbg")
@@ -99,6 +107,7 @@ fn main() {
                 "run, r" => run(cmd.1),
                 "did-i-break-anything, diba" => run_alldirs(&RunCommand::Test, cmd.1),
                 "clippy, c" => run_alldirs(&RunCommand::Clippy, cmd.1),
+                "program, p" => program::copy_program(cmd.1),
                 "dbg, d" => run(&Command::new("")
                     .args(args())
                     .get_matches_from(["", "-d", "-n"])),
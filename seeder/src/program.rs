@@ -0,0 +1,153 @@
+/* ---------------------------------------------------------------------------
+    seeder - Sunflower's build tool, sunflowerkernel.org
+    Copyright (C) 2026 janicria
+
+    This program is free software: you can redistribute it and/or modify
+    it under the terms of the GNU General Public License as published by
+    the Free Software Foundation, either version 3 of the License, or
+    (at your option) any later version.
+
+    This program is distributed in the hope that it will be useful,
+    but WITHOUT ANY WARRANTY; without even the implied warranty of
+    MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+    GNU General Public License for more details.
+
+    You should have received a copy of the GNU General Public License
+    along with this program.  If not, see <https://www.gnu.org/licenses/>.
+--------------------------------------------------------------------------- */
+
+/*!
+    seeder/src/program.rs
+
+    Builds user programs and copies them onto the floppy drive
+*/
+
+use crate::cmd::{self, FLOPPY_IMG};
+use clap::ArgMatches;
+use libfs::{
+    BLOCK_SIZE, BLOCK_START, BlockPtr, FileMode, INODES, INode, InodePtr, MAGIC, PROGRAM_INODE,
+    header::FilesystemHeader,
+    init::{self, ReadTblError},
+    table::{self, BlockBitmap, InodeTable, UpdateInodeError},
+};
+use libutil::ExclusiveMap;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    process::{self, Command, ExitStatusError},
+};
+use thiserror::Error;
+
+/// The directory programs are built into, relative to their own directory.
+const PROGRAM_TARGET_DIR: &str = "target/x86_64-unknown-none/release";
+
+libfs::table_statics!();
+
+/// Ran when the program command is specified.
+pub fn copy_program(args: &ArgMatches) {
+    let dir: &String = args.get_one("dir").expect("clap requires dir");
+    if let Err(e) = try_copy(dir) {
+        println!("error: failed copying program `{dir}`: {e}");
+        process::exit(7)
+    }
+}
+
+/// Builds the program in `dir` then copies it into [`PROGRAM_INODE`] on the floppy drive,
+/// reusing the blocks of any program already there.
+fn try_copy(dir: &str) -> Result<(), ProgramError> {
+    let path = fs::canonicalize(dir)?;
+    let name = path.file_name().and_then(|n| n.to_str()).ok_or(ProgramError::BadDir)?;
+
+    println!("Building program {name}...");
+    Command::new("cargo")
+        .args(["build", "--release"])
+        .current_dir(&path)
+        .status()?
+        .exit_ok()?;
+
+    let elf = fs::read(path.join(PROGRAM_TARGET_DIR).join(name))?;
+    if elf.len() > INode::MAX_SIZE {
+        return Err(ProgramError::TooBig(elf.len()));
+    }
+
+    // Load the filesystem
+    cmd::create_floppy();
+    let mut buf = [0; size_of::<FilesystemHeader>()];
+    read_block(0, &mut buf)?;
+    if FilesystemHeader::from_raw(buf).magic != MAGIC {
+        return Err(ProgramError::Unformatted);
+    }
+    init::read_table(&INODE_TBL, &BLOCK_BMP, read_block)?;
+
+    let mut nod = INODE_TBL[PROGRAM_INODE].map(|n| n.clone()).expect("seeder is single threaded");
+    if nod.is_available() {
+        nod = INode::new(FileMode::empty(), 0, InodePtr::null());
+    }
+
+    // Write the program over it's blocks
+    for (idx, chunk) in elf.chunks(BLOCK_SIZE).enumerate() {
+        let mut ptr = nod.block(idx);
+        if ptr.is_null() {
+            ptr = table::alloc_next_bmp(&BLOCK_BMP);
+            if ptr.is_null() {
+                return Err(ProgramError::NoSpace);
+            }
+            nod.set_block(idx, &ptr);
+        }
+
+        let mut block = [0; BLOCK_SIZE];
+        block[..chunk.len()].copy_from_slice(chunk);
+        write_block(BLOCK_START + ptr.get_nullable() as u64, &block)?;
+    }
+
+    nod.set_size(elf.len() as u16);
+    INODE_TBL[PROGRAM_INODE].map(|n| *n = nod).expect("seeder is single threaded");
+    table::update_inode(&InodePtr::new(PROGRAM_INODE as u16 + 1), &INODE_TBL, write_block)?;
+
+    println!(
+        "Copied {name} ({}b) into inode {PROGRAM_INODE} of {FLOPPY_IMG}, run it in sunflower using SysCmd 8",
+        elf.len()
+    );
+    Ok(())
+}
+
+/// Reads from the floppy drive starting at block `block` into `buf`.
+fn read_block(block: u64, buf: &mut [u8]) -> Result<(), io::Error> {
+    let mut file = File::open(FLOPPY_IMG)?;
+    file.seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+    file.read_exact(buf)
+}
+
+/// Writes `buf` to the floppy drive starting at block `block`.
+fn write_block(block: u64, buf: &[u8]) -> Result<(), io::Error> {
+    let mut file = OpenOptions::new().write(true).open(FLOPPY_IMG)?;
+    file.seek(SeekFrom::Start(block * BLOCK_SIZE as u64))?;
+    file.write_all(buf)
+}
+
+#[derive(Error, Debug)]
+enum ProgramError {
+    #[error("{0}")]
+    Io(#[from] io::Error),
+
+    #[error("cargo returned error: {0}")]
+    BadExitStatus(#[from] ExitStatusError),
+
+    #[error("the program's directory doesn't have a valid name")]
+    BadDir,
+
+    #[error("the program is {0} bytes, but files can only be {max} bytes", max = INode::MAX_SIZE)]
+    TooBig(usize),
+
+    #[error("{FLOPPY_IMG} isn't formatted yet, run sunflower once to format it")]
+    Unformatted,
+
+    #[error("failed reading the inode table, {0}")]
+    ReadTable(#[from] ReadTblError<io::Error>),
+
+    #[error("failed updating the program's inode, {0}")]
+    UpdateInode(#[from] UpdateInodeError<io::Error>),
+
+    #[error("ran out of free blocks on {FLOPPY_IMG}")]
+    NoSpace,
+}